@@ -0,0 +1,70 @@
+/* C interface to the kvstore database, exported by the kvstore_ffi library.
+ * Mirrors kvstore-ffi/src/lib.rs and can be regenerated with cbindgen:
+ *
+ *     cbindgen --config kvstore-ffi/cbindgen.toml --crate kvstore-ffi \
+ *         --output kvstore-ffi/include/kvstore.h
+ */
+
+#ifndef KVSTORE_H
+#define KVSTORE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* The call succeeded. */
+#define KV_OK 0
+
+/* The key does not exist in the database. */
+#define KV_NOT_FOUND 1
+
+/* The call failed; see kv_last_error. */
+#define KV_ERROR -1
+
+/* An open database, created by kv_open and destroyed by kv_close. */
+typedef struct KvStore KvStore;
+
+/* Called by kv_scan for every key and its value.  Returning a non-zero
+ * value stops the scan. */
+typedef int (*KvScanCallback)(const char *key, const char *value, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif  /* __cplusplus */
+
+/* Opens, or creates, the database file at path.  Returns NULL on failure. */
+KvStore *kv_open(const char *path);
+
+/* Flushes and closes a database opened with kv_open.  Passing NULL does
+ * nothing. */
+void kv_close(KvStore *db);
+
+/* Looks up key and stores a newly allocated copy of its value in *value,
+ * which must be released with kv_free_string. */
+int kv_get(KvStore *db, const char *key, char **value);
+
+/* Inserts or overwrites the value of key. */
+int kv_put(KvStore *db, const char *key, const char *value);
+
+/* Deletes the value of key. */
+int kv_delete(KvStore *db, const char *key);
+
+/* Calls callback with every key and its value, in key order, passing
+ * user_data through unchanged.  The strings are only valid for the duration
+ * of the callback. */
+int kv_scan(KvStore *db, KvScanCallback callback, void *user_data);
+
+/* Frees a string returned by kv_get.  Passing NULL does nothing. */
+void kv_free_string(char *s);
+
+/* Returns a description of the last error on the calling thread, or NULL if
+ * there was none.  The string remains valid until the next failing call on
+ * the same thread. */
+const char *kv_last_error(void);
+
+#ifdef __cplusplus
+}  /* extern "C" */
+#endif  /* __cplusplus */
+
+#endif  /* KVSTORE_H */
@@ -0,0 +1,245 @@
+//! C bindings for the kvstore database.  The functions declared in
+//! `include/kvstore.h` are exported from the `kvstore_ffi` shared and static
+//! libraries, so the store can be embedded from C, C++ and any language with a
+//! C foreign function interface.
+//!
+//! Every function returns [`KV_OK`] on success, [`KV_NOT_FOUND`] when the key
+//! does not exist, or [`KV_ERROR`] on failure, in which case [`kv_last_error`]
+//! describes what went wrong.
+
+use kvstore::ActionKV;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// The call succeeded.
+pub const KV_OK: c_int = 0;
+/// The key does not exist in the database.
+pub const KV_NOT_FOUND: c_int = 1;
+/// The call failed; see [`kv_last_error`].
+pub const KV_ERROR: c_int = -1;
+
+/// An open database, created by [`kv_open`] and destroyed by [`kv_close`].
+pub struct KvStore {
+    akv: ActionKV,
+}
+
+/// Called by [`kv_scan`] for every key and its value.  Returning a non-zero
+/// value stops the scan.
+pub type KvScanCallback =
+    extern "C" fn(key: *const c_char, value: *const c_char, user_data: *mut c_void) -> c_int;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Records `error` as the calling thread's last error.
+fn set_last_error(error: &Error) {
+    let message = error.to_string().replace('\0', "");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Converts the result of a database call into a status code.
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => KV_OK,
+        Err(e) => {
+            set_last_error(&e);
+            KV_ERROR
+        }
+    }
+}
+
+/// Borrows the NUL-terminated UTF-8 string at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a NUL-terminated string that outlives the
+/// returned reference.
+unsafe fn to_str<'a>(ptr: *const c_char) -> Result<&'a str> {
+    if ptr.is_null() {
+        let error = Error::new(ErrorKind::InvalidInput, "null string argument");
+        return Err(error);
+    }
+
+    match CStr::from_ptr(ptr).to_str() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(Error::new(ErrorKind::InvalidInput, e)),
+    }
+}
+
+/// Copies `s` into a newly allocated C string, to be freed with
+/// [`kv_free_string`].
+fn to_c_string(s: String) -> Result<CString> {
+    CString::new(s).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Returns `true` if `error` is the error `get` and `delete` report for a
+/// missing key.
+fn is_not_found(error: &Error, akv: &ActionKV, key: &str) -> bool {
+    error.kind() == ErrorKind::InvalidData && !akv.contains_key(key)
+}
+
+/// Opens, or creates, the database file at `path`.  Returns null on failure.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kv_open(path: *const c_char) -> *mut KvStore {
+    let result = to_str(path).and_then(|path| ActionKV::open(Path::new(path)));
+    match result {
+        Ok(akv) => Box::into_raw(Box::new(KvStore { akv })),
+        Err(e) => {
+            set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Flushes and closes a database opened with [`kv_open`].  Passing null does
+/// nothing.
+///
+/// # Safety
+///
+/// `db` must be null or a pointer returned by [`kv_open`] that has not been
+/// closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kv_close(db: *mut KvStore) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Looks up `key` and stores a newly allocated copy of its value in `*value`,
+/// which must be released with [`kv_free_string`].
+///
+/// # Safety
+///
+/// `db` must be a pointer returned by [`kv_open`], `key` a NUL-terminated
+/// string and `value` a valid pointer to write the result to.
+#[no_mangle]
+pub unsafe extern "C" fn kv_get(
+    db: *mut KvStore,
+    key: *const c_char,
+    value: *mut *mut c_char,
+) -> c_int {
+    let akv = &(*db).akv;
+    let key = match to_str(key) {
+        Ok(key) => key,
+        Err(e) => return status(Err(e)),
+    };
+
+    match akv.get(key.to_string()).and_then(to_c_string) {
+        Ok(found) => {
+            *value = found.into_raw();
+            KV_OK
+        }
+        Err(e) if is_not_found(&e, akv, key) => KV_NOT_FOUND,
+        Err(e) => status(Err(e)),
+    }
+}
+
+/// Inserts or overwrites the value of `key`.
+///
+/// # Safety
+///
+/// `db` must be a pointer returned by [`kv_open`]; `key` and `value` must be
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn kv_put(
+    db: *mut KvStore,
+    key: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let akv = &mut (*db).akv;
+    let result = to_str(key).and_then(|key| {
+        let value = to_str(value)?;
+        akv.insert(key.to_string(), value.to_string())
+    });
+    status(result)
+}
+
+/// Deletes the value of `key`.
+///
+/// # Safety
+///
+/// `db` must be a pointer returned by [`kv_open`] and `key` a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn kv_delete(db: *mut KvStore, key: *const c_char) -> c_int {
+    let akv = &mut (*db).akv;
+    let key = match to_str(key) {
+        Ok(key) => key,
+        Err(e) => return status(Err(e)),
+    };
+
+    match akv.delete(key.to_string()) {
+        Ok(()) => KV_OK,
+        Err(e) if is_not_found(&e, akv, key) => KV_NOT_FOUND,
+        Err(e) => status(Err(e)),
+    }
+}
+
+/// Calls `callback` with every key and its value, in key order, passing
+/// `user_data` through unchanged.  The strings are only valid for the duration
+/// of the callback.
+///
+/// # Safety
+///
+/// `db` must be a pointer returned by [`kv_open`] and `callback` a valid
+/// function pointer.
+#[no_mangle]
+pub unsafe extern "C" fn kv_scan(
+    db: *mut KvStore,
+    callback: KvScanCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let akv = &(*db).akv;
+    let mut keys: Vec<&str> = akv.keys().collect();
+    keys.sort();
+
+    for key in keys {
+        let result = akv.get(key.to_string()).and_then(|value| {
+            let key = to_c_string(key.to_string())?;
+            let value = to_c_string(value)?;
+            Ok((key, value))
+        });
+        let (key, value) = match result {
+            Ok(pair) => pair,
+            Err(e) => return status(Err(e)),
+        };
+
+        if callback(key.as_ptr(), value.as_ptr(), user_data) != 0 {
+            break;
+        }
+    }
+
+    KV_OK
+}
+
+/// Frees a string returned by [`kv_get`].  Passing null does nothing.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by [`kv_get`] that has not been freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn kv_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Returns a description of the last error on the calling thread, or null if
+/// there was none.  The string remains valid until the next failing call on
+/// the same thread.
+#[no_mangle]
+pub extern "C" fn kv_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
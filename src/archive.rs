@@ -0,0 +1,253 @@
+//! The `snapshot` and `restore` commands, which pack a database into a single
+//! compressed archive that can be shipped to another host and unpacked there.
+//!
+//! A snapshot is a zstd-compressed tar archive holding:
+//!
+//! * `manifest.json`: the archive format and version, when and from where the
+//!   snapshot was taken, and the number of keys, size and CRC of the log;
+//! * `data`: a compacted copy of the log;
+//! * `hints`: one JSON object per key giving the size and write time of its
+//!   value, used to check the restored index;
+//! * `blobs/`: the blob files referenced by the log, if any.
+
+use kvstore::ActionKV;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies the archive format in the manifest.
+const FORMAT: &str = "kvstore-snapshot";
+
+/// Version of the archive layout written by [`snapshot`].
+const VERSION: u64 = 1;
+
+/// What a snapshot holds, as recorded in its manifest.
+pub struct Summary {
+    pub keys: u64,
+    pub data_bytes: u64,
+    pub blobs: u64,
+}
+
+/// Writes a snapshot of `akv`, whose log is at `database`, to a new archive at
+/// `path`.  An error of kind `AlreadyExists` is returned if `path` already
+/// exists.
+pub fn snapshot(akv: &ActionKV, database: &Path, path: &Path) -> Result<Summary> {
+    if path.exists() {
+        let error_message = format!("file: {} already exists", path.display());
+        let error = Error::new(ErrorKind::AlreadyExists, error_message);
+        return Err(error);
+    }
+
+    let staging = staging_dir(path);
+    std::fs::create_dir(&staging)?;
+    let result = write_archive(akv, database, &staging, path);
+    std::fs::remove_dir_all(&staging)?;
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// Copies `akv` into `staging` and packs the copy into the archive at `path`.
+fn write_archive(akv: &ActionKV, database: &Path, staging: &Path, path: &Path) -> Result<Summary> {
+    let data = staging.join("data");
+    akv.copy_to(&data)?;
+    let copy = ActionKV::open_with_options(&data, crate::options())?;
+
+    let hints = staging.join("hints");
+    let mut file = BufWriter::new(File::create(&hints)?);
+    for (key, meta) in copy.keys_with_meta() {
+        let hint = serde_json::json!({
+            "key": key,
+            "value_size": meta.value_size,
+            "timestamp": meta.timestamp,
+        });
+        writeln!(file, "{hint}")?;
+    }
+    file.flush()?;
+
+    let blob_dir = blob_dir(&data);
+    let blobs = match std::fs::read_dir(&blob_dir) {
+        Ok(entries) => entries.count() as u64,
+        Err(_) => 0,
+    };
+
+    let summary = Summary {
+        keys: copy.keys().count() as u64,
+        data_bytes: std::fs::metadata(&data)?.len(),
+        blobs,
+    };
+    let manifest = serde_json::json!({
+        "format": FORMAT,
+        "version": VERSION,
+        "created": now(),
+        "source": database.display().to_string(),
+        "keys": summary.keys,
+        "data_bytes": summary.data_bytes,
+        "data_crc32": checksum(&data)?,
+        "blobs": summary.blobs,
+    });
+    let manifest_path = staging.join("manifest.json");
+    std::fs::write(&manifest_path, format!("{manifest:#}\n"))?;
+
+    let file = File::create_new(path)?;
+    let encoder = zstd::Encoder::new(file, 0)?;
+    let mut archive = tar::Builder::new(encoder);
+    archive.append_path_with_name(&manifest_path, "manifest.json")?;
+    archive.append_path_with_name(&data, "data")?;
+    archive.append_path_with_name(&hints, "hints")?;
+    if blob_dir.is_dir() {
+        archive.append_dir_all("blobs", &blob_dir)?;
+    }
+    let file = archive.into_inner()?.finish()?;
+    file.sync_all()?;
+
+    Ok(summary)
+}
+
+/// Unpacks the snapshot at `path` into a new database at `database`, after
+/// checking the log against the manifest and the index against the hints.  An
+/// error of kind `AlreadyExists` is returned if `database` already exists.
+pub fn restore(path: &Path, database: &Path) -> Result<Summary> {
+    if database.exists() {
+        let error_message = format!("database: {} already exists", database.display());
+        let error = Error::new(ErrorKind::AlreadyExists, error_message);
+        return Err(error);
+    }
+
+    let staging = staging_dir(database);
+    std::fs::create_dir(&staging)?;
+    let result = unpack_archive(path, &staging, database);
+    std::fs::remove_dir_all(&staging)?;
+    result
+}
+
+/// Unpacks the archive at `path` into `staging`, checks it, and moves the
+/// database into place at `database`.
+fn unpack_archive(path: &Path, staging: &Path, database: &Path) -> Result<Summary> {
+    let decoder = zstd::Decoder::new(File::open(path)?)?;
+    tar::Archive::new(decoder).unpack(staging)?;
+
+    let manifest = std::fs::read_to_string(staging.join("manifest.json"))?;
+    let manifest: serde_json::Value = serde_json::from_str(&manifest)?;
+    if manifest["format"] != FORMAT {
+        let error_message = format!("archive: {} is not a kvstore snapshot", path.display());
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+    if manifest["version"] != VERSION {
+        let error_message = format!(
+            "archive: {} has unsupported version {}",
+            path.display(),
+            manifest["version"]
+        );
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let data = staging.join("data");
+    let saved_checksum = manifest["data_crc32"].as_u64();
+    let checksum = checksum(&data)? as u64;
+    if saved_checksum != Some(checksum) {
+        let error_message = format!(
+            "archive: {} data checksum mismatch: expected=0x{:0x} actual=0x{:0x}",
+            path.display(),
+            saved_checksum.unwrap_or_default(),
+            checksum
+        );
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let blobs = staging.join("blobs");
+    if blobs.is_dir() {
+        std::fs::rename(&blobs, blob_dir(&data))?;
+    }
+
+    let copy = ActionKV::open_with_options(&data, crate::options())?;
+    check_hints(&copy, &staging.join("hints"))?;
+    let summary = Summary {
+        keys: copy.keys().count() as u64,
+        data_bytes: std::fs::metadata(&data)?.len(),
+        blobs: manifest["blobs"].as_u64().unwrap_or_default(),
+    };
+    drop(copy);
+
+    if blob_dir(&data).is_dir() {
+        std::fs::rename(blob_dir(&data), blob_dir(database))?;
+    }
+    std::fs::rename(&data, database)?;
+    Ok(summary)
+}
+
+/// Checks that the index of `akv` holds exactly the keys listed in the hint
+/// file at `path`, with the same value sizes and write times.
+fn check_hints(akv: &ActionKV, path: &Path) -> Result<()> {
+    let file = BufReader::new(File::open(path)?);
+    let mut keys = akv.keys_with_meta();
+    for line in file.lines() {
+        let hint: serde_json::Value = serde_json::from_str(&line?)?;
+        let matches = match keys.next() {
+            Some((key, meta)) => {
+                hint["key"] == key
+                    && hint["value_size"] == meta.value_size
+                    && hint["timestamp"] == meta.timestamp
+            }
+            None => false,
+        };
+        if !matches {
+            let error_message = format!("snapshot index does not match hint: {hint}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+    }
+
+    if let Some((key, _)) = keys.next() {
+        let error_message = format!("snapshot key: {key} is missing from the hints");
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Returns the 32-bit CRC of the file at `path`.
+fn checksum(path: &Path) -> Result<u32> {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+    let mut digest = crc.digest();
+    let mut file = BufReader::new(File::open(path)?);
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buffer[..n]);
+    }
+    Ok(digest.finalize())
+}
+
+/// Returns the directory the commands work in while building or unpacking an
+/// archive next to `path`.
+fn staging_dir(path: &Path) -> PathBuf {
+    let mut dir = OsString::from(path.as_os_str());
+    dir.push(".staging");
+    PathBuf::from(dir)
+}
+
+/// Returns the blob directory of the database at `path`, mirroring the
+/// library's naming.
+fn blob_dir(path: &Path) -> PathBuf {
+    let mut dir = OsString::from(path.as_os_str());
+    dir.push(".blobs");
+    PathBuf::from(dir)
+}
@@ -0,0 +1,105 @@
+//! The audit log, which records every administrative command run against a
+//! database, such as compaction, imports, merges and restores, along with
+//! when it ran and whether it succeeded.
+//!
+//! The log lives next to the database: for `kvstore.db` it is
+//! `kvstore.db.audit`, holding one JSON object per line.  Entries are only
+//! ever appended, and each is flushed to stable storage before the command
+//! returns.
+
+use crate::Actions;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Returns a description of `action` for the audit log, or `None` if it is
+/// not an administrative command.
+pub fn operation(action: &Actions) -> Option<String> {
+    let operation = match action {
+        Actions::Apply {
+            file,
+            atomic: false,
+        } => format!("apply {file}"),
+        Actions::Apply { file, atomic: true } => format!("apply {file} --atomic"),
+        Actions::ApplyIncrement { input } => format!("apply-increment {input}"),
+        Actions::Backup { output, since } => format!("backup {output} --since {since}"),
+        Actions::Checkpoint => "checkpoint".to_string(),
+        Actions::Gc { dry_run: false, .. } => "gc".to_string(),
+        Actions::Import { input, .. } => format!("import {input}"),
+        Actions::Merge { other, .. } => format!("merge {other}"),
+        Actions::Migrate { dir, .. } => format!("migrate {dir}"),
+        Actions::Restore { archive } => format!("restore {archive}"),
+        Actions::RestoreTo { time, output } => format!("restore-to {time} {output}"),
+        Actions::SetPassphrase { remove: false } => "set-passphrase".to_string(),
+        Actions::SetPassphrase { remove: true } => "set-passphrase --remove".to_string(),
+        Actions::Snapshot { output } => format!("snapshot {output}"),
+        Actions::Sweep => "sweep".to_string(),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+/// Appends an entry for `operation` with its `outcome` to the audit log of the
+/// database at `database`.
+pub fn record<T>(database: &Path, operation: &str, outcome: &Result<T>) -> Result<()> {
+    let outcome = match outcome {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("error: {e}"),
+    };
+    let entry = serde_json::json!({
+        "time": now(),
+        "operation": operation,
+        "outcome": outcome,
+    });
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path(database))?;
+    writeln!(file, "{entry}")?;
+    file.sync_data()
+}
+
+/// Prints the entries of the audit log of the database at `database`, oldest
+/// first, skipping those recorded before `since`, in milliseconds since the
+/// Unix epoch.
+pub fn print(database: &Path, since: u64) -> Result<()> {
+    let file = match File::open(audit_path(database)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for line in BufReader::new(file).lines() {
+        let entry: serde_json::Value = serde_json::from_str(&line?)?;
+        let time = entry["time"].as_u64().unwrap_or_default();
+        if time < since {
+            continue;
+        }
+        let time = UNIX_EPOCH + Duration::from_millis(time);
+        println!(
+            "{}\t{}\t{}",
+            humantime::format_rfc3339_seconds(time),
+            entry["operation"].as_str().unwrap_or_default(),
+            entry["outcome"].as_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the audit log of the database at `path`.
+fn audit_path(path: &Path) -> PathBuf {
+    let mut audit = OsString::from(path.as_os_str());
+    audit.push(".audit");
+    PathBuf::from(audit)
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
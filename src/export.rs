@@ -0,0 +1,89 @@
+//! Writers for the `export` command, which copy every entry of the database
+//! into a format other tools can read.
+
+use clap::ValueEnum;
+use kvstore::ActionKV;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+/// Formats the `export` command can write.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Redis protocol (RESP) `SET` commands, for `redis-cli --pipe`
+    Resp,
+    /// A SQLite database with a single `kv(key, value)` table (requires the
+    /// `sqlite` feature)
+    Sqlite,
+}
+
+/// Writes every entry of `akv`, in key order, to a new file at `path` and
+/// returns the number of entries written.  An error of kind `AlreadyExists`
+/// is returned if `path` already exists.
+pub fn export(akv: &ActionKV, format: Format, path: &Path) -> Result<u64> {
+    if path.exists() {
+        let error_message = format!("file: {} already exists", path.display());
+        let error = Error::new(ErrorKind::AlreadyExists, error_message);
+        return Err(error);
+    }
+
+    match format {
+        Format::Resp => export_resp(akv, path),
+        Format::Sqlite => export_sqlite(akv, path),
+    }
+}
+
+/// Writes a `SET` command for each of `keys` to a new file at `path`, encoded
+/// in the Redis serialization protocol so that the file can be bulk loaded
+/// with `redis-cli --pipe < path`.
+fn export_resp(akv: &ActionKV, path: &Path) -> Result<u64> {
+    let file = std::fs::File::create_new(path)?;
+    let mut file = BufWriter::new(file);
+
+    let mut exported = 0;
+    akv.for_each(|key, value| {
+        write!(file, "*3\r\n$3\r\nSET\r\n")?;
+        write!(file, "${}\r\n{key}\r\n", key.len())?;
+        write!(file, "${}\r\n", value.len())?;
+        file.write_all(value)?;
+        file.write_all(b"\r\n")?;
+        exported += 1;
+        Ok(())
+    })?;
+
+    file.flush()?;
+    Ok(exported)
+}
+
+/// Writes `keys` and their values to the `kv` table of a new SQLite database
+/// at `path`, in a single transaction.
+#[cfg(feature = "sqlite")]
+fn export_sqlite(akv: &ActionKV, path: &Path) -> Result<u64> {
+    let mut db = rusqlite::Connection::open(path).map_err(Error::other)?;
+    let transaction = db.transaction().map_err(Error::other)?;
+    transaction
+        .execute(
+            "CREATE TABLE kv (key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL)",
+            (),
+        )
+        .map_err(Error::other)?;
+
+    let mut exported = 0;
+    {
+        let mut insert = transaction
+            .prepare("INSERT INTO kv (key, value) VALUES (?1, ?2)")
+            .map_err(Error::other)?;
+        for entry in akv.scan("") {
+            let (key, value) = entry?;
+            insert.execute((&key, &value)).map_err(Error::other)?;
+            exported += 1;
+        }
+    }
+
+    transaction.commit().map_err(Error::other)?;
+    Ok(exported)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn export_sqlite(_akv: &ActionKV, _path: &Path) -> Result<u64> {
+    Err(crate::missing_feature("sqlite"))
+}
@@ -0,0 +1,283 @@
+//! Readers for the `import` command, which load entries written by other
+//! tools into the database.
+
+use clap::ValueEnum;
+use kvstore::{ActionKV, Operation};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Formats the `import` command can read.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Comma-separated values with a header row; see [`Columns`]
+    Csv,
+    /// A SQLite database with a `kv(key, value)` table, as written by
+    /// `export --format sqlite` (requires the `sqlite` feature)
+    Sqlite,
+}
+
+/// Which CSV columns hold the key and the value.  Columns are named by their
+/// header, or by their zero-based index.  By default the first column is the
+/// key and the second the value.
+pub struct Columns {
+    /// The column holding the key.
+    pub key: Option<String>,
+    /// The column holding the value.
+    pub value: Option<String>,
+    /// Builds the value from several columns instead: every `{column}` in the
+    /// template is replaced with that column of the row.
+    pub template: Option<String>,
+}
+
+/// Inserts every entry stored in `path` into `akv`, flushes it, and returns
+/// the number of entries imported.  `columns` only applies to CSV files.
+pub fn import(akv: &mut ActionKV, format: Format, path: &Path, columns: &Columns) -> Result<u64> {
+    if !path.exists() {
+        let error_message = format!("file: {} does not exist", path.display());
+        let error = Error::new(ErrorKind::NotFound, error_message);
+        return Err(error);
+    }
+
+    let imported = match format {
+        Format::Csv => import_csv(akv, path, columns)?,
+        Format::Sqlite => import_sqlite(akv, path)?,
+    };
+
+    akv.flush()?;
+    Ok(imported)
+}
+
+/// Reads the pairs for the `insert-many` command from the file at `path`.
+/// Each non-empty line holds one pair: a JSON object with `key` and `value`
+/// strings, or a key separated from its value by the first tab or, failing
+/// that, the first `=`.
+pub fn read_pairs(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = read_text(path)?;
+
+    let mut pairs = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let pair = if line.trim_start().starts_with('{') {
+            json_pair(line)
+        } else {
+            line.split_once('\t')
+                .or_else(|| line.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        };
+
+        match pair {
+            Some(pair) => pairs.push(pair),
+            None => {
+                let error_message = format!(
+                    "line {line_number}: expected key<TAB>value, key=value or a JSON object"
+                );
+                let error = Error::new(ErrorKind::InvalidInput, error_message);
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// Reads the operations for the `apply` command from the JSON Lines file at
+/// `path`.  Each non-empty line is an object with an `op` of `set`, with `key`
+/// and `value` strings, or of `delete`, with a `key` string.
+pub fn read_operations(path: &Path) -> Result<Vec<Operation>> {
+    let contents = read_text(path)?;
+
+    let mut operations = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match json_operation(line) {
+            Some(operation) => operations.push(operation),
+            None => {
+                let error_message = format!(
+                    "line {line_number}: expected {{\"op\":\"set\",\"key\":...,\"value\":...}} \
+                     or {{\"op\":\"delete\",\"key\":...}}"
+                );
+                let error = Error::new(ErrorKind::InvalidInput, error_message);
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(operations)
+}
+
+/// Reads the text file at `path`, reporting one that is not UTF-8 as a bad
+/// argument (`InvalidInput`) rather than as damage to the database.
+fn read_text(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::InvalidData => {
+            let error_message = format!("{}: {e}", path.display());
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            Err(error)
+        }
+        result => result,
+    }
+}
+
+/// Parses a JSON line of the form `{"op": "set", "key": "...", "value":
+/// "..."}` or `{"op": "delete", "key": "..."}`.
+fn json_operation(line: &str) -> Option<Operation> {
+    let object: serde_json::Value = serde_json::from_str(line).ok()?;
+    let key = object.get("key")?.as_str()?.to_string();
+    match object.get("op")?.as_str()? {
+        "set" => {
+            let value = object.get("value")?.as_str()?.to_string();
+            Some(Operation::Set { key, value })
+        }
+        "delete" => Some(Operation::Delete { key }),
+        _ => None,
+    }
+}
+
+/// Parses a JSON line of the form `{"key": "...", "value": "..."}`.
+fn json_pair(line: &str) -> Option<(String, String)> {
+    let object: serde_json::Value = serde_json::from_str(line).ok()?;
+    let key = object.get("key")?.as_str()?;
+    let value = object.get("value")?.as_str()?;
+    Some((key.to_string(), value.to_string()))
+}
+
+/// Inserts one entry per row of the CSV file at `path`, taking the key and
+/// value from `columns`.
+fn import_csv(akv: &mut ActionKV, path: &Path, columns: &Columns) -> Result<u64> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+
+    let key_column = match &columns.key {
+        Some(name) => column_index(&headers, name)?,
+        None => 0,
+    };
+    let value = match (&columns.template, &columns.value) {
+        (Some(template), _) => Value::Template(parse_template(&headers, template)?),
+        (None, Some(name)) => Value::Column(column_index(&headers, name)?),
+        (None, None) => Value::Column(1),
+    };
+
+    let mut imported = 0;
+    for record in reader.records() {
+        let record = record?;
+        let line = match record.position() {
+            Some(position) => position.line(),
+            None => 0,
+        };
+        let field = |index: usize| match record.get(index) {
+            Some(field) => Ok(field),
+            None => {
+                let error_message = format!("line {line}: missing column {index}");
+                Err(Error::new(ErrorKind::InvalidInput, error_message))
+            }
+        };
+
+        let key = field(key_column)?.to_string();
+        let value = match &value {
+            Value::Column(index) => field(*index)?.to_string(),
+            Value::Template(parts) => {
+                let mut value = String::new();
+                for part in parts {
+                    match part {
+                        Part::Literal(text) => value.push_str(text),
+                        Part::Column(index) => value.push_str(field(*index)?),
+                    }
+                }
+                value
+            }
+        };
+
+        akv.insert(key, value)?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// Where the value of each CSV row comes from.
+enum Value {
+    Column(usize),
+    Template(Vec<Part>),
+}
+
+/// A piece of a value template: literal text, or the contents of a column.
+enum Part {
+    Literal(String),
+    Column(usize),
+}
+
+/// Returns the index of the column named `name` in `headers`.  A number that
+/// does not name a column is taken as a zero-based index.
+fn column_index(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+    if let Some(index) = headers.iter().position(|header| header == name) {
+        return Ok(index);
+    }
+
+    match name.parse::<usize>() {
+        Ok(index) if index < headers.len() => Ok(index),
+        _ => {
+            let error_message = format!("column: {name} not found in CSV header");
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            Err(error)
+        }
+    }
+}
+
+/// Splits `template` into literal text and `{column}` references.
+fn parse_template(headers: &csv::StringRecord, template: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                let error_message = format!("template: {template} has an unclosed '{{'");
+                let error = Error::new(ErrorKind::InvalidInput, error_message);
+                return Err(error);
+            }
+        };
+
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_string()));
+        }
+        parts.push(Part::Column(column_index(headers, &rest[start + 1..end])?));
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+/// Inserts every row of the `kv` table of the SQLite database at `path`.
+#[cfg(feature = "sqlite")]
+fn import_sqlite(akv: &mut ActionKV, path: &Path) -> Result<u64> {
+    let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY;
+    let db = rusqlite::Connection::open_with_flags(path, flags).map_err(Error::other)?;
+    let mut select = db
+        .prepare("SELECT key, value FROM kv")
+        .map_err(Error::other)?;
+    let mut rows = select.query(()).map_err(Error::other)?;
+
+    let mut imported = 0;
+    while let Some(row) = rows.next().map_err(Error::other)? {
+        let key: String = row.get(0).map_err(Error::other)?;
+        let value: String = row.get(1).map_err(Error::other)?;
+        akv.insert(key, value)?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn import_sqlite(_akv: &mut ActionKV, _path: &Path) -> Result<u64> {
+    Err(crate::missing_feature("sqlite"))
+}
@@ -0,0 +1,240 @@
+use crate::{
+    blob, checksum, expiry, lease, passphrase, sys, ActionKV, IndexEntry, FLAG_BLOB,
+    FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE, FLAG_TOMBSTONE,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Seek, SeekFrom};
+use std::path::Path;
+
+impl ActionKV {
+    /// Writes an incremental backup to a new file at `path`: every record
+    /// appended after `sequence`, oldest first, along with the blob files those
+    /// records reference.  Returns the sequence number the backup reaches, to
+    /// be passed as `sequence` when taking the next increment.  A `sequence`
+    /// of zero backs up the whole log.  An error of kind `AlreadyExists` is
+    /// returned if `path` already exists.
+    ///
+    /// The increment is itself a log, and is restored by applying it with
+    /// [`apply_increment`](ActionKV::apply_increment) to the full backup, or
+    /// to the previous increments, it was taken after.
+    pub fn backup_since(&self, sequence: u64, path: &Path) -> Result<u64> {
+        // Compaction rewrites records in key order, so collect the newer
+        // records first and copy them in the order they were written.
+        let mut records = Vec::new();
+        self.scan_log(|position, _, akv| {
+            // Checkpoints describe positions in this log only.
+            if akv.sequence > sequence && akv.flags & FLAG_CHECKPOINT == 0 {
+                records.push((akv.sequence, position));
+            }
+            Ok(())
+        })?;
+        records.sort_unstable();
+
+        let file = sys::with_mode(&mut std::fs::OpenOptions::new(), self.options.file_mode)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let source_dir = blob::blob_dir(&self.path);
+        let target_dir = blob::blob_dir(path);
+        let mut copied_blobs = HashSet::new();
+
+        let mut out = BufWriter::new(file);
+        checksum::write_format(&mut out, self.checksum)?;
+        for (_, position) in records {
+            let akv = self.undelta(self.get_record_at_position(position)?)?;
+            if akv.flags & FLAG_BLOB != 0 && copied_blobs.insert(akv.value.clone()) {
+                let value = blob::read_blob(&source_dir, &akv.value)?;
+                blob::write_blob(&target_dir, &akv.value, &value, self.options.file_mode)?;
+            }
+
+            ActionKV::write_record(
+                &mut out,
+                self.checksum,
+                &akv.key,
+                &akv.value,
+                &akv.user_meta,
+                akv.flags,
+                akv.timestamp,
+                akv.sequence,
+            )?;
+        }
+
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(self.last_sequence())
+    }
+
+    /// Appends the records of the increment at `path`, written by
+    /// [`backup_since`](ActionKV::backup_since), to the database, keeping
+    /// their sequence numbers and timestamps, and returns the number of
+    /// records applied.  Increments must be applied in the order they were
+    /// taken.  An error of kind `InvalidData` is returned, before anything is
+    /// written, if the increment overlaps writes the database already holds.
+    pub fn apply_increment(&mut self, path: &Path) -> Result<u64> {
+        // Processes sharing the database must not write in between the check
+        // of the first sequence number and the records appended.
+        self.locked(|akv| akv.append_increment(path))
+    }
+
+    /// Appends the records of the increment at `path`, for
+    /// `apply_increment`.
+    fn append_increment(&mut self, path: &Path) -> Result<u64> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+        let max_record_size = self.options.max_record_size;
+        let checksum = checksum::read_format(&mut file, max_record_size)?;
+        let start = checksum.first_record();
+
+        if length > start {
+            let first = ActionKV::process_record(&mut file, max_record_size, Some(checksum))?;
+            if first.sequence <= self.last_sequence() {
+                let error_message = format!(
+                    "increment: {} starts at sequence {}, but the database is already at {}",
+                    path.display(),
+                    first.sequence,
+                    self.last_sequence()
+                );
+                let error = Error::new(ErrorKind::InvalidData, error_message);
+                return Err(error);
+            }
+            file.seek(SeekFrom::Start(start))?;
+        }
+
+        let source_dir = blob::blob_dir(path);
+        let mut applied = 0;
+        let mut position = start;
+        while position < length {
+            let akv = ActionKV::process_record(&mut file, max_record_size, Some(checksum))?;
+            position = file.stream_position()?;
+            if akv.flags & FLAG_CHECKPOINT != 0 {
+                continue;
+            }
+
+            if akv.flags & FLAG_BLOB != 0 {
+                let value = blob::read_blob(&source_dir, &akv.value)?;
+                blob::write_blob(
+                    &self.blob_dir()?,
+                    &akv.value,
+                    &value,
+                    self.options.file_mode,
+                )?;
+            }
+
+            let mut record = Vec::new();
+            ActionKV::write_record(
+                &mut record,
+                self.checksum,
+                &akv.key,
+                &akv.value,
+                &akv.user_meta,
+                akv.flags,
+                akv.timestamp,
+                akv.sequence,
+            )?;
+            let start = self.append(&record)?;
+            self.next_sequence = self.next_sequence.max(akv.sequence + 1);
+
+            if akv.flags & FLAG_PASSPHRASE != 0 {
+                passphrase::load(&mut self.passphrase, akv.value, start);
+            } else if akv.flags & FLAG_EXPIRY != 0 {
+                expiry::load(&mut self.expiries, akv.key, &akv.value, start)?;
+            } else if akv.flags & FLAG_TOMBSTONE != 0 {
+                self.blob_refs.set(&akv.key, None);
+                self.expiries.remove(&akv.key);
+                self.database.remove(&akv.key);
+                self.remove_older(&akv.key);
+            } else if akv.flags & FLAG_LEASE != 0 {
+                lease::load(&mut self.leases, akv.key, &akv.value, akv.sequence, start)?;
+            } else {
+                let (blob, value_size) = match akv.flags & FLAG_BLOB {
+                    0 => (None, akv.value.len() as u64),
+                    _ => {
+                        let dir = blob::blob_dir(&self.path);
+                        (Some(akv.value.as_str()), blob::value_size(&dir, &akv.value))
+                    }
+                };
+                self.blob_refs.set(&akv.key, blob);
+                self.expiries.remove(&akv.key);
+                let length = record.len() as u64;
+                let entry = IndexEntry::new(start, length, value_size, akv.timestamp, akv.sequence);
+                self.database.insert(&akv.key, entry);
+            }
+            applied += 1;
+        }
+
+        self.rebuild_lru();
+        Ok(applied)
+    }
+
+    /// Writes the database as it stood at `timestamp`, in milliseconds since
+    /// the Unix epoch, to a new file at `path`, and returns the number of keys
+    /// written.  The log is replayed up to the cutoff: each key gets the last
+    /// value written at or before `timestamp`, and keys first written after it
+    /// are left out.  Only history still in the log can be recovered, so
+    /// values overwritten before the last compaction are gone.  The copy
+    /// requires the passphrase the database currently requires, if any.  An error of
+    /// kind `AlreadyExists` is returned if `path` already exists.
+    pub fn restore_to(&self, timestamp: u64, path: &Path) -> Result<u64> {
+        // A tombstone is the latest record of a removed key, with no position.
+        let mut latest: BTreeMap<String, (u64, Option<u64>)> = BTreeMap::new();
+        self.scan_log(|position, _, akv| {
+            let skipped = FLAG_LEASE | FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_PASSPHRASE;
+            if akv.flags & skipped != 0 || akv.timestamp > timestamp {
+                return Ok(());
+            }
+            let newer = match latest.get(&akv.key) {
+                Some((sequence, _)) => akv.sequence > *sequence,
+                None => true,
+            };
+            if newer {
+                let position = (akv.flags & FLAG_TOMBSTONE == 0).then_some(position);
+                latest.insert(akv.key, (akv.sequence, position));
+            }
+            Ok(())
+        })?;
+
+        let file = sys::with_mode(&mut std::fs::OpenOptions::new(), self.options.file_mode)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let source_dir = blob::blob_dir(&self.path);
+        let target_dir = blob::blob_dir(path);
+        let mut copied_blobs = HashSet::new();
+
+        let passphrase = self
+            .passphrase
+            .as_ref()
+            .map(|passphrase| passphrase.position);
+        let mut written = 0;
+        let mut out = BufWriter::new(file);
+        checksum::write_format(&mut out, self.checksum)?;
+        let values = latest.values().filter_map(|(_, position)| *position);
+        for position in values.chain(passphrase) {
+            let akv = self.undelta(self.get_record_at_position(position)?)?;
+            if akv.flags & FLAG_BLOB != 0 && copied_blobs.insert(akv.value.clone()) {
+                let value = blob::read_blob(&source_dir, &akv.value)?;
+                blob::write_blob(&target_dir, &akv.value, &value, self.options.file_mode)?;
+            }
+
+            ActionKV::write_record(
+                &mut out,
+                self.checksum,
+                &akv.key,
+                &akv.value,
+                &akv.user_meta,
+                akv.flags,
+                akv.timestamp,
+                akv.sequence,
+            )?;
+            if akv.flags & FLAG_PASSPHRASE == 0 {
+                written += 1;
+            }
+        }
+
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(written)
+    }
+}
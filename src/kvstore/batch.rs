@@ -0,0 +1,75 @@
+use crate::{now, ActionKV, IndexEntry, FLAG_BLOB};
+use std::io::Result;
+
+impl ActionKV {
+    /// Creates or updates an entry for every `key`/`value` pair in `pairs`.
+    /// The records are appended to the log with a single write, so loading
+    /// many entries costs one flush under [`SyncPolicy::Always`] rather than
+    /// one per entry.  Every pair is passed through the write hooks and
+    /// checked against the limits configured in [`Options`] before anything is
+    /// written.
+    ///
+    /// [`SyncPolicy::Always`]: crate::SyncPolicy::Always
+    /// [`Options`]: crate::Options
+    pub fn insert_many<I>(&mut self, pairs: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self.run_write_hooks(&key, value)?;
+                Ok((key, value))
+            })
+            .collect::<Result<Vec<(String, String)>>>()?;
+        for (key, value) in &pairs {
+            self.check_limits(key, value)?;
+        }
+        self.locked(|akv| akv.append_pairs(pairs))
+    }
+
+    /// Appends a record for every pair in `pairs` with a single write, for
+    /// `insert_many`.
+    fn append_pairs(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.apply_repairs()?;
+
+        let timestamp = now();
+        let mut records = Vec::new();
+        let mut entries = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let value_size = value.len() as u64;
+            let (value, flags, blob) = if self.spills(&value) {
+                let name = self.store_blob(value.as_bytes())?;
+                (name.clone(), FLAG_BLOB, Some(name))
+            } else {
+                (value, 0, None)
+            };
+
+            let offset = records.len() as u64;
+            let sequence = self.next_sequence;
+            ActionKV::write_record(
+                &mut records,
+                self.checksum,
+                &key,
+                &value,
+                &[],
+                flags,
+                timestamp,
+                sequence,
+            )?;
+            self.next_sequence += 1;
+            let length = records.len() as u64 - offset;
+            entries.push((key, offset, length, value_size, sequence, blob));
+        }
+
+        let start = self.append(&records)?;
+        for (key, offset, length, value_size, sequence, blob) in entries {
+            self.blob_refs.set(&key, blob.as_deref());
+            self.expiries.remove(&key);
+            let entry = IndexEntry::new(start + offset, length, value_size, timestamp, sequence);
+            self.database.insert(&key, entry);
+            self.cache_written(&key, value_size)?;
+        }
+        Ok(())
+    }
+}
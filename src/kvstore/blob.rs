@@ -0,0 +1,155 @@
+use crate::checksum::CRC_32;
+use crate::{memory, sys};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// The 64-bit CRC naming deduplicated blobs, built once like
+/// [`CRC_32`].
+static CRC_64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+/// Returns the directory holding the blob files of the database at `path`.
+/// For a database named `kvstore.db` this is `kvstore.db.blobs`.
+pub(crate) fn blob_dir(path: &Path) -> PathBuf {
+    let mut dir = OsString::from(path.as_os_str());
+    dir.push(".blobs");
+    PathBuf::from(dir)
+}
+
+/// Returns the number of blob files in `dir` and the bytes they take up, or
+/// zeros if there is no such directory.
+pub(crate) fn dir_usage(dir: &Path) -> Result<(u64, u64)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    };
+
+    let (mut files, mut bytes) = (0, 0);
+    for entry in entries {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// Writes `value` to the blob file `name` inside `dir`, creating the directory
+/// if needed, and flushes it to stable storage.  The value is prefixed with its
+/// 32-bit CRC so that a damaged blob is detected when it is read back.  The
+/// file is created with the permission bits `mode`, if given.
+pub(crate) fn write_blob(dir: &Path, name: &str, value: &[u8], mode: Option<u32>) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    let checksum = CRC_32.checksum(value);
+
+    let file = sys::with_mode(&mut std::fs::OpenOptions::new(), mode)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dir.join(name))?;
+    let mut file = BufWriter::new(file);
+    file.write_u32::<BigEndian>(checksum)?;
+    file.write_all(value)?;
+
+    // The log record referencing this blob may be flushed before the blob
+    // otherwise, so make the blob durable first.
+    let file = file.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Reads the blob file `name` inside `dir` and verifies its checksum.
+pub(crate) fn read_blob(dir: &Path, name: &str) -> Result<Vec<u8>> {
+    let file = File::open(dir.join(name))?;
+    let mut file = BufReader::new(file);
+    let saved_checksum = file.read_u32::<BigEndian>()?;
+    let mut value = Vec::new();
+    file.read_to_end(&mut value)?;
+
+    let checksum = CRC_32.checksum(&value);
+    if saved_checksum != checksum {
+        let error_message = format!(
+            "blob {name} checksum mismatch: expected=0x{:0x} actual=0x{:0x}",
+            saved_checksum, checksum
+        );
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    Ok(value)
+}
+
+/// Returns the length of the value held by the blob file `name` inside `dir`,
+/// or zero if the blob cannot be found.
+pub(crate) fn value_size(dir: &Path, name: &str) -> u64 {
+    match std::fs::metadata(dir.join(name)) {
+        Ok(metadata) => metadata.len().saturating_sub(4),
+        Err(_) => 0,
+    }
+}
+
+/// Returns the content-addressed blob name for `value`, used when
+/// [`Options::dedup`](crate::Options::dedup) is enabled.  The name combines a
+/// 64-bit CRC of the value with its length.
+pub(crate) fn content_name(value: &[u8]) -> String {
+    let hash = CRC_64.checksum(value);
+    format!("{hash:016x}-{:x}", value.len())
+}
+
+/// Tracks which live keys reference which blob files, so that a blob shared
+/// by several keys is only removed once nothing refers to it anymore.
+#[derive(Default)]
+pub(crate) struct BlobRefs {
+    by_key: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+impl BlobRefs {
+    /// Records that `key` now references the blob `name`, or no blob at all,
+    /// releasing whichever blob the key referenced before.
+    pub(crate) fn set(&mut self, key: &str, name: Option<&str>) {
+        let previous = match name {
+            Some(name) => {
+                *self.counts.entry(name.to_string()).or_insert(0) += 1;
+                self.by_key.insert(key.to_string(), name.to_string())
+            }
+            None => self.by_key.remove(key),
+        };
+
+        if let Some(previous) = previous {
+            if let Some(count) = self.counts.get_mut(&previous) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&previous);
+                }
+            }
+        }
+    }
+
+    /// Returns the blob referenced by `key`, if any.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.by_key.get(key).map(String::as_str)
+    }
+
+    /// Returns `true` if at least one live key references the blob `name`.
+    pub(crate) fn is_referenced(&self, name: &str) -> bool {
+        self.counts.contains_key(name)
+    }
+
+    /// Returns the estimated bytes taken up by the references.
+    pub(crate) fn memory(&self) -> u64 {
+        let strings: u64 = self
+            .by_key
+            .iter()
+            .map(|(key, name)| memory::string_bytes(key) + memory::string_bytes(name))
+            .chain(self.counts.keys().map(memory::string_bytes))
+            .sum();
+        memory::hash_map_bytes(&self.by_key) + memory::hash_map_bytes(&self.counts) + strings
+    }
+}
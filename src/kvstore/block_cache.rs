@@ -0,0 +1,149 @@
+use crate::ActionKV;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// Size of the blocks the log file is cached in.
+const BLOCK_SIZE: u64 = 32 * 1024;
+
+/// Recently read blocks of the log, kept when
+/// [`Options::block_cache_size`](crate::Options::block_cache_size) is set so
+/// reads of records near each other, such as keys with a common prefix after
+/// compaction has written them side by side, are served from memory.  Only
+/// whole blocks below the end of the written log are cached: the log is only
+/// ever appended to, so they never change until the log is replaced.
+pub(crate) struct BlockCache {
+    /// Tick of the last use, and contents, of every cached block by index.
+    blocks: HashMap<u64, (u64, Arc<[u8]>)>,
+    /// Cached block indexes by the tick of their last use.
+    order: BTreeMap<u64, u64>,
+    next_tick: u64,
+    /// Number of blocks the cache holds at most.
+    capacity: usize,
+}
+
+impl BlockCache {
+    /// Creates an empty cache holding at most `size` bytes.
+    pub(crate) fn new(size: u64) -> BlockCache {
+        BlockCache {
+            blocks: HashMap::new(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+            capacity: (size / BLOCK_SIZE) as usize,
+        }
+    }
+
+    /// Returns the bytes taken up by the cached blocks.
+    pub(crate) fn memory(&self) -> u64 {
+        self.blocks
+            .values()
+            .map(|(_, block)| block.len() as u64)
+            .sum()
+    }
+
+    /// Returns the cached block `index`, recording the use.
+    fn get(&mut self, index: u64) -> Option<Arc<[u8]>> {
+        let tick = self.next_tick;
+        let (last, block) = self.blocks.get_mut(&index)?;
+        self.order.remove(last);
+        self.order.insert(tick, index);
+        *last = tick;
+        self.next_tick += 1;
+        Some(block.clone())
+    }
+
+    /// Caches block `index`, evicting the least recently used block if the
+    /// cache is full.
+    fn insert(&mut self, index: u64, block: Arc<[u8]>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.blocks.len() >= self.capacity {
+            if let Some((_, victim)) = self.order.pop_first() {
+                self.blocks.remove(&victim);
+            }
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.order.insert(tick, index);
+        self.blocks.insert(index, (tick, block));
+    }
+}
+
+/// Reads the written part of the log through the block cache, one block at
+/// a time.
+pub(crate) struct BlockReader<'a> {
+    akv: &'a ActionKV,
+    cache: &'a RefCell<BlockCache>,
+    position: u64,
+    /// Offset just past the written part of the log.
+    end: u64,
+    /// The block holding `position`, once read.
+    block: Option<(u64, Arc<[u8]>)>,
+}
+
+impl<'a> BlockReader<'a> {
+    /// Creates a reader of the log of `akv` through `cache`, starting at
+    /// `position` and stopping at `end`.
+    pub(crate) fn new(
+        akv: &'a ActionKV,
+        cache: &'a RefCell<BlockCache>,
+        position: u64,
+        end: u64,
+    ) -> BlockReader<'a> {
+        BlockReader {
+            akv,
+            cache,
+            position,
+            end,
+            block: None,
+        }
+    }
+
+    /// Returns block `index` of the log, from the cache if it holds it.  A
+    /// whole block read from the log is added to the cache; the last, partly
+    /// written block before `end` is not.
+    fn read_block(&self, index: u64) -> Result<Arc<[u8]>> {
+        if let Some(block) = self.cache.borrow_mut().get(index) {
+            return Ok(block);
+        }
+
+        let start = index * BLOCK_SIZE;
+        let length = BLOCK_SIZE.min(self.end - start);
+        let mut block = Vec::with_capacity(length as usize);
+        let mut file = self.akv.storage.reader();
+        file.seek(SeekFrom::Start(start))?;
+        file.take(length).read_to_end(&mut block)?;
+
+        let block: Arc<[u8]> = block.into();
+        if block.len() as u64 == BLOCK_SIZE {
+            self.cache.borrow_mut().insert(index, block.clone());
+        }
+        Ok(block)
+    }
+}
+
+impl Read for BlockReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.position >= self.end {
+            return Ok(0);
+        }
+
+        let index = self.position / BLOCK_SIZE;
+        let block = match &self.block {
+            Some((current, block)) if *current == index => block.clone(),
+            _ => {
+                let block = self.read_block(index)?;
+                self.block = Some((index, block.clone()));
+                block
+            }
+        };
+
+        let offset = (self.position % BLOCK_SIZE) as usize;
+        let length = buf.len().min(block.len().saturating_sub(offset));
+        buf[..length].copy_from_slice(&block[offset..offset + length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{memory, ActionKV};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Result;
+
+/// Recency of use and size of every key, kept when
+/// [`Options::max_live_bytes`](crate::Options::max_live_bytes) turns the
+/// database into a cache.  Every key gets a tick from a counter each time it
+/// is written or read; the key with the lowest tick is the least recently
+/// used.
+#[derive(Default)]
+pub(crate) struct Lru {
+    /// Tick of the last use, and size, of every key.
+    keys: HashMap<String, (u64, u64)>,
+    /// Keys by the tick of their last use.
+    order: BTreeMap<u64, String>,
+    next_tick: u64,
+    /// Total size of every key and its value.
+    total: u64,
+}
+
+impl Lru {
+    /// Records a use of `key`, which now takes up `size` bytes.
+    pub(crate) fn touch(&mut self, key: &str, size: u64) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        match self.keys.get_mut(key) {
+            Some((last, old_size)) => {
+                self.order.remove(last);
+                self.total = self.total - *old_size + size;
+                *last = tick;
+                *old_size = size;
+            }
+            None => {
+                self.total += size;
+                self.keys.insert(key.to_string(), (tick, size));
+            }
+        }
+        self.order.insert(tick, key.to_string());
+    }
+
+    /// Records a read of `key`, which keeps its size.
+    pub(crate) fn touch_read(&mut self, key: &str) {
+        if let Some((_, size)) = self.keys.get(key) {
+            let size = *size;
+            self.touch(key, size);
+        }
+    }
+
+    /// Stops tracking `key`, once it has been removed from the database.
+    pub(crate) fn forget(&mut self, key: &str) {
+        if let Some((tick, size)) = self.keys.remove(key) {
+            self.order.remove(&tick);
+            self.total -= size;
+        }
+    }
+
+    /// Returns the estimated bytes taken up by the recency of every key.
+    pub(crate) fn memory(&self) -> u64 {
+        let keys = self.keys.keys().map(memory::string_bytes).sum::<u64>();
+        memory::hash_map_bytes(&self.keys) + memory::btree_map_bytes(&self.order) + keys * 2
+    }
+
+    /// Returns the least recently used keys that have to go for the total
+    /// size to drop to `max_bytes`, oldest first, sparing `keep`.
+    fn victims(&self, max_bytes: u64, keep: &str) -> Vec<String> {
+        let mut total = self.total;
+        let mut victims = Vec::new();
+        for key in self.order.values() {
+            if total <= max_bytes {
+                break;
+            }
+            if key != keep {
+                total -= self.keys[key].1;
+                victims.push(key.clone());
+            }
+        }
+        victims
+    }
+}
+
+/// Returns the size counted against the cache limit for `key` holding a
+/// value of `value_size` bytes.
+pub(crate) fn entry_size(key: &str, value_size: u64) -> u64 {
+    key.len() as u64 + value_size
+}
+
+impl ActionKV {
+    /// Rebuilds the recency of use from the index when the database is a
+    /// cache, ordering keys by when they were last written.
+    pub(crate) fn rebuild_lru(&mut self) {
+        if self.options.max_live_bytes.is_none() {
+            return;
+        }
+
+        let mut entries: Vec<(&str, u64, u64)> = self
+            .database
+            .iter()
+            .map(|(key, entry)| (key, entry.meta.timestamp, entry.meta.value_size))
+            .collect();
+        entries.sort_by_key(|(_, timestamp, _)| *timestamp);
+
+        let mut lru = Lru::default();
+        for (key, _, value_size) in entries {
+            lru.touch(key, entry_size(key, value_size));
+        }
+        self.lru = Some(lru.into());
+    }
+
+    /// Records that `key` was just read, when the database is a cache or
+    /// counts accesses.
+    pub(crate) fn cache_read(&self, key: &str) {
+        self.count_read(key);
+        if let Some(lru) = &self.lru {
+            lru.borrow_mut().touch_read(key);
+        }
+    }
+
+    /// Records that `key` was just written with a value of `value_size`
+    /// bytes, and evicts the least recently used keys if the cache is now over
+    /// its limit.
+    pub(crate) fn cache_written(&mut self, key: &str, value_size: u64) -> Result<()> {
+        self.count_write(key);
+        let max_bytes = match self.options.max_live_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok(()),
+        };
+        let lru = match &mut self.lru {
+            Some(lru) => lru.get_mut(),
+            None => return Ok(()),
+        };
+
+        lru.touch(key, entry_size(key, value_size));
+        let victims = lru.victims(max_bytes, key);
+        if victims.is_empty() {
+            return Ok(());
+        }
+        self.write_tombstones(&victims)
+    }
+}
@@ -0,0 +1,409 @@
+use crate::checksum::CRC_32;
+use crate::compact::sync_parent_dir;
+use crate::expiry::Expiry;
+use crate::index::Index;
+use crate::info::IndexFile;
+use crate::lease::Lease;
+use crate::passphrase::{self, Passphrase};
+use crate::storage::Storage;
+use crate::sys;
+use crate::{now, record_length, ActionKV, IndexEntry, KeyMeta, FLAG_CHECKPOINT};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies an index file written by [`ActionKV::checkpoint`].
+const INDEX_MAGIC: u32 = 0x414B_5649;
+
+/// Version of the index file layout.
+const INDEX_VERSION: u8 = 4;
+
+/// A checkpoint record in the log, written by [`ActionKV::checkpoint`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Sequence number of the checkpoint record.  Every write before the
+    /// checkpoint has a lower sequence number, which makes it a clean point to
+    /// resume [`backup_since`](ActionKV::backup_since) or replication from.
+    pub sequence: u64,
+    /// When the checkpoint was written, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Offset of the checkpoint record in the log.
+    pub position: u64,
+}
+
+/// Returns the file holding the index saved by the last checkpoint of the
+/// database at `path`.  For a database named `kvstore.db` this is
+/// `kvstore.db.index`.
+pub(crate) fn index_path(path: &Path) -> PathBuf {
+    let mut index = OsString::from(path.as_os_str());
+    index.push(".index");
+    PathBuf::from(index)
+}
+
+/// Removes the index saved by the last checkpoint of the database at `path`,
+/// once it no longer describes the log.
+pub(crate) fn remove_index(path: &Path) -> Result<()> {
+    match std::fs::remove_file(index_path(path)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The index as saved by a checkpoint, read back from its index file.
+struct SavedIndex {
+    checkpoint: Checkpoint,
+    next_sequence: u64,
+    database: Index,
+    blobs: Vec<(String, String)>,
+    leases: HashMap<String, Lease>,
+    expiries: HashMap<String, Expiry>,
+    passphrase: Option<Passphrase>,
+}
+
+impl ActionKV {
+    /// Appends a checkpoint record to the log, flushes the log to stable
+    /// storage, and saves the index next to the database as it stands at the
+    /// checkpoint.  The next open loads the saved index and only scans the
+    /// records written after the checkpoint, instead of the whole log.
+    ///
+    /// The saved index is a cache: it is ignored, and the log scanned in full,
+    /// if it is missing, damaged, or refers to a checkpoint the log no longer
+    /// holds, as after compaction.  Records before the checkpoint are not
+    /// checksummed when the saved index is used.  Databases without a file
+    /// only write the checkpoint record.
+    pub fn checkpoint(&mut self) -> Result<Checkpoint> {
+        self.check_fully_indexed("checkpointing")?;
+        self.locked(|akv| akv.write_checkpoint())
+    }
+
+    /// Appends a checkpoint record and saves the index, for `checkpoint`.
+    fn write_checkpoint(&mut self) -> Result<Checkpoint> {
+        let timestamp = now();
+        let sequence = self.next_sequence;
+        let mut record = Vec::new();
+        ActionKV::write_record(
+            &mut record,
+            self.checksum,
+            "",
+            "",
+            &[],
+            FLAG_CHECKPOINT,
+            timestamp,
+            sequence,
+        )?;
+        self.next_sequence += 1;
+        let position = self.append_records(&record)?;
+        self.flush()?;
+        self.storage.sync_data()?;
+
+        let checkpoint = Checkpoint {
+            sequence,
+            timestamp,
+            position,
+        };
+        self.last_checkpoint = Some(checkpoint);
+        if let Storage::File(_) = self.storage {
+            self.save_index(checkpoint)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Returns the most recent checkpoint in the log, if any.
+    pub fn last_checkpoint(&self) -> Option<Checkpoint> {
+        self.last_checkpoint
+    }
+
+    /// Writes a checkpoint if [`Options::checkpoint_interval`] bytes have been
+    /// appended to the log since the last one.
+    ///
+    /// [`Options::checkpoint_interval`]: crate::Options::checkpoint_interval
+    pub(crate) fn checkpoint_if_due(&mut self) -> Result<()> {
+        let interval = match self.options.checkpoint_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+
+        let since = match self.last_checkpoint {
+            Some(checkpoint) => checkpoint.position,
+            None => 0,
+        };
+        if self.end - since >= interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a checkpoint, for [`Options::checkpoint_period`], unless nothing
+    /// was appended to the log since the last one.
+    ///
+    /// [`Options::checkpoint_period`]: crate::Options::checkpoint_period
+    pub(crate) fn checkpoint_if_written(&mut self) -> Result<()> {
+        let since = match self.last_checkpoint {
+            Some(checkpoint) => checkpoint.position + record_length("", "", &[]),
+            None => self.checksum.first_record(),
+        };
+        if self.end > since {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index, as of `checkpoint`, to the index file, replacing the
+    /// previous one atomically.
+    fn save_index(&self, checkpoint: Checkpoint) -> Result<()> {
+        let mut data = Vec::new();
+        data.write_u32::<BigEndian>(INDEX_MAGIC)?;
+        data.write_u8(INDEX_VERSION)?;
+        data.write_u64::<BigEndian>(checkpoint.sequence)?;
+        data.write_u64::<BigEndian>(checkpoint.timestamp)?;
+        data.write_u64::<BigEndian>(checkpoint.position)?;
+        data.write_u64::<BigEndian>(self.next_sequence)?;
+
+        data.write_u64::<BigEndian>(self.database.len() as u64)?;
+        for (key, entry) in &self.database {
+            write_string(&mut data, key)?;
+            data.write_u64::<BigEndian>(entry.position)?;
+            data.write_u64::<BigEndian>(entry.length)?;
+            data.write_u64::<BigEndian>(entry.meta.value_size)?;
+            data.write_u64::<BigEndian>(entry.meta.timestamp)?;
+            data.write_u64::<BigEndian>(entry.meta.sequence)?;
+            write_string(&mut data, self.blob_refs.get(key).unwrap_or_default())?;
+        }
+
+        data.write_u64::<BigEndian>(self.leases.len() as u64)?;
+        for (key, lease) in &self.leases {
+            write_string(&mut data, key)?;
+            data.write_u64::<BigEndian>(lease.token)?;
+            data.write_u64::<BigEndian>(lease.expires_at)?;
+            data.write_u64::<BigEndian>(lease.position)?;
+        }
+
+        data.write_u64::<BigEndian>(self.expiries.len() as u64)?;
+        for (key, expiry) in &self.expiries {
+            write_string(&mut data, key)?;
+            data.write_u64::<BigEndian>(expiry.expires_at)?;
+            data.write_u64::<BigEndian>(expiry.position)?;
+        }
+
+        match &self.passphrase {
+            Some(passphrase) => {
+                write_string(&mut data, &passphrase.verifier)?;
+                data.write_u64::<BigEndian>(passphrase.position)?;
+            }
+            None => {
+                write_string(&mut data, "")?;
+                data.write_u64::<BigEndian>(0)?;
+            }
+        }
+
+        self.save_file(&index_path(&self.path), data)
+    }
+
+    /// Writes `data` followed by its CRC-32 to the file at `path` next to the
+    /// database, replacing the previous one atomically.
+    pub(crate) fn save_file(&self, path: &Path, mut data: Vec<u8>) -> Result<()> {
+        let checksum = CRC_32.checksum(&data);
+        data.write_u32::<BigEndian>(checksum)?;
+
+        let mut temp = OsString::from(path.as_os_str());
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut file = sys::with_mode(&mut std::fs::OpenOptions::new(), self.options.file_mode)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)?;
+        sync_parent_dir(path)
+    }
+
+    /// Loads the index saved by the last checkpoint, if there is a usable one,
+    /// and returns the offset the log has to be scanned from: just past the
+    /// checkpoint record, or zero without a saved index.
+    pub(crate) fn load_index(&mut self) -> Result<u64> {
+        let saved = match self.read_saved_index()? {
+            Some(saved) => saved,
+            None => return Ok(0),
+        };
+
+        let checkpoint = saved.checkpoint;
+        self.database = saved.database;
+        for (key, name) in &saved.blobs {
+            self.blob_refs.set(key, Some(name));
+        }
+        self.leases = saved.leases;
+        self.expiries = saved.expiries;
+        self.passphrase = saved.passphrase;
+        self.next_sequence = saved.next_sequence;
+        self.last_checkpoint = Some(checkpoint);
+        Ok(checkpoint.position + record_length("", "", &[]))
+    }
+
+    /// Returns the state of the index file saved by the last checkpoint.
+    pub(crate) fn index_file(&self) -> Result<IndexFile> {
+        if !index_path(&self.path).exists() {
+            return Ok(IndexFile::Missing);
+        }
+        match self.read_saved_index()? {
+            Some(saved) => Ok(IndexFile::Current(saved.checkpoint)),
+            None => Ok(IndexFile::Stale),
+        }
+    }
+
+    /// Reads the index saved by the last checkpoint, or returns `None` if
+    /// there is none, it is damaged, or it refers to a checkpoint the log no
+    /// longer holds.
+    fn read_saved_index(&self) -> Result<Option<SavedIndex>> {
+        if !matches!(self.storage, Storage::File(_)) {
+            return Ok(None);
+        }
+
+        let data = match std::fs::read(index_path(&self.path)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let saved = match read_index(&data) {
+            Ok(saved) => saved,
+            Err(_) => return Ok(None),
+        };
+
+        // The saved index only describes this log if the checkpoint it was
+        // taken at is still there.
+        let checkpoint = saved.checkpoint;
+        if checkpoint.position >= self.storage.len()? {
+            return Ok(None);
+        }
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(checkpoint.position))?;
+        let record =
+            ActionKV::process_record(&mut file, self.options.max_record_size, Some(self.checksum));
+        match record {
+            Ok(kv) if kv.flags & FLAG_CHECKPOINT != 0 && kv.sequence == checkpoint.sequence => {
+                Ok(Some(saved))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Returns the contents of a file written by
+/// [`save_file`](ActionKV::save_file), after checking its CRC-32.
+pub(crate) fn checked_body(data: &[u8]) -> Result<&[u8]> {
+    if data.len() < 4 {
+        let error = Error::new(ErrorKind::InvalidData, "index file too short");
+        return Err(error);
+    }
+    let (body, saved_checksum) = data.split_at(data.len() - 4);
+    let saved_checksum = u32::from_be_bytes(saved_checksum.try_into().unwrap());
+    let checksum = CRC_32.checksum(body);
+    if saved_checksum != checksum {
+        let error_message = format!(
+            "index checksum mismatch: expected=0x{:0x} actual=0x{:0x}",
+            saved_checksum, checksum
+        );
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+    Ok(body)
+}
+
+/// Parses an index file, checking its magic, version and checksum.
+fn read_index(data: &[u8]) -> Result<SavedIndex> {
+    let mut body = checked_body(data)?;
+    let magic = body.read_u32::<BigEndian>()?;
+    let version = body.read_u8()?;
+    if magic != INDEX_MAGIC || version != INDEX_VERSION {
+        let error_message = format!("unsupported index file: magic=0x{magic:0x} version={version}");
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let checkpoint = Checkpoint {
+        sequence: body.read_u64::<BigEndian>()?,
+        timestamp: body.read_u64::<BigEndian>()?,
+        position: body.read_u64::<BigEndian>()?,
+    };
+    let next_sequence = body.read_u64::<BigEndian>()?;
+
+    let mut database = Index::default();
+    let mut blobs = Vec::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        let key = read_string(&mut body)?;
+        let position = body.read_u64::<BigEndian>()?;
+        let length = body.read_u64::<BigEndian>()?;
+        let meta = KeyMeta {
+            value_size: body.read_u64::<BigEndian>()?,
+            timestamp: body.read_u64::<BigEndian>()?,
+            sequence: body.read_u64::<BigEndian>()?,
+        };
+        let blob = read_string(&mut body)?;
+        if !blob.is_empty() {
+            blobs.push((key.clone(), blob));
+        }
+        let entry = IndexEntry {
+            position,
+            length,
+            meta,
+        };
+        database.insert(&key, entry);
+    }
+
+    let mut leases = HashMap::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        let key = read_string(&mut body)?;
+        let lease = Lease {
+            token: body.read_u64::<BigEndian>()?,
+            expires_at: body.read_u64::<BigEndian>()?,
+            position: body.read_u64::<BigEndian>()?,
+        };
+        leases.insert(key, lease);
+    }
+
+    let mut expiries = HashMap::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        let key = read_string(&mut body)?;
+        let expiry = Expiry {
+            expires_at: body.read_u64::<BigEndian>()?,
+            position: body.read_u64::<BigEndian>()?,
+        };
+        expiries.insert(key, expiry);
+    }
+
+    let mut passphrase = None;
+    let verifier = read_string(&mut body)?;
+    passphrase::load(&mut passphrase, verifier, body.read_u64::<BigEndian>()?);
+
+    Ok(SavedIndex {
+        checkpoint,
+        next_sequence,
+        database,
+        blobs,
+        leases,
+        expiries,
+        passphrase,
+    })
+}
+
+/// Writes `value` prefixed with its length.
+pub(crate) fn write_string<W: Write>(out: &mut W, value: &str) -> Result<()> {
+    out.write_u32::<BigEndian>(value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+/// Reads a string written by [`write_string`].
+pub(crate) fn read_string<R: Read>(input: &mut R) -> Result<String> {
+    let length = input.read_u32::<BigEndian>()?;
+    let mut value = Vec::new();
+    input.take(length as u64).read_to_end(&mut value)?;
+    if value.len() != length as usize {
+        let error = Error::new(ErrorKind::UnexpectedEof, "index file truncated");
+        return Err(error);
+    }
+    String::from_utf8(value).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,121 @@
+use crate::{now, record_length, ActionKV, FLAG_CHECKPOINT};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+/// CRC-32 as computed by `cksum`, which also protects blob files and saved
+/// indexes and picks the shard of a key.  Building a `Crc` computes its lookup
+/// table, so it is built once, at compile time, rather than per use.
+pub(crate) static CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+
+/// Key of the format record that starts a log whose records are not
+/// checksummed with CRC-32.
+const FORMAT_KEY: &str = "kvstore-format";
+
+/// The checksum protecting every record of a database, chosen by
+/// [`Options::checksum`](crate::Options::checksum) when the database is
+/// created and kept for its lifetime, including across compaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 as computed by `cksum`, used by databases created before
+    /// CRC-32C became the default.  Databases using it carry no format
+    /// record.
+    Crc32,
+    /// CRC-32C (Castagnoli), computed with the SSE 4.2 or ARMv8 CRC
+    /// instructions where the processor has them and in software elsewhere.
+    #[default]
+    Crc32c,
+}
+
+impl Checksum {
+    /// Returns the checksum of `parts`, taken as one run of bytes.
+    pub(crate) fn checksum(self, parts: &[&[u8]]) -> u32 {
+        match self {
+            Checksum::Crc32 => {
+                let mut digest = CRC_32.digest();
+                for part in parts {
+                    digest.update(part);
+                }
+                digest.finalize()
+            }
+            Checksum::Crc32c => parts
+                .iter()
+                .fold(0, |crc, part| crc32c::crc32c_append(crc, part)),
+        }
+    }
+
+    /// Returns the offset of the first record of a log using this checksum:
+    /// just past its format record, or zero if it has none.
+    pub(crate) fn first_record(self) -> u64 {
+        match self.name() {
+            Some(name) => record_length(FORMAT_KEY, name, &[]),
+            None => 0,
+        }
+    }
+
+    /// Returns the name the format record stores for this checksum, or `None`
+    /// for CRC-32, which has no format record.
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Checksum::Crc32 => None,
+            Checksum::Crc32c => Some("crc32c"),
+        }
+    }
+}
+
+/// Writes the format record that starts a new log whose records use
+/// `checksum`, if it needs one.  The format record itself is always
+/// checksummed with CRC-32, so it can be read before the format is known.
+pub(crate) fn write_format<W: Write>(out: &mut W, checksum: Checksum) -> Result<()> {
+    match checksum.name() {
+        Some(name) => ActionKV::write_record(
+            out,
+            Checksum::Crc32,
+            FORMAT_KEY,
+            name,
+            &[],
+            FLAG_CHECKPOINT,
+            now(),
+            0,
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Returns the checksum used by the log in `reader`, from the format record
+/// at its start.  A log without one, including an empty log, uses CRC-32.
+/// The reader is left at the first record after the format record.
+pub(crate) fn read_format<R: Read + Seek>(
+    reader: &mut R,
+    max_record_size: u64,
+) -> Result<Checksum> {
+    reader.seek(SeekFrom::Start(0))?;
+    let checksum = match ActionKV::process_record(reader, max_record_size, Some(Checksum::Crc32)) {
+        Ok(akv) if akv.flags & FLAG_CHECKPOINT != 0 && akv.key == FORMAT_KEY => {
+            match akv.value.as_str() {
+                "crc32c" => Checksum::Crc32c,
+                format => {
+                    let error_message = format!("unsupported log format: {format}");
+                    let error = Error::new(ErrorKind::Unsupported, error_message);
+                    return Err(error);
+                }
+            }
+        }
+        _ => Checksum::Crc32,
+    };
+    reader.seek(SeekFrom::Start(checksum.first_record()))?;
+    Ok(checksum)
+}
+
+impl ActionKV {
+    /// Starts an empty log with the checksum chosen by
+    /// [`Options::checksum`](crate::Options::checksum), writing its format
+    /// record.
+    pub(crate) fn start_log(&mut self) -> Result<()> {
+        self.checksum = self.options.checksum;
+        let mut record = Vec::new();
+        write_format(&mut record, self.checksum)?;
+        if !record.is_empty() {
+            self.append_records(&record)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,621 @@
+use crate::index::Index;
+use crate::storage::Storage;
+use crate::{
+    blob, checkpoint, checksum, now, partial, record_length, sys, ActionKV, AutoCompact,
+    KeyValuePair, FLAG_BLOB, FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE,
+};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How much space a compaction would reclaim, as reported by
+/// [`ActionKV::compaction_report`].
+#[derive(Debug, Default)]
+pub struct CompactionReport {
+    /// Number of records in the log.
+    pub total_records: u64,
+    /// Size of the log in bytes.
+    pub total_bytes: u64,
+    /// Number of superseded records compaction would discard.
+    pub reclaimable_records: u64,
+    /// Bytes of the log taken up by superseded records.
+    pub reclaimable_bytes: u64,
+    /// Number of blob files no key references anymore.
+    pub reclaimable_blob_files: u64,
+    /// Bytes taken up by unreferenced blob files.
+    pub reclaimable_blob_bytes: u64,
+    /// Superseded records grouped by key prefix.
+    pub prefixes: BTreeMap<String, PrefixReport>,
+}
+
+/// Space a compaction would reclaim from the keys sharing one prefix.
+#[derive(Debug, Default)]
+pub struct PrefixReport {
+    /// Number of superseded records with this prefix.
+    pub reclaimable_records: u64,
+    /// Bytes of the log taken up by superseded records with this prefix.
+    pub reclaimable_bytes: u64,
+}
+
+/// How far a compaction has come, passed to the callback of
+/// [`ActionKV::compact_with_progress`] after each record is copied.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompactionProgress {
+    /// Records copied to the compacted log so far.
+    pub records: u64,
+    /// Number of records the compacted log will hold.
+    pub total_records: u64,
+    /// Bytes copied to the compacted log so far.
+    pub bytes: u64,
+}
+
+/// Key of the record at the start of a compacted log carrying the highest
+/// sequence number issued before compaction.  The records that held the
+/// highest numbers, such as tombstones and superseded values, may be
+/// discarded, and sequence numbers are recovered from the records in the log,
+/// so without it numbering would go backwards on the next open.
+pub(crate) const SEQUENCE_KEY: &str = "kvstore-sequence";
+
+/// Called with the progress of a compaction; returning `Break` cancels it.
+pub(crate) type ProgressFn<'a> = &'a mut dyn FnMut(&CompactionProgress) -> ControlFlow<()>;
+
+/// A step of a compaction that replaces the log file, at which
+/// [`fail_compaction_at`] stops it to test recovery from a crash there.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompactionStep {
+    /// The temporary file is written but not synced.
+    Written,
+    /// The temporary file is synced but not renamed over the log.
+    Synced,
+    /// The log is replaced but unreferenced blob files are not removed.
+    Renamed,
+}
+
+thread_local! {
+    /// The step compactions on this thread stop at, if any.
+    static FAIL_AT: Cell<Option<CompactionStep>> = const { Cell::new(None) };
+}
+
+/// Makes compactions run on this thread fail at `step`, leaving the files as
+/// they would be had the process died there, until called with `None`.  For
+/// crash tests only.
+#[doc(hidden)]
+pub fn fail_compaction_at(step: Option<CompactionStep>) {
+    FAIL_AT.with(|fail_at| fail_at.set(step));
+}
+
+/// Fails with an error of kind `Other` if compactions on this thread are to
+/// stop at `step`.
+fn failpoint(step: CompactionStep) -> Result<()> {
+    if FAIL_AT.with(|fail_at| fail_at.get()) == Some(step) {
+        let error_message = format!("compaction stopped at {step:?}");
+        return Err(Error::other(error_message));
+    }
+    Ok(())
+}
+
+/// Returns the temporary file a compaction of the database at `path` is
+/// written to before it replaces the database.  For a database named
+/// `kvstore.db` this is `kvstore.db.compact`.
+pub(crate) fn compaction_path(path: &Path) -> PathBuf {
+    let mut temp = OsString::from(path.as_os_str());
+    temp.push(".compact");
+    PathBuf::from(temp)
+}
+
+/// Removes the temporary file left behind by a compaction that was
+/// interrupted before it replaced the database.  The database itself is only
+/// ever replaced by an atomic rename, so a leftover temporary file never holds
+/// data that is missing from the database.
+pub(crate) fn remove_leftover(path: &Path) -> Result<()> {
+    let temp = compaction_path(path);
+    if temp.exists() {
+        std::fs::remove_file(&temp)?;
+    }
+    Ok(())
+}
+
+impl ActionKV {
+    /// Writes a compacted copy of the database to a new file at `path`.  Only
+    /// the current record of each key is copied, along with any blob files
+    /// those records reference.  An error of kind `AlreadyExists` is returned
+    /// if `path` already exists.
+    pub fn copy_to(&self, path: &Path) -> Result<()> {
+        self.check_fully_indexed("copying")?;
+        let file = sys::with_mode(&mut std::fs::OpenOptions::new(), self.options.file_mode)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let blob_target = blob::blob_dir(path);
+        let file =
+            self.write_live_records(file, Some(&blob_target), &mut |_| ControlFlow::Continue(()))?;
+        file.sync_all()?;
+
+        if self.options.drop_page_cache {
+            self.storage.drop_page_cache()?;
+            sys::drop_page_cache(&file)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the database so that it only holds the current record of each
+    /// key, and removes blob files no longer referenced by any key.
+    ///
+    /// The compacted database is written to a temporary file which is synced
+    /// to disk and then atomically renamed over the database, after which the
+    /// directory itself is synced.  If the process dies at any point the
+    /// database is left either untouched or fully compacted; a leftover
+    /// temporary file is removed the next time the database is opened while
+    /// no process sharing it holds its lock.
+    ///
+    /// A database kept in memory is compacted by replacing its buffer, and one
+    /// kept in a stream by rewriting the stream in place.
+    pub fn compact(&mut self) -> Result<()> {
+        self.run_compaction(None, &mut |_| ControlFlow::Continue(()))
+    }
+
+    /// Compacts the database like [`compact`](ActionKV::compact), calling
+    /// `progress` after each record is copied.  If `progress` returns
+    /// `ControlFlow::Break`, the compaction is abandoned, leaving the database
+    /// untouched, and an error of kind `Interrupted` is returned.  The
+    /// callback can check a flag set by another thread to let an operator
+    /// cancel a compaction that is hurting latency.
+    pub fn compact_with_progress<F>(&mut self, mut progress: F) -> Result<()>
+    where
+        F: FnMut(&CompactionProgress) -> ControlFlow<()>,
+    {
+        self.run_compaction(None, &mut progress)
+    }
+
+    /// Runs a compaction, throttled to `max_bytes_per_sec` and reporting to
+    /// `progress`.
+    pub(crate) fn run_compaction(
+        &mut self,
+        max_bytes_per_sec: Option<u64>,
+        progress: ProgressFn<'_>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.check_fully_indexed("compaction")?;
+        // Processes sharing the file must not append to the log being
+        // replaced.
+        self.locked(|akv| akv.rewrite_log(max_bytes_per_sec, progress))
+    }
+
+    /// Replaces the log with one holding only the live records, for
+    /// `run_compaction`.
+    fn rewrite_log(
+        &mut self,
+        max_bytes_per_sec: Option<u64>,
+        progress: ProgressFn<'_>,
+    ) -> Result<()> {
+        self.flush()?;
+
+        match self.storage {
+            Storage::File(_) => {}
+            Storage::Memory(_) => {
+                let buffer = self.write_live_records(Vec::new(), None, progress)?;
+                self.storage = Storage::Memory(buffer);
+                return self.reload();
+            }
+            Storage::Stream(_) => {
+                let buffer = self.write_live_records(Vec::new(), None, progress)?;
+                self.storage.write_at(0, &buffer)?;
+                self.storage.set_len(buffer.len() as u64)?;
+                self.storage.sync_data()?;
+                return self.reload();
+            }
+        }
+
+        let file = self.write_compacted_log(max_bytes_per_sec, progress)?;
+        self.replace_log(file)
+    }
+
+    /// Writes the live records to the temporary file a compaction replaces
+    /// the log with, throttled to `max_bytes_per_sec` and reporting to
+    /// `progress`, and returns the file.  The file is removed if writing
+    /// fails.
+    pub(crate) fn write_compacted_log(
+        &self,
+        max_bytes_per_sec: Option<u64>,
+        progress: ProgressFn<'_>,
+    ) -> Result<File> {
+        let temp = compaction_path(&self.path);
+        let file = sys::with_mode(&mut std::fs::OpenOptions::new(), self.options.file_mode)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        let file = Throttled::new(file, max_bytes_per_sec);
+        match self.write_live_records(file, None, progress) {
+            Ok(file) => {
+                failpoint(CompactionStep::Written)?;
+                Ok(file.into_inner())
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    /// Completes a compaction run by a [`SharedKV`](crate::SharedKV) while
+    /// it kept taking writes.  `snapshot` is a read-only handle on the log
+    /// as it was when the compaction started, and `file` the compacted log
+    /// it wrote.  The records appended since are copied to the end of `file`,
+    /// which then replaces the log.
+    pub(crate) fn finish_online_compaction(
+        &mut self,
+        snapshot: &ActionKV,
+        file: File,
+    ) -> Result<()> {
+        let copied = self.flush().and_then(|()| {
+            let replaced = !sys::is_same_file(snapshot.storage.file("compaction")?, &self.path)?;
+            if replaced || snapshot.end > self.end {
+                let error = Error::new(ErrorKind::Interrupted, "log replaced during compaction");
+                return Err(error);
+            }
+            self.copy_records_since(snapshot.end, &file)
+        });
+        if let Err(e) = copied {
+            let _ = std::fs::remove_file(compaction_path(&self.path));
+            return Err(e);
+        }
+        self.replace_log(file)
+    }
+
+    /// Appends every record written from `start` on to `out`, for
+    /// `finish_online_compaction`.  Deltas are written in full, since the
+    /// records they refer to move, and checkpoints are left out.
+    fn copy_records_since(&self, start: u64, out: &File) -> Result<()> {
+        let max_record_size = self.options.max_record_size;
+        let mut file = self.storage.reader();
+        let mut position = file.seek(SeekFrom::Start(start))?;
+        let mut out = BufWriter::new(out);
+        while position < self.end {
+            let akv = ActionKV::process_record(&mut file, max_record_size, Some(self.checksum))?;
+            position = file.stream_position()?;
+            // Transactions start with a checkpoint record that has a key.
+            if akv.flags & FLAG_CHECKPOINT != 0 && akv.key.is_empty() {
+                continue;
+            }
+
+            let akv = self.undelta(akv)?;
+            ActionKV::write_record(
+                &mut out,
+                self.checksum,
+                &akv.key,
+                &akv.value,
+                &akv.user_meta,
+                akv.flags,
+                akv.timestamp,
+                akv.sequence,
+            )?;
+        }
+        out.flush()
+    }
+
+    /// Replaces the log with the compacted log `file`, written to the
+    /// temporary compaction path, and indexes it.
+    fn replace_log(&mut self, file: File) -> Result<()> {
+        file.sync_all()?;
+        failpoint(CompactionStep::Synced)?;
+
+        if self.options.drop_page_cache {
+            self.storage.drop_page_cache()?;
+            sys::drop_page_cache(&file)?;
+        }
+        drop(file);
+
+        std::fs::rename(compaction_path(&self.path), &self.path)?;
+        sync_parent_dir(&self.path)?;
+        failpoint(CompactionStep::Renamed)?;
+        // The saved index and summary describe the old log.
+        checkpoint::remove_index(&self.path)?;
+        partial::remove_summary(&self.path)?;
+
+        self.storage = Storage::File(crate::open_log(&self.path, self.options.file_mode)?);
+        self.reload()?;
+
+        self.remove_unreferenced_blobs()
+    }
+
+    /// Returns `true` if `policy` calls for compacting the log now.
+    pub(crate) fn should_compact(&self, policy: &AutoCompact) -> bool {
+        if self.end < policy.min_file_size || self.end == 0 {
+            return false;
+        }
+
+        let dead = self.end.saturating_sub(self.database.live_length());
+        dead as f64 / self.end as f64 >= policy.min_dead_ratio
+    }
+
+    /// Rebuilds the index from the compacted log.
+    pub(crate) fn reload(&mut self) -> Result<()> {
+        self.database = Index::default();
+        self.blob_refs = blob::BlobRefs::default();
+        self.leases = HashMap::new();
+        self.expiries = HashMap::new();
+        self.last_checkpoint = None;
+        self.passphrase = None;
+        self.generation += 1;
+        self.load()
+    }
+
+    /// Walks the log and reports how many records and bytes a call to
+    /// [`compact`](ActionKV::compact) would reclaim, without changing
+    /// anything.  Superseded records are also grouped by key prefix: the part
+    /// of the key before the first `delimiter`, or the empty string for keys
+    /// without one.
+    pub fn compaction_report(&self, delimiter: char) -> Result<CompactionReport> {
+        let mut report = CompactionReport::default();
+
+        self.scan_log(|position, size, akv| {
+            report.total_records += 1;
+            report.total_bytes += size;
+
+            if !self.is_live_record(position, &akv) {
+                report.reclaimable_records += 1;
+                report.reclaimable_bytes += size;
+
+                let prefix = match akv.key.split_once(delimiter) {
+                    Some((prefix, _)) => prefix.to_string(),
+                    None => String::new(),
+                };
+                let prefix_report = report.prefixes.entry(prefix).or_default();
+                prefix_report.reclaimable_records += 1;
+                prefix_report.reclaimable_bytes += size;
+            }
+
+            Ok(())
+        })?;
+
+        // Only a database kept in a file has blob files.
+        let dir = blob::blob_dir(&self.path);
+        let has_file = matches!(self.storage, Storage::File(_));
+        if has_file && dir.exists() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let referenced = match entry.file_name().to_str() {
+                    Some(name) => self.blob_refs.is_referenced(name),
+                    None => false,
+                };
+                if !referenced {
+                    report.reclaimable_blob_files += 1;
+                    report.reclaimable_blob_bytes += entry.metadata()?.len();
+                }
+            }
+        }
+
+        if self.options.drop_page_cache {
+            self.storage.drop_page_cache()?;
+        }
+
+        Ok(report)
+    }
+
+    /// The compaction pipeline: writes the current record of every key, in key
+    /// order, followed by the records of leases still held, to `out`.  Record
+    /// flags, timestamps and sequence numbers are preserved, and a record
+    /// keyed [`SEQUENCE_KEY`] comes first to carry the highest sequence
+    /// number issued.  When
+    /// `blob_target` is given, the blobs referenced by the records are copied
+    /// into that directory.  Stops with an error of kind `Interrupted` if
+    /// `progress` returns `Break`.
+    fn write_live_records<W: Write>(
+        &self,
+        out: W,
+        blob_target: Option<&Path>,
+        progress: ProgressFn<'_>,
+    ) -> Result<W> {
+        let now = now();
+        let mut lease_keys: Vec<&String> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.is_held(now))
+            .map(|(key, _)| key)
+            .collect();
+        lease_keys.sort();
+
+        // Expiry records follow every value, since a value record read after
+        // its key's expiry record clears the expiry.
+        let mut expiry_keys: Vec<&String> = self
+            .expiries
+            .keys()
+            .filter(|key| self.database.contains_key(key))
+            .collect();
+        expiry_keys.sort();
+
+        let passphrase = self
+            .passphrase
+            .as_ref()
+            .map(|passphrase| passphrase.position);
+        let total_records = self.database.len() + expiry_keys.len() + lease_keys.len();
+        let mut status = CompactionProgress {
+            total_records: (total_records + passphrase.iter().count()) as u64,
+            ..CompactionProgress::default()
+        };
+
+        let positions = self
+            .database
+            .values()
+            .map(|entry| entry.position)
+            .chain(
+                expiry_keys
+                    .into_iter()
+                    .map(|key| self.expiries[key].position),
+            )
+            .chain(lease_keys.into_iter().map(|key| self.leases[key].position))
+            .chain(passphrase);
+
+        let source_dir = blob::blob_dir(&self.path);
+        let mut copied_blobs = HashSet::new();
+
+        let mut out = BufWriter::new(out);
+        checksum::write_format(&mut out, self.checksum)?;
+        let sequence = self.last_sequence();
+        if sequence > 0 {
+            ActionKV::write_record(
+                &mut out,
+                self.checksum,
+                SEQUENCE_KEY,
+                "",
+                &[],
+                FLAG_CHECKPOINT,
+                now,
+                sequence,
+            )?;
+        }
+        for position in positions {
+            let akv = self.undelta(self.get_record_at_position(position)?)?;
+
+            if let Some(target_dir) = blob_target {
+                if akv.flags & FLAG_BLOB != 0 && copied_blobs.insert(akv.value.clone()) {
+                    let value = blob::read_blob(&source_dir, &akv.value)?;
+                    blob::write_blob(target_dir, &akv.value, &value, self.options.file_mode)?;
+                }
+            }
+
+            ActionKV::write_record(
+                &mut out,
+                self.checksum,
+                &akv.key,
+                &akv.value,
+                &akv.user_meta,
+                akv.flags,
+                akv.timestamp,
+                akv.sequence,
+            )?;
+
+            status.records += 1;
+            status.bytes += record_length(&akv.key, &akv.value, &akv.user_meta);
+            if progress(&status).is_break() {
+                let error = Error::new(ErrorKind::Interrupted, "compaction cancelled");
+                return Err(error);
+            }
+        }
+
+        out.into_inner().map_err(|e| e.into_error())
+    }
+
+    /// Returns `true` if `akv`, read at `position`, would survive compaction:
+    /// it is the current record of its key or grants a lease still held.  The
+    /// record carrying the highest sequence number counts as live, since
+    /// compaction writes it again.
+    fn is_live_record(&self, position: u64, akv: &KeyValuePair) -> bool {
+        if akv.flags & FLAG_CHECKPOINT != 0 {
+            return akv.key == SEQUENCE_KEY;
+        }
+        if akv.flags & FLAG_PASSPHRASE != 0 {
+            return match &self.passphrase {
+                Some(passphrase) => passphrase.position == position,
+                None => false,
+            };
+        }
+        if akv.flags & FLAG_EXPIRY != 0 {
+            return match self.expiries.get(&akv.key) {
+                Some(expiry) => expiry.position == position && self.database.contains_key(&akv.key),
+                None => false,
+            };
+        }
+        if akv.flags & FLAG_LEASE != 0 {
+            return match self.leases.get(&akv.key) {
+                Some(lease) => lease.position == position && lease.is_held(now()),
+                None => false,
+            };
+        }
+
+        match self.database.get(&akv.key) {
+            Some(entry) => entry.position == position,
+            None => false,
+        }
+    }
+
+    /// Deletes every file in the blob directory that no key references.
+    fn remove_unreferenced_blobs(&self) -> Result<()> {
+        let dir = blob::blob_dir(&self.path);
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let referenced = match name.to_str() {
+                Some(name) => self.blob_refs.is_referenced(name),
+                None => false,
+            };
+            if !referenced {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A writer that sleeps as needed to stay under a byte rate, used to keep a
+/// background compaction from saturating the disk.
+struct Throttled<W> {
+    inner: W,
+    max_bytes_per_sec: Option<u64>,
+    started: Instant,
+    written: u64,
+}
+
+impl<W: Write> Throttled<W> {
+    fn new(inner: W, max_bytes_per_sec: Option<u64>) -> Throttled<W> {
+        Throttled {
+            inner,
+            max_bytes_per_sec,
+            started: Instant::now(),
+            written: 0,
+        }
+    }
+
+    fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+
+        if let Some(rate) = self.max_bytes_per_sec.filter(|rate| *rate > 0) {
+            let due = Duration::from_secs_f64(self.written as f64 / rate as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                std::thread::sleep(due - elapsed);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Flushes the directory entry of `path` to disk so that a rename into that
+/// directory survives a crash.  Directories cannot be opened as files on
+/// Windows, where the rename is already durable once it returns.
+#[cfg(unix)]
+pub(crate) fn sync_parent_dir(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
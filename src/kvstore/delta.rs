@@ -0,0 +1,179 @@
+use crate::{ActionKV, KeyValuePair, FLAG_BLOB, FLAG_DELTA};
+use std::io::{Error, ErrorKind, Result};
+
+/// Longest chain of deltas a value is rebuilt from.  Once the current version
+/// of a key is this many deltas away from a full value, the next update is
+/// written in full again, bounding the records a read has to visit.
+const MAX_CHAIN: usize = 16;
+
+/// An update stored by [`Options::delta_threshold`] as the difference from
+/// the previous version of the key.  The new value is the first `prefix`
+/// bytes of the base value, then `middle`, then its last `suffix` bytes.
+///
+/// The record holds the fields separated by colons, `middle` last, so it
+/// stays valid UTF-8 like every other value.
+///
+/// [`Options::delta_threshold`]: crate::Options::delta_threshold
+struct Delta<'a> {
+    /// Offset of the record holding the previous version.
+    base: u64,
+    /// Length of the new value in bytes.
+    size: u64,
+    prefix: usize,
+    suffix: usize,
+    middle: &'a str,
+}
+
+impl<'a> Delta<'a> {
+    /// Parses the value of a delta record.
+    fn parse(value: &'a str) -> Result<Delta<'a>> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "invalid delta record");
+        let mut fields = value.splitn(5, ':');
+        let mut number = || -> Result<u64> {
+            let field = fields.next().ok_or_else(invalid)?;
+            field.parse().map_err(|_| invalid())
+        };
+        let base = number()?;
+        let size = number()?;
+        let prefix = number()? as usize;
+        let suffix = number()? as usize;
+        let middle = fields.next().ok_or_else(invalid)?;
+        Ok(Delta {
+            base,
+            size,
+            prefix,
+            suffix,
+            middle,
+        })
+    }
+
+    /// Rebuilds the new value from the `base` value.
+    fn apply(&self, base: &str) -> Result<String> {
+        let fits = self.prefix + self.suffix <= base.len()
+            && base.is_char_boundary(self.prefix)
+            && base.is_char_boundary(base.len() - self.suffix);
+        if !fits {
+            let error = Error::new(ErrorKind::InvalidData, "delta does not fit its base");
+            return Err(error);
+        }
+
+        let mut value = String::with_capacity(self.size as usize);
+        value.push_str(&base[..self.prefix]);
+        value.push_str(self.middle);
+        value.push_str(&base[base.len() - self.suffix..]);
+        Ok(value)
+    }
+}
+
+/// Returns the length of the value a delta record rebuilds, as kept in the
+/// index.
+pub(crate) fn value_size(value: &str) -> Result<u64> {
+    Ok(Delta::parse(value)?.size)
+}
+
+impl ActionKV {
+    /// Returns the delta to store in place of `value` for `key`, if
+    /// [`Options::delta_threshold`] is set, the value is at least that long,
+    /// and the delta from the current version is at most half its size.
+    /// Anything that keeps the current version from being read, such as a
+    /// damaged record awaiting read repair, writes the value in full instead.
+    ///
+    /// [`Options::delta_threshold`]: crate::Options::delta_threshold
+    pub(crate) fn delta_for(&self, key: &str, value: &str) -> Option<String> {
+        let threshold = self.options.delta_threshold?;
+        if value.len() < threshold as usize {
+            return None;
+        }
+
+        let position = self.database.get(key)?.position;
+        let base = self.get_record_at_position(position).ok()?;
+        if base.flags & FLAG_BLOB != 0 {
+            return None;
+        }
+        let (base_value, chain) = self.rebuild(base).ok()?;
+        if chain >= MAX_CHAIN {
+            return None;
+        }
+
+        let prefix = common_prefix(&base_value, value);
+        let suffix = common_suffix(&base_value[prefix..], &value[prefix..]);
+        let middle = &value[prefix..value.len() - suffix];
+        let delta = format!("{position}:{}:{prefix}:{suffix}:{middle}", value.len());
+        match delta.len() * 2 <= value.len() {
+            true => Some(delta),
+            false => None,
+        }
+    }
+
+    /// Returns the record `akv` with a delta replaced by the full value it
+    /// rebuilds, for copying the record out of this log.  Other records are
+    /// returned unchanged.
+    pub(crate) fn undelta(&self, mut akv: KeyValuePair) -> Result<KeyValuePair> {
+        if akv.flags & FLAG_DELTA != 0 {
+            let delta = std::mem::take(&mut akv.value);
+            akv.value = self.rebuild_delta(delta)?.0;
+            akv.flags &= !FLAG_DELTA;
+        }
+        Ok(akv)
+    }
+
+    /// Returns the value of the record `akv`, rebuilding it if the record is
+    /// a delta, along with the number of deltas applied.
+    pub(crate) fn rebuild(&self, akv: KeyValuePair) -> Result<(String, usize)> {
+        match akv.flags & FLAG_DELTA {
+            0 => Ok((self.resolve_value(akv)?, 0)),
+            _ => self.rebuild_delta(akv.value),
+        }
+    }
+
+    /// Rebuilds the value of the delta record whose value is `delta`, by
+    /// following the chain of bases back to a full value and applying the
+    /// deltas to it in turn.
+    fn rebuild_delta(&self, delta: String) -> Result<(String, usize)> {
+        let mut deltas = vec![delta];
+        // Every base was written before the record pointing at it, which also
+        // rules out a damaged chain that loops.
+        let mut limit = u64::MAX;
+        let base = loop {
+            let base = Delta::parse(&deltas[deltas.len() - 1])?.base;
+            if base >= limit {
+                let error = Error::new(ErrorKind::InvalidData, "invalid delta chain");
+                return Err(error);
+            }
+            limit = base;
+
+            let record = self.get_record_at_position(base)?;
+            if record.flags & FLAG_DELTA == 0 {
+                break self.resolve_value(record)?;
+            }
+            deltas.push(record.value);
+        };
+
+        let mut value = base;
+        for delta in deltas.iter().rev() {
+            value = Delta::parse(delta)?.apply(&value)?;
+        }
+        Ok((value, deltas.len()))
+    }
+}
+
+/// Returns the length in bytes of the longest common prefix of `a` and `b`
+/// that ends on a character boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or(a.len().min(b.len()))
+}
+
+/// Returns the length in bytes of the longest common suffix of `a` and `b`
+/// that starts on a character boundary.
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum()
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// The error carried by [`ActionKV::update_if_version`](crate::ActionKV::update_if_version)
+/// when the key was modified after the caller read it.  It is returned inside
+/// an [`std::io::Error`] and can be recovered with
+/// `error.get_ref().and_then(|e| e.downcast_ref::<VersionMismatch>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionMismatch {
+    /// The key that was being updated.
+    pub key: String,
+    /// The sequence number the caller expected the key to have.
+    pub expected: u64,
+    /// The sequence number the key actually has, or zero if it does not exist.
+    pub actual: u64,
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version mismatch for key: {} expected={} actual={}",
+            self.key, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+/// The error carried when a key the operation needs does not exist, or has
+/// expired.  It is returned inside an [`std::io::Error`], of kind
+/// `InvalidData` except from
+/// [`update_strict`](crate::ActionKV::update_strict), and can be told apart
+/// from damaged records with
+/// `error.get_ref().and_then(|e| e.downcast_ref::<KeyNotFound>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyNotFound {
+    /// The key that was not found.
+    pub key: String,
+    /// The time the key was looked up as of, for reads of the past.
+    pub as_of: Option<u64>,
+}
+
+impl KeyNotFound {
+    /// Returns the error for `key` missing from the database, as an
+    /// [`std::io::Error`] of `kind`.
+    pub(crate) fn error(kind: ErrorKind, key: &str) -> Error {
+        let not_found = KeyNotFound {
+            key: key.to_string(),
+            as_of: None,
+        };
+        Error::new(kind, not_found)
+    }
+}
+
+impl fmt::Display for KeyNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key: {} not found in database", self.key)?;
+        match self.as_of {
+            Some(timestamp) => write!(f, " at {timestamp}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for KeyNotFound {}
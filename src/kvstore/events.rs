@@ -0,0 +1,262 @@
+use crate::{
+    ActionKV, KeyValuePair, FLAG_CHECKPOINT, FLAG_DELETE, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE,
+    FLAG_TOMBSTONE,
+};
+use std::io::{Error, ErrorKind, Result};
+
+/// What a change reported by [`ActionKV::events_since`] did to its key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventOp {
+    /// A value was written.
+    Set,
+    /// The key was deleted, or dropped from the index by a tombstone.
+    Delete,
+    /// The key was given an expiry.
+    Expire,
+    /// The expiry of the key was removed.
+    Persist,
+}
+
+impl EventOp {
+    /// Returns the name of the operation, such as `set`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventOp::Set => "set",
+            EventOp::Delete => "delete",
+            EventOp::Expire => "expire",
+            EventOp::Persist => "persist",
+        }
+    }
+}
+
+/// A change to a key, read from its record in the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// What the change did.
+    pub op: EventOp,
+    /// The key it changed.
+    pub key: String,
+    /// The sequence number of its record.
+    pub sequence: u64,
+    /// When it was written, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// Where [`ActionKV::events_after`] stopped reading the log of a database,
+/// so that following it reads only the records appended since.  A cursor
+/// belongs to the database it was returned by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventCursor {
+    /// The sequence number of the last change read.
+    sequence: u64,
+    /// The generation of the log and the offset reading stopped at, unknown
+    /// for a cursor made by [`EventCursor::new`].
+    position: Option<(u64, u64)>,
+}
+
+impl EventCursor {
+    /// Returns a cursor after the change with sequence number `sequence`,
+    /// such as the last one a previous run reported.
+    pub fn new(sequence: u64) -> EventCursor {
+        EventCursor {
+            sequence,
+            position: None,
+        }
+    }
+
+    /// Returns the sequence number of the last change read.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// A change to a key replayed by [`ActionKV::changes_since`], with the value
+/// it wrote.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeRecord {
+    /// What the change did.
+    pub op: EventOp,
+    /// The key it changed.
+    pub key: String,
+    /// The value written by a [`Set`](EventOp::Set), after read hooks.
+    pub value: Option<String>,
+    /// The metadata written with the value by
+    /// [`insert_with_meta`](ActionKV::insert_with_meta), or empty.
+    pub user_meta: Vec<u8>,
+    /// When the key expires after an [`Expire`](EventOp::Expire), in
+    /// milliseconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    /// The sequence number of its record.
+    pub sequence: u64,
+    /// When it was written, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// An iterator over the changes written after a sequence number, oldest
+/// first, returned by [`ActionKV::changes_since`].  The log is scanned for
+/// where the changes are when the iterator is created, and the value of each
+/// is read as the iterator reaches it.
+pub struct Changes<'a> {
+    akv: &'a ActionKV,
+    records: std::vec::IntoIter<(u64, u64)>,
+}
+
+impl ActionKV {
+    /// Returns the changes written after `sequence`, oldest first, as the
+    /// records in the log describe them.  Passing the sequence of the last
+    /// change returned, after a [`refresh`](ActionKV::refresh), follows a
+    /// database another process writes to.  Compaction keeps only the last
+    /// record of every key, so the changes it superseded are not reported,
+    /// nor are keys deleted before it.  Leases, checkpoints and passphrase
+    /// records are left out.
+    pub fn events_since(&self, sequence: u64) -> Result<Vec<Event>> {
+        // Compaction rewrites records in key order, so collect the newer
+        // records first and sort them in the order they were written.
+        let mut events = Vec::new();
+        self.scan_log(|_, _, akv| {
+            if akv.sequence > sequence {
+                events.extend(event(akv));
+            }
+            Ok(())
+        })?;
+        events.sort_unstable_by_key(|event| event.sequence);
+        Ok(events)
+    }
+
+    /// Returns a cursor past the last change in the log, to follow the changes
+    /// written from now on with [`events_after`](ActionKV::events_after).
+    pub fn event_cursor(&self) -> EventCursor {
+        EventCursor {
+            sequence: self.last_sequence(),
+            position: Some((self.generation, self.end)),
+        }
+    }
+
+    /// Returns the changes written after `cursor`, oldest first, like
+    /// [`events_since`](ActionKV::events_since), and the cursor to pass next.
+    /// Only the records appended since the cursor was returned are read,
+    /// unless compaction has replaced the log in the meantime, after which it
+    /// is scanned in full for the changes with higher sequence numbers.
+    pub fn events_after(&self, cursor: EventCursor) -> Result<(Vec<Event>, EventCursor)> {
+        let events = match cursor.position {
+            Some((generation, position)) if generation == self.generation => {
+                // Records are appended in the order they are written.
+                let mut events = Vec::new();
+                self.scan_log_from(position, |_, _, akv| {
+                    events.extend(event(akv));
+                    Ok(())
+                })?;
+                events
+            }
+            _ => self.events_since(cursor.sequence)?,
+        };
+        let next = EventCursor {
+            sequence: events
+                .last()
+                .map_or(cursor.sequence, |event| event.sequence),
+            position: Some((self.generation, self.end)),
+        };
+        Ok((events, next))
+    }
+
+    /// Returns an iterator replaying the changes written after `sequence`
+    /// like [`events_since`](ActionKV::events_since), along with the values
+    /// they wrote, to keep a search index or other downstream copy in sync.
+    /// Only committed writes are replayed: a transaction is left out until
+    /// all of it is in the log.  Store the [`sequence`](ChangeRecord::sequence)
+    /// of the last change applied and pass it to the next call to resume.
+    pub fn changes_since(&self, sequence: u64) -> Result<Changes<'_>> {
+        let mut records = Vec::new();
+        self.scan_log(|position, _, akv| {
+            if akv.sequence > sequence && op(&akv).is_some() {
+                records.push((akv.sequence, position));
+            }
+            Ok(())
+        })?;
+        records.sort_unstable();
+        let changes = Changes {
+            akv: self,
+            records: records.into_iter(),
+        };
+        Ok(changes)
+    }
+}
+
+impl Changes<'_> {
+    /// Reads the change recorded at `position`.
+    fn read(&self, position: u64) -> Result<ChangeRecord> {
+        let mut akv = self.akv.get_record_at_position(position)?;
+        let Some(op) = op(&akv) else {
+            let error_message = format!("record at offset {position} changes no key");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        };
+
+        let key = akv.key.clone();
+        let user_meta = std::mem::take(&mut akv.user_meta);
+        let (sequence, timestamp) = (akv.sequence, akv.timestamp);
+        let (value, expires_at) = match op {
+            EventOp::Set => {
+                let value = self.akv.resolve_value(akv)?;
+                (Some(self.akv.run_read_hooks(&key, value)?), None)
+            }
+            EventOp::Expire => (None, akv.value.parse().ok()),
+            EventOp::Delete | EventOp::Persist => (None, None),
+        };
+        let change = ChangeRecord {
+            op,
+            key,
+            value,
+            user_meta,
+            expires_at,
+            sequence,
+            timestamp,
+        };
+        Ok(change)
+    }
+}
+
+impl Iterator for Changes<'_> {
+    type Item = Result<ChangeRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, position) = self.records.next()?;
+        Some(self.read(position))
+    }
+}
+
+/// Returns the change recorded by `akv`, or `None` for a record that changes
+/// no key.
+fn event(akv: KeyValuePair) -> Option<Event> {
+    let event = Event {
+        op: op(&akv)?,
+        key: akv.key,
+        sequence: akv.sequence,
+        timestamp: akv.timestamp,
+    };
+    Some(event)
+}
+
+/// Returns what `akv` did to its key, or `None` for a record that changes no
+/// key, such as the one starting a transaction.
+fn op(akv: &KeyValuePair) -> Option<EventOp> {
+    if akv.flags & (FLAG_LEASE | FLAG_CHECKPOINT | FLAG_PASSPHRASE) != 0 {
+        return None;
+    }
+
+    let op = if akv.flags & FLAG_TOMBSTONE != 0 {
+        EventOp::Delete
+    } else if akv.flags & FLAG_EXPIRY != 0 {
+        // A zero expiry time clears the expiry.
+        match akv.value.as_str() {
+            "0" => EventOp::Persist,
+            _ => EventOp::Expire,
+        }
+    } else if akv.flags & FLAG_DELETE != 0 && akv.user_meta.is_empty() {
+        // `delete` marks its record with empty metadata.
+        EventOp::Delete
+    } else {
+        EventOp::Set
+    };
+    Some(op)
+}
@@ -0,0 +1,199 @@
+use crate::{now, ActionKV, KeyNotFound, FLAG_EXPIRY};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+/// When a key expires, taken from the newest expiry record of the key.
+pub(crate) struct Expiry {
+    /// When the key expires, in milliseconds since the Unix epoch.
+    pub(crate) expires_at: u64,
+    /// Offset of the record that set the expiry.
+    pub(crate) position: u64,
+}
+
+impl ActionKV {
+    /// Makes `key` expire `ttl` from now.  See
+    /// [`expire_at`](ActionKV::expire_at).
+    pub fn expire(&mut self, key: &str, ttl: Duration) -> Result<()> {
+        let expires_at = now().saturating_add(ttl.as_millis() as u64);
+        self.expire_at(key, expires_at)
+    }
+
+    /// Makes `key` expire at `timestamp`, in milliseconds since the Unix
+    /// epoch, replacing any expiry it had.  Once expired, the key reads as if
+    /// it did not exist.  The expiry is written as a small record of its own,
+    /// so the value is not rewritten, and writing a new value to the key
+    /// clears it.  An error is returned if the key does not exist.
+    pub fn expire_at(&mut self, key: &str, timestamp: u64) -> Result<()> {
+        self.locked(|akv| {
+            if !akv.contains_key(key) {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+                return Err(error);
+            }
+
+            // A zero expiry record clears the expiry.
+            akv.apply_repairs()?;
+            akv.write_expiry(key, timestamp.max(1))
+        })
+    }
+
+    /// Removes the expiry of `key`, so it is kept until deleted, and returns
+    /// whether it had one.  An error is returned if the key does not exist.
+    pub fn persist(&mut self, key: &str) -> Result<bool> {
+        self.locked(|akv| {
+            if !akv.contains_key(key) {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+                return Err(error);
+            }
+            if !akv.expiries.contains_key(key) {
+                return Ok(false);
+            }
+
+            akv.apply_repairs()?;
+            akv.write_expiry(key, 0)?;
+            Ok(true)
+        })
+    }
+
+    /// Rewrites the record of `key` with its current value and metadata and a
+    /// fresh timestamp, without the caller supplying the value.  With `ttl`
+    /// given the key also expires `ttl` from now, giving sliding expiration
+    /// to a session store that touches a session on every request; otherwise
+    /// any expiry the key had is kept.  The value is copied as stored, without
+    /// passing through the read and write hooks.  An error is returned if the
+    /// key does not exist.
+    pub fn touch(&mut self, key: &str, ttl: Option<Duration>) -> Result<()> {
+        self.locked(|akv| {
+            let position = match akv.database.get(key) {
+                Some(entry) if !akv.is_expired(key) => entry.position,
+                _ => {
+                    let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+                    return Err(error);
+                }
+            };
+            let record = akv.read_record_at(position, !akv.options.skip_read_checksums)?;
+            let user_meta = record.user_meta.clone();
+            let value = akv.resolve_value(record)?;
+
+            let timestamp = now();
+            let expires_at = match ttl {
+                Some(ttl) => Some(timestamp.saturating_add(ttl.as_millis() as u64)),
+                None => akv.expiries.get(key).map(|expiry| expiry.expires_at),
+            };
+            akv.apply_repairs()?;
+            // Writing the value clears the expiry, so it is written again after.
+            akv.write_value(key.to_string(), value, &user_meta, timestamp)?;
+            match expires_at {
+                Some(expires_at) => akv.write_expiry(key, expires_at),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Returns how long `key` has left before it expires, or `None` if it
+    /// does not expire.  An error is returned if the key does not exist.
+    pub fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        if !self.contains_key(key) {
+            let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+            return Err(error);
+        }
+
+        let ttl = self.expiries.get(key).map(|expiry| {
+            let remaining = expiry.expires_at.saturating_sub(now());
+            Duration::from_millis(remaining)
+        });
+        Ok(ttl)
+    }
+
+    /// Returns every key starting with `prefix` that has an expiry, with when
+    /// it expires in milliseconds since the Unix epoch, soonest first and then
+    /// in key order.  Keys that have already expired are left out.  Expiries
+    /// are kept in memory, so nothing is read from the log.
+    pub fn expiring(&self, prefix: &str) -> Vec<(String, u64)> {
+        let now = now();
+        let mut expiring: Vec<(String, u64)> = self
+            .expiries
+            .iter()
+            .filter(|(key, expiry)| key.starts_with(prefix) && expiry.expires_at > now)
+            .filter(|(key, _)| self.database.contains_key(key))
+            .map(|(key, expiry)| (key.clone(), expiry.expires_at))
+            .collect();
+        expiring.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        expiring
+    }
+
+    /// Removes every expired key from the database and returns how many were
+    /// removed.  A tombstone is written for each key, all in a single append.
+    /// Unlike [`delete`](ActionKV::delete), which leaves the key with an empty
+    /// value, the key is dropped from the index, and the next compaction
+    /// reclaims its records.
+    pub fn remove_expired(&mut self) -> Result<u64> {
+        self.locked(|akv| {
+            let now = now();
+            let mut expired: Vec<String> = akv
+                .expiries
+                .iter()
+                .filter(|(_, expiry)| expiry.expires_at <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            if expired.is_empty() {
+                return Ok(0);
+            }
+            expired.sort();
+
+            akv.apply_repairs()?;
+            akv.write_tombstones(&expired)?;
+            Ok(expired.len() as u64)
+        })
+    }
+
+    /// Returns `true` if `key` has an expiry that has passed.
+    pub(crate) fn is_expired(&self, key: &str) -> bool {
+        self.is_expired_at(key, now())
+    }
+
+    /// Returns `true` if `key` has an expiry that has passed at time `at`.
+    pub(crate) fn is_expired_at(&self, key: &str, at: u64) -> bool {
+        match self.expiries.get(key) {
+            Some(expiry) => expiry.expires_at <= at,
+            None => false,
+        }
+    }
+
+    /// Appends an expiry record setting the expiry of `key` to `expires_at`,
+    /// or clearing it if `expires_at` is zero.
+    pub(crate) fn write_expiry(&mut self, key: &str, expires_at: u64) -> Result<()> {
+        let value = expires_at.to_string();
+        let position = self.insert_in_database(key, &value, &[], FLAG_EXPIRY, now())?;
+        load(&mut self.expiries, key.to_string(), &value, position)
+    }
+}
+
+/// Applies an expiry record read from the log at `position` to `expiries`.  A
+/// record whose value is zero clears the expiry.
+pub(crate) fn load(
+    expiries: &mut HashMap<String, Expiry>,
+    key: String,
+    value: &str,
+    position: u64,
+) -> Result<()> {
+    let expires_at = match value.parse::<u64>() {
+        Ok(expires_at) => expires_at,
+        Err(_) => {
+            let error_message = format!("invalid expiry record for key: {key}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+    };
+
+    if expires_at == 0 {
+        expiries.remove(&key);
+    } else {
+        let expiry = Expiry {
+            expires_at,
+            position,
+        };
+        expiries.insert(key, expiry);
+    }
+    Ok(())
+}
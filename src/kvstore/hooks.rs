@@ -0,0 +1,86 @@
+use crate::ActionKV;
+use std::io::Result;
+
+/// A hook called with the key and value of every write, returning the value
+/// to store.
+type WriteHook = Box<dyn Fn(&str, String) -> Result<String> + Send>;
+
+/// A hook called with the key and value of every read, returning the value to
+/// hand to the caller.
+type ReadHook = Box<dyn Fn(&str, String) -> Result<String> + Send>;
+
+/// A hook called with the key of every delete.
+type DeleteHook = Box<dyn Fn(&str) -> Result<()> + Send>;
+
+/// The hooks registered on a database, each kind run in the order registered.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    write: Vec<WriteHook>,
+    read: Vec<ReadHook>,
+    delete: Vec<DeleteHook>,
+}
+
+impl ActionKV {
+    /// Registers `hook` to be called before every value is written by
+    /// [`insert`](ActionKV::insert) and the methods built on it, including
+    /// [`insert_many`](ActionKV::insert_many).  The hook receives the key and
+    /// the value, and returns the value to store in its place, so it can
+    /// validate or transform values, for example to enforce a key naming
+    /// convention or to encrypt the values under a prefix.  Returning an error
+    /// vetoes the write, which is then reported to the caller with nothing
+    /// written.  Several hooks are run in the order they were registered, each
+    /// receiving the value returned by the one before it.
+    pub fn on_write<F>(&mut self, hook: F)
+    where
+        F: Fn(&str, String) -> Result<String> + Send + 'static,
+    {
+        self.hooks.write.push(Box::new(hook));
+    }
+
+    /// Registers `hook` to be called after every value is read by
+    /// [`get`](ActionKV::get), [`get_with_meta`](ActionKV::get_with_meta) and
+    /// [`ReadView::get`](crate::ReadView::get).  The hook receives the key and
+    /// the stored value, and returns the value to hand to the caller, undoing
+    /// whatever a write hook did to it.  Returning an error fails the read.
+    pub fn on_read<F>(&mut self, hook: F)
+    where
+        F: Fn(&str, String) -> Result<String> + Send + 'static,
+    {
+        self.hooks.read.push(Box::new(hook));
+    }
+
+    /// Registers `hook` to be called before every [`delete`](ActionKV::delete).
+    /// Returning an error vetoes the delete.
+    pub fn on_delete<F>(&mut self, hook: F)
+    where
+        F: Fn(&str) -> Result<()> + Send + 'static,
+    {
+        self.hooks.delete.push(Box::new(hook));
+    }
+
+    /// Passes `value`, about to be written for `key`, through the write hooks.
+    pub(crate) fn run_write_hooks(&self, key: &str, value: String) -> Result<String> {
+        self.hooks
+            .write
+            .iter()
+            .try_fold(value, |value, hook| hook(key, value))
+    }
+
+    /// Returns `true` if any read hooks are registered.
+    pub(crate) fn has_read_hooks(&self) -> bool {
+        !self.hooks.read.is_empty()
+    }
+
+    /// Passes `value`, just read for `key`, through the read hooks.
+    pub(crate) fn run_read_hooks(&self, key: &str, value: String) -> Result<String> {
+        self.hooks
+            .read
+            .iter()
+            .try_fold(value, |value, hook| hook(key, value))
+    }
+
+    /// Runs the delete hooks for `key`.
+    pub(crate) fn run_delete_hooks(&self, key: &str) -> Result<()> {
+        self.hooks.delete.iter().try_for_each(|hook| hook(key))
+    }
+}
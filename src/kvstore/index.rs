@@ -0,0 +1,737 @@
+use crate::{memory, IndexEntry};
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap};
+use std::mem::size_of;
+use std::ops::Bound;
+use std::slice;
+
+/// Most keys a block holds.
+const BLOCK_KEYS: usize = 128;
+
+/// The index of every live key, in key order, with where its current record
+/// lives.
+///
+/// Rather than give every key a `String` of its own, keys are kept in blocks
+/// of up to [`BLOCK_KEYS`] neighbouring keys whose bytes share one buffer,
+/// saving the string header and allocation of every key.  Blocks are kept in
+/// a `BTreeMap` by their first key.  A block that fills up is split in half,
+/// or, when the key is added at its end, as happens when keys are written in
+/// order, a new block is started so that blocks written in order stay full.
+#[derive(Default)]
+pub(crate) struct Index {
+    blocks: BTreeMap<Box<str>, Block>,
+    len: usize,
+    /// The summed length of the records the entries point to.
+    live_length: u64,
+}
+
+/// Neighbouring keys of the index, sorted, with their entries.
+struct Block {
+    /// The bytes of every key in the block, including those of removed keys
+    /// until the block is repacked.
+    keys: String,
+    slots: Vec<Slot>,
+    /// The tag of every slot: the four bytes of its key following the prefix
+    /// `shared`, which a search compares before the key itself, sparing a
+    /// read of the key for every step.
+    tags: Vec<u32>,
+    /// Length of a prefix every key of the block starts with.
+    shared: usize,
+    /// Bytes of `keys` no longer used by any slot.
+    garbage: usize,
+}
+
+/// A key of a block, as the range of its bytes in the block's buffer, and
+/// its entry.
+struct Slot {
+    start: u32,
+    length: u32,
+    entry: IndexEntry,
+}
+
+/// Returns the four bytes of `key` following its first `shared` bytes, as a
+/// number ordered like the keys, padded with zeros past its end.
+fn tag(key: &[u8], shared: usize) -> u32 {
+    let mut tag = [0; 4];
+    let rest = key.get(shared..).unwrap_or_default();
+    let length = rest.len().min(4);
+    tag[..length].copy_from_slice(&rest[..length]);
+    u32::from_be_bytes(tag)
+}
+
+impl Block {
+    /// Creates an empty block.
+    fn new() -> Block {
+        Block {
+            keys: String::new(),
+            slots: Vec::with_capacity(BLOCK_KEYS),
+            tags: Vec::with_capacity(BLOCK_KEYS),
+            shared: 0,
+            garbage: 0,
+        }
+    }
+
+    /// Creates a block holding `slots` of the keys in `keys`, packed into a
+    /// buffer of their own.
+    fn packed<'a>(keys: &str, slots: impl Iterator<Item = &'a Slot>) -> Block {
+        let mut block = Block::new();
+        for slot in slots {
+            let key = &keys[slot.start as usize..(slot.start + slot.length) as usize];
+            block.push(key, slot.entry);
+        }
+        block
+    }
+
+    /// Returns the key of `slot`.
+    fn key(&self, slot: &Slot) -> &str {
+        &self.keys[slot.start as usize..(slot.start + slot.length) as usize]
+    }
+
+    /// Returns the first key of the block.
+    fn first_key(&self) -> &str {
+        self.key(&self.slots[0])
+    }
+
+    /// Finds `key` in the block, returning its slot if present, or where it
+    /// would go if not.
+    fn search(&self, key: &str) -> std::result::Result<usize, usize> {
+        let Some(first) = self.slots.first() else {
+            return Err(0);
+        };
+        let bytes = key.as_bytes();
+        let prefix = &self.key(first).as_bytes()[..self.shared];
+        match bytes[..self.shared.min(bytes.len())].cmp(prefix) {
+            Ordering::Equal if bytes.len() >= self.shared => {}
+            Ordering::Greater => return Err(self.slots.len()),
+            _ => return Err(0),
+        }
+
+        // Only the slots with the same tag need their keys compared.
+        let tag = tag(bytes, self.shared);
+        let start = self.tags.partition_point(|other| *other < tag);
+        let length = self.tags[start..].partition_point(|other| *other == tag);
+        self.slots[start..start + length]
+            .binary_search_by(|slot| self.key(slot).cmp(key))
+            .map(|index| start + index)
+            .map_err(|index| start + index)
+    }
+
+    /// Returns the number of slots holding keys that sort before `key`, and
+    /// also those equal to it if `equal`.
+    fn partition(&self, key: &str, equal: bool) -> usize {
+        match self.search(key) {
+            Ok(index) if equal => index + 1,
+            Ok(index) | Err(index) => index,
+        }
+    }
+
+    /// Appends the bytes of `key` to the buffer and returns its slot and tag.
+    /// The shared prefix is cut down to the part `key` starts with too, which
+    /// changes the tag of every slot.
+    fn slot(&mut self, key: &str, entry: IndexEntry) -> (Slot, u32) {
+        let shared = match self.slots.first() {
+            Some(first) => {
+                let first = self.key(first).as_bytes();
+                let common = first.iter().zip(key.as_bytes()).take_while(|(a, b)| a == b);
+                self.shared.min(common.count())
+            }
+            None => key.len(),
+        };
+        if shared != self.shared {
+            self.shared = shared;
+            let tags = self
+                .slots
+                .iter()
+                .map(|slot| tag(self.key(slot).as_bytes(), shared));
+            self.tags = tags.collect();
+        }
+
+        let start = self.keys.len() as u32;
+        self.keys.push_str(key);
+        let slot = Slot {
+            start,
+            length: key.len() as u32,
+            entry,
+        };
+        (slot, tag(key.as_bytes(), self.shared))
+    }
+
+    /// Adds `key`, which sorts after every key in the block, at its end.
+    fn push(&mut self, key: &str, entry: IndexEntry) {
+        let (slot, tag) = self.slot(key, entry);
+        self.slots.push(slot);
+        self.tags.push(tag);
+    }
+
+    /// Adds `key`, which the block does not hold, as slot `index`.
+    fn insert(&mut self, index: usize, key: &str, entry: IndexEntry) {
+        let (slot, tag) = self.slot(key, entry);
+        self.slots.insert(index, slot);
+        self.tags.insert(index, tag);
+    }
+
+    /// Removes the slot at `index` and returns its entry, repacking the
+    /// buffer once most of it is garbage.
+    fn remove(&mut self, index: usize) -> IndexEntry {
+        let slot = self.slots.remove(index);
+        self.tags.remove(index);
+        self.garbage += slot.length as usize;
+        if self.garbage > self.keys.len() / 2 {
+            let packed = Block::packed(&self.keys, self.slots.iter());
+            *self = packed;
+        }
+        slot.entry
+    }
+
+    /// Returns the bytes taken up by the block, its slots and its keys.
+    fn memory(&self) -> u64 {
+        let slots = self.slots.capacity() * size_of::<Slot>();
+        let tags = self.tags.capacity() * size_of::<u32>();
+        (size_of::<Block>() + slots + tags + self.keys.capacity()) as u64
+    }
+}
+
+impl Index {
+    /// Returns the number of keys in the index.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the summed length of the records of every key, the part of
+    /// the log compaction would keep.
+    pub(crate) fn live_length(&self) -> u64 {
+        self.live_length
+    }
+
+    /// Returns the block that holds `key`, or would hold it: the last block
+    /// whose first key is not after `key`, or else the first block.
+    fn block_for(&self, key: &str) -> Option<(&str, &Block)> {
+        let mut before = self
+            .blocks
+            .range::<str, _>((Bound::Unbounded, Bound::Included(key)));
+        before
+            .next_back()
+            .or_else(|| self.blocks.iter().next())
+            .map(|(first, block)| (&**first, block))
+    }
+
+    /// Returns the block of `blocks` that holds `key`, or would hold it, like
+    /// `block_for`, for changing.
+    fn block_for_mut<'a>(
+        blocks: &'a mut BTreeMap<Box<str>, Block>,
+        key: &str,
+    ) -> Option<&'a mut Block> {
+        let (first, _) = blocks.first_key_value()?;
+        if key < &**first {
+            return blocks.values_mut().next();
+        }
+        let before = (Bound::Unbounded, Bound::Included(key));
+        blocks
+            .range_mut::<str, _>(before)
+            .next_back()
+            .map(|(_, block)| block)
+    }
+
+    /// Returns the entry of `key`, if the index holds it.
+    pub(crate) fn get(&self, key: &str) -> Option<&IndexEntry> {
+        let (_, block) = self.block_for(key)?;
+        let index = block.search(key).ok()?;
+        Some(&block.slots[index].entry)
+    }
+
+    /// Returns `true` if the index holds `key`.
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Sets the entry of `key`, returning the one it replaces, if any.
+    pub(crate) fn insert(&mut self, key: &str, entry: IndexEntry) -> Option<IndexEntry> {
+        self.live_length += entry.length;
+        let previous = self.insert_entry(key, entry);
+        if let Some(previous) = &previous {
+            self.live_length -= previous.length;
+        }
+        previous
+    }
+
+    /// Sets the entry of `key` in its block, returning the one it replaces.
+    fn insert_entry(&mut self, key: &str, entry: IndexEntry) -> Option<IndexEntry> {
+        let Some(block) = Index::block_for_mut(&mut self.blocks, key) else {
+            self.start_block(key, entry);
+            return None;
+        };
+        let index = match block.search(key) {
+            Ok(index) => {
+                let previous = std::mem::replace(&mut block.slots[index].entry, entry);
+                return Some(previous);
+            }
+            Err(index) => index,
+        };
+        self.len += 1;
+
+        if block.slots.len() < BLOCK_KEYS && index > 0 {
+            block.insert(index, key, entry);
+            return None;
+        }
+
+        // The block has to be filed anew: under the key, which becomes its
+        // first, or in two halves once it is full.  A full block the key goes
+        // past the end of is left as it is and a new block started instead.
+        let first: Box<str> = Box::from(block.first_key());
+        if block.slots.len() < BLOCK_KEYS {
+            block.insert(index, key, entry);
+            self.rekey(&first);
+            return None;
+        }
+        if index == BLOCK_KEYS {
+            block.keys.shrink_to_fit();
+            self.len -= 1;
+            self.start_block(key, entry);
+            return None;
+        }
+
+        let half = BLOCK_KEYS / 2;
+        let mut left = Block::packed(&block.keys, block.slots[..half].iter());
+        let mut right = Block::packed(&block.keys, block.slots[half..].iter());
+        match index.checked_sub(half) {
+            Some(index) => right.insert(index, key, entry),
+            None => left.insert(index, key, entry),
+        }
+        self.blocks.remove(&first);
+        self.blocks.insert(Box::from(left.first_key()), left);
+        self.blocks.insert(Box::from(right.first_key()), right);
+        None
+    }
+
+    /// Adds `key`, which the index does not hold, to a new block of its own.
+    fn start_block(&mut self, key: &str, entry: IndexEntry) {
+        let mut block = Block::new();
+        block.push(key, entry);
+        self.blocks.insert(Box::from(key), block);
+        self.len += 1;
+    }
+
+    /// Removes `key` from the index, returning its entry if it was present.
+    pub(crate) fn remove(&mut self, key: &str) -> Option<IndexEntry> {
+        let block = Index::block_for_mut(&mut self.blocks, key)?;
+        let index = block.search(key).ok()?;
+        let entry = block.remove(index);
+        self.len -= 1;
+        self.live_length -= entry.length;
+
+        // A block whose first key is removed was filed under it.
+        if index == 0 {
+            self.rekey(key);
+        }
+        Some(entry)
+    }
+
+    /// Files the block found under `first` under its current first key,
+    /// after its first key changed, or drops it once it is empty.
+    fn rekey(&mut self, first: &str) {
+        if let Some(block) = self.blocks.remove(first) {
+            if !block.slots.is_empty() {
+                self.blocks.insert(Box::from(block.first_key()), block);
+            }
+        }
+    }
+
+    /// Repacks the index into full blocks, each allocated to fit, once it
+    /// is loaded.  Keys loaded out of order leave blocks partly full.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        let mut block = Block::new();
+        for (_, old) in std::mem::take(&mut self.blocks) {
+            for slot in &old.slots {
+                if block.slots.len() == BLOCK_KEYS {
+                    let full = std::mem::replace(&mut block, Block::new());
+                    self.file(full);
+                }
+                block.push(old.key(slot), slot.entry);
+            }
+        }
+        if !block.slots.is_empty() {
+            self.file(block);
+        }
+    }
+
+    /// Adds `block`, which follows every block of the index, under its first
+    /// key, its buffer cut down to fit.
+    fn file(&mut self, mut block: Block) {
+        block.keys.shrink_to_fit();
+        block.slots.shrink_to_fit();
+        block.tags.shrink_to_fit();
+        self.blocks.insert(Box::from(block.first_key()), block);
+    }
+
+    /// Returns an iterator over every key and its entry, in key order.
+    pub(crate) fn iter(&self) -> Range<'_> {
+        self.range((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Returns an iterator over every key, in key order.
+    pub(crate) fn keys(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// Returns an iterator over every entry, in key order.
+    pub(crate) fn values(&self) -> impl DoubleEndedIterator<Item = &IndexEntry> {
+        self.iter().map(|(_, entry)| entry)
+    }
+
+    /// Returns the keys at the zero-based `positions` in key order, which
+    /// must be ascending.  Positions past the last key are ignored.
+    pub(crate) fn nth_keys(&self, positions: impl IntoIterator<Item = usize>) -> Vec<&str> {
+        let mut keys = Vec::new();
+        let mut blocks = self.blocks.values();
+        let mut block = blocks.next();
+        let mut start = 0;
+        for position in positions {
+            while let Some(current) = block {
+                if position < start + current.slots.len() {
+                    break;
+                }
+                start += current.slots.len();
+                block = blocks.next();
+            }
+            match block {
+                Some(block) => keys.push(block.key(&block.slots[position - start])),
+                None => break,
+            }
+        }
+        keys
+    }
+
+    /// Returns an iterator over the keys within `range` and their entries, in
+    /// key order.  A range whose start is after its end is empty.
+    pub(crate) fn range(&self, range: (Bound<&str>, Bound<&str>)) -> Range<'_> {
+        let empty = Range {
+            blocks: None,
+            front: None,
+            back: None,
+        };
+
+        let start = match range.0 {
+            Bound::Unbounded => self
+                .blocks
+                .iter()
+                .next()
+                .map(|(first, block)| (&**first, block, 0)),
+            Bound::Included(key) | Bound::Excluded(key) => {
+                self.block_for(key).map(|(first, block)| {
+                    let equal = matches!(range.0, Bound::Excluded(_));
+                    (first, block, block.partition(key, equal))
+                })
+            }
+        };
+        let end = match range.1 {
+            Bound::Unbounded => self
+                .blocks
+                .iter()
+                .next_back()
+                .map(|(first, block)| (&**first, block, block.slots.len())),
+            Bound::Included(key) | Bound::Excluded(key) => {
+                let equal = matches!(range.1, Bound::Included(_));
+                self.blocks
+                    .range::<str, _>((Bound::Unbounded, range.1))
+                    .next_back()
+                    .map(|(first, block)| (&**first, block, block.partition(key, equal)))
+            }
+        };
+        let (Some((start_first, start_block, start)), Some((end_first, end_block, end))) =
+            (start, end)
+        else {
+            return empty;
+        };
+
+        if start_first > end_first {
+            return empty;
+        }
+        if start_first == end_first {
+            let slots = start_block.slots[start..end.max(start)].iter();
+            return Range {
+                blocks: None,
+                front: Some((start_block, slots)),
+                back: None,
+            };
+        }
+        let between = (Bound::Excluded(start_first), Bound::Excluded(end_first));
+        Range {
+            blocks: Some(self.blocks.range::<str, _>(between)),
+            front: Some((start_block, start_block.slots[start..].iter())),
+            back: Some((end_block, end_block.slots[..end].iter())),
+        }
+    }
+
+    /// Returns the bytes taken up by the index: its blocks, with their keys
+    /// and entries, and the first key of every block.
+    pub(crate) fn memory(&self) -> u64 {
+        let blocks: u64 = self
+            .blocks
+            .iter()
+            .map(|(first, block)| first.len() as u64 + block.memory())
+            .sum();
+        memory::btree_map_bytes(&self.blocks) + blocks
+    }
+}
+
+impl std::ops::Index<&str> for Index {
+    type Output = IndexEntry;
+
+    fn index(&self, key: &str) -> &IndexEntry {
+        self.get(key).expect("key not in index")
+    }
+}
+
+impl<'a> IntoIterator for &'a Index {
+    type Item = (&'a str, &'a IndexEntry);
+    type IntoIter = Range<'a>;
+
+    fn into_iter(self) -> Range<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over part of an [`Index`], in key order, returned by
+/// [`Index::range`].  The slots left to visit in the first and last blocks
+/// are kept apart from the blocks in between.
+pub(crate) struct Range<'a> {
+    blocks: Option<btree_map::Range<'a, Box<str>, Block>>,
+    front: Option<(&'a Block, slice::Iter<'a, Slot>)>,
+    back: Option<(&'a Block, slice::Iter<'a, Slot>)>,
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = (&'a str, &'a IndexEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((block, slots)) = &mut self.front {
+                if let Some(slot) = slots.next() {
+                    return Some((block.key(slot), &slot.entry));
+                }
+            }
+            match self.blocks.as_mut().and_then(Iterator::next) {
+                Some((_, block)) => self.front = Some((block, block.slots.iter())),
+                None => {
+                    let (block, slots) = self.back.as_mut()?;
+                    let slot = slots.next()?;
+                    return Some((block.key(slot), &slot.entry));
+                }
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for Range<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((block, slots)) = &mut self.back {
+                if let Some(slot) = slots.next_back() {
+                    return Some((block.key(slot), &slot.entry));
+                }
+            }
+            match self
+                .blocks
+                .as_mut()
+                .and_then(DoubleEndedIterator::next_back)
+            {
+                Some((_, block)) => self.back = Some((block, block.slots.iter())),
+                None => {
+                    let (block, slots) = self.front.as_mut()?;
+                    let slot = slots.next_back()?;
+                    return Some((block.key(slot), &slot.entry));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the `i`th key, which sorts like `i`.
+    fn key(i: usize) -> String {
+        format!("key{i:04}")
+    }
+
+    /// Returns an entry recording `i` as its position.
+    fn entry(i: usize) -> IndexEntry {
+        IndexEntry::new(i as u64, 10, 1, 0, i as u64)
+    }
+
+    /// Returns an index of keys `0..count`, added in order.
+    fn in_order(count: usize) -> Index {
+        let mut index = Index::default();
+        for i in 0..count {
+            index.insert(&key(i), entry(i));
+        }
+        index
+    }
+
+    /// Returns the keys of `range` and the positions of their entries.
+    fn collect<'a>(range: impl Iterator<Item = (&'a str, &'a IndexEntry)>) -> Vec<(String, u64)> {
+        range
+            .map(|(key, entry)| (key.to_string(), entry.position))
+            .collect()
+    }
+
+    /// Returns the number of keys in each block of `index`.
+    fn block_sizes(index: &Index) -> Vec<usize> {
+        index
+            .blocks
+            .values()
+            .map(|block| block.slots.len())
+            .collect()
+    }
+
+    #[test]
+    fn full_block_splits_in_half_when_a_key_goes_inside_it() {
+        let mut index = Index::default();
+        for i in (0..BLOCK_KEYS).rev() {
+            index.insert(&key(2 * i), entry(2 * i));
+        }
+        assert_eq!(block_sizes(&index), [BLOCK_KEYS]);
+
+        index.insert(&key(BLOCK_KEYS + 1), entry(BLOCK_KEYS + 1));
+        assert_eq!(block_sizes(&index), [BLOCK_KEYS / 2, BLOCK_KEYS / 2 + 1]);
+        assert_eq!(index.len(), BLOCK_KEYS + 1);
+        assert_eq!(index.live_length(), 10 * (BLOCK_KEYS as u64 + 1));
+
+        let mut expected: Vec<usize> = (0..BLOCK_KEYS).map(|i| 2 * i).collect();
+        expected.push(BLOCK_KEYS + 1);
+        expected.sort_unstable();
+        let expected: Vec<(String, u64)> = expected.iter().map(|&i| (key(i), i as u64)).collect();
+        assert_eq!(collect(index.iter()), expected);
+        for (key, position) in &expected {
+            assert_eq!(index.get(key).map(|entry| entry.position), Some(*position));
+        }
+    }
+
+    #[test]
+    fn full_block_is_left_full_when_keys_go_past_its_end() {
+        let index = in_order(2 * BLOCK_KEYS + 1);
+        assert_eq!(block_sizes(&index), [BLOCK_KEYS, BLOCK_KEYS, 1]);
+        assert_eq!(index.len(), 2 * BLOCK_KEYS + 1);
+    }
+
+    #[test]
+    fn key_before_the_first_block_refiles_it() {
+        let mut index = in_order(BLOCK_KEYS / 2);
+        index.insert("a", entry(0));
+        assert_eq!(index.blocks.keys().next().map(|first| &**first), Some("a"));
+        assert!(index.contains_key("a"));
+        assert_eq!(index.keys().next(), Some("a"));
+    }
+
+    #[test]
+    fn removing_every_key_of_a_block_drops_it() {
+        let mut index = in_order(3 * BLOCK_KEYS);
+        for i in BLOCK_KEYS..2 * BLOCK_KEYS {
+            assert_eq!(
+                index.remove(&key(i)).map(|entry| entry.position),
+                Some(i as u64)
+            );
+        }
+        assert_eq!(block_sizes(&index), [BLOCK_KEYS, BLOCK_KEYS]);
+        assert_eq!(
+            index.get(&key(BLOCK_KEYS)).map(|entry| entry.position),
+            None
+        );
+        assert_eq!(
+            index.remove(&key(BLOCK_KEYS)).map(|entry| entry.position),
+            None
+        );
+
+        // The first key of a block is removed: the block is filed under the
+        // key after it.
+        index.remove(&key(2 * BLOCK_KEYS));
+        let first = key(2 * BLOCK_KEYS + 1);
+        assert!(index.blocks.contains_key(first.as_str()));
+        let position = index.get(&first).map(|entry| entry.position);
+        assert_eq!(position, Some(2 * BLOCK_KEYS as u64 + 1));
+
+        for i in (0..3 * BLOCK_KEYS).rev() {
+            index.remove(&key(i));
+        }
+        assert!(index.blocks.is_empty());
+        assert_eq!(index.len(), 0);
+        assert_eq!(index.live_length(), 0);
+        assert_eq!(index.iter().next().map(|(key, _)| key), None);
+        assert_eq!(index.iter().next_back().map(|(key, _)| key), None);
+
+        index.insert(&key(7), entry(7));
+        assert_eq!(collect(index.iter()), [(key(7), 7)]);
+    }
+
+    #[test]
+    fn range_and_reverse_iteration_cross_block_edges() {
+        let count = 3 * BLOCK_KEYS;
+        let index = in_order(count);
+        let model: BTreeMap<String, u64> = (0..count).map(|i| (key(i), i as u64)).collect();
+
+        // Keys on either side of each block edge, keys between them that the
+        // index does not hold, and keys before and after every other.
+        let mut bounds = vec!["a".to_string(), "z".to_string()];
+        for edge in [BLOCK_KEYS, 2 * BLOCK_KEYS] {
+            bounds.extend([key(edge - 1), key(edge), format!("{}~", key(edge - 1))]);
+        }
+        bounds.push(key(0));
+        bounds.push(key(count - 1));
+
+        let bound = |kind: usize, key: &str| match kind {
+            0 => Bound::Included(key.to_string()),
+            1 => Bound::Excluded(key.to_string()),
+            _ => Bound::Unbounded,
+        };
+        for start in &bounds {
+            for end in &bounds {
+                if start > end {
+                    continue;
+                }
+                for kinds in [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (0, 2)] {
+                    let (low, high) = (bound(kinds.0, start), bound(kinds.1, end));
+                    if start == end
+                        && matches!((&low, &high), (Bound::Excluded(_), Bound::Excluded(_)))
+                    {
+                        // `BTreeMap::range` panics on this empty range.
+                        continue;
+                    }
+                    let expected: Vec<(String, u64)> = model
+                        .range((low.clone(), high.clone()))
+                        .map(|(key, position)| (key.clone(), *position))
+                        .collect();
+                    let range = (
+                        low.as_ref().map(String::as_str),
+                        high.as_ref().map(String::as_str),
+                    );
+                    assert_eq!(collect(index.range(range)), expected, "{range:?}");
+
+                    let mut reversed = expected.clone();
+                    reversed.reverse();
+                    assert_eq!(collect(index.range(range).rev()), reversed, "{range:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn iteration_from_both_ends_meets_in_the_middle() {
+        let count = 2 * BLOCK_KEYS + 3;
+        let index = in_order(count);
+        let mut range = index.iter();
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        while let Some((key, _)) = range.next() {
+            front.push(key.to_string());
+            match range.next_back() {
+                Some((key, _)) => back.push(key.to_string()),
+                None => break,
+            }
+        }
+        back.reverse();
+        front.extend(back);
+        let expected: Vec<String> = (0..count).map(key).collect();
+        assert_eq!(front, expected);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{blob, ActionKV, Checkpoint, Checksum};
+use std::io::Result;
+
+/// Facts about how a database is stored, as reported by
+/// [`ActionKV::info`], so that diagnosing a problem starts from the file
+/// itself.
+#[derive(Debug)]
+pub struct DatabaseInfo {
+    /// The checksum protecting every record.  Logs checksummed with CRC-32
+    /// have no format record; every other log starts with a format record
+    /// naming its checksum.
+    pub checksum: Checksum,
+    /// Whether a passphrase is required to open the database.  Values
+    /// themselves are never encrypted, nor compressed.
+    pub passphrase: bool,
+    /// Number of keys in the index.
+    pub keys: u64,
+    /// Sequence number of the most recent write.
+    pub last_sequence: u64,
+    /// Bytes of records in the log, which is a single file.
+    pub log_bytes: u64,
+    /// Length of the log file, which exceeds `log_bytes` when space is
+    /// preallocated.
+    pub file_bytes: u64,
+    /// Number of blob files holding values stored outside the log.
+    pub blob_files: u64,
+    /// Bytes taken up by the blob files.
+    pub blob_bytes: u64,
+    /// The most recent checkpoint in the log, if any.
+    pub last_checkpoint: Option<Checkpoint>,
+    /// The state of the index file saved by the last checkpoint.
+    pub index_file: IndexFile,
+    /// Whether another process holds the lock taken by databases shared with
+    /// [`Options::multi_process`](crate::Options::multi_process).
+    pub lock: LockState,
+}
+
+/// The state of the index file saved by [`ActionKV::checkpoint`], which lets
+/// an open skip scanning the log up to the checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFile {
+    /// There is no index file, so opening the database scans the whole log.
+    Missing,
+    /// The index file describes the log as of this checkpoint, and is used
+    /// by the next open.
+    Current(Checkpoint),
+    /// The index file is damaged or refers to a checkpoint the log no longer
+    /// holds, as after compaction, and is ignored.
+    Stale,
+}
+
+/// Whether the lock of a database shared between processes is held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockState {
+    /// There is no lock file: the database has not been opened with
+    /// [`Options::multi_process`](crate::Options::multi_process).
+    Unshared,
+    /// No other process holds the lock.
+    Free,
+    /// Another process holds the lock, and writes from this one wait for it.
+    Held,
+}
+
+impl ActionKV {
+    /// Returns facts about how the database is stored: its checksum and
+    /// protection, the size of its log and blob files, the state of the index
+    /// saved by the last checkpoint, and whether another process holds its
+    /// lock.  Databases without a file report no blobs, index file or lock.
+    pub fn info(&self) -> Result<DatabaseInfo> {
+        let (blob_files, blob_bytes, index_file, lock) = match self.storage.file("info") {
+            Ok(_) => {
+                let (files, bytes) = blob::dir_usage(&blob::blob_dir(&self.path))?;
+                (files, bytes, self.index_file()?, self.lock_state()?)
+            }
+            Err(_) => (0, 0, IndexFile::Missing, LockState::Unshared),
+        };
+
+        Ok(DatabaseInfo {
+            checksum: self.checksum,
+            passphrase: self.passphrase.is_some(),
+            keys: self.database.len() as u64,
+            last_sequence: self.last_sequence(),
+            log_bytes: self.end,
+            file_bytes: self.storage.len()?,
+            blob_files,
+            blob_bytes,
+            last_checkpoint: self.last_checkpoint,
+            index_file,
+            lock,
+        })
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Escapes a NUL byte inside a string part.
+const ESCAPED_NUL: &str = "\0\u{2}";
+
+/// Ends a string part.  It sorts below [`ESCAPED_NUL`] and below every other
+/// character, so a string sorts before the strings it is a prefix of.
+const TERMINATOR: &str = "\0\u{1}";
+
+/// The width of an encoded integer part: 16 hex digits.
+const INTEGER_WIDTH: usize = 16;
+
+/// Builds a key out of numbers, timestamps and strings whose encoding sorts
+/// the way the parts do, so that [`range`](crate::ActionKV::range) and
+/// [`scan`](crate::ActionKV::scan) visit numeric and composite keys in order.
+/// Parts compare left to right, like a tuple:
+///
+/// ```
+/// # use kvstore::KeyEncoder;
+/// let low = KeyEncoder::new().str("order").u64(9).finish();
+/// let high = KeyEncoder::new().str("order").u64(10).finish();
+/// assert!(low < high);
+/// ```
+///
+/// Integers are written as 16 hex digits, with the sign bit of `i64`s flipped
+/// so that negative numbers sort first, and timestamps as signed milliseconds
+/// since the Unix epoch.  Strings end in a terminator that sorts below any
+/// character, so every key built from a given first part falls within the
+/// prefix that part encodes to.  [`KeyDecoder`] reads the parts back.
+#[derive(Clone, Debug, Default)]
+pub struct KeyEncoder {
+    key: String,
+}
+
+impl KeyEncoder {
+    /// Returns an encoder with no parts.
+    pub fn new() -> KeyEncoder {
+        KeyEncoder::default()
+    }
+
+    /// Appends an unsigned integer.
+    pub fn u64(mut self, value: u64) -> KeyEncoder {
+        self.key.push_str(&format!("{value:016x}"));
+        self
+    }
+
+    /// Appends a signed integer.
+    pub fn i64(self, value: i64) -> KeyEncoder {
+        self.u64(value as u64 ^ (1 << 63))
+    }
+
+    /// Appends a timestamp, to the millisecond.
+    pub fn timestamp(self, time: SystemTime) -> KeyEncoder {
+        let millis = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_millis() as i64,
+            Err(e) => -(e.duration().as_millis() as i64),
+        };
+        self.i64(millis)
+    }
+
+    /// Appends a string.
+    pub fn str(mut self, value: &str) -> KeyEncoder {
+        self.key.push_str(&value.replace('\0', ESCAPED_NUL));
+        self.key.push_str(TERMINATOR);
+        self
+    }
+
+    /// Returns the key built so far.  It is also a prefix of every key built
+    /// by appending more parts, for use with [`scan`](crate::ActionKV::scan).
+    pub fn finish(self) -> String {
+        self.key
+    }
+}
+
+/// Reads back the parts of a key built by [`KeyEncoder`], in the order they
+/// were appended.  A part that does not match what was encoded there is
+/// reported as [`ErrorKind::InvalidData`], and leaves the decoder where it
+/// was.
+#[derive(Clone, Debug)]
+pub struct KeyDecoder<'a> {
+    rest: &'a str,
+}
+
+impl<'a> KeyDecoder<'a> {
+    /// Returns a decoder reading the parts of `key`.
+    pub fn new(key: &'a str) -> KeyDecoder<'a> {
+        KeyDecoder { rest: key }
+    }
+
+    /// Reads an unsigned integer.
+    pub fn u64(&mut self) -> Result<u64> {
+        let digits = self.rest.get(..INTEGER_WIDTH).filter(|digits| {
+            digits
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        });
+        let Some(digits) = digits else {
+            let error_message = format!("expected an integer key part at {:?}", self.rest);
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        };
+        self.rest = &self.rest[INTEGER_WIDTH..];
+        u64::from_str_radix(digits, 16).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Reads a signed integer.
+    pub fn i64(&mut self) -> Result<i64> {
+        Ok((self.u64()? ^ (1 << 63)) as i64)
+    }
+
+    /// Reads a timestamp.
+    pub fn timestamp(&mut self) -> Result<SystemTime> {
+        let millis = self.i64()?;
+        let since = Duration::from_millis(millis.unsigned_abs());
+        let time = match millis < 0 {
+            true => UNIX_EPOCH.checked_sub(since),
+            false => UNIX_EPOCH.checked_add(since),
+        };
+        time.ok_or_else(|| Error::new(ErrorKind::InvalidData, "timestamp key part out of range"))
+    }
+
+    /// Reads a string.
+    pub fn str(&mut self) -> Result<String> {
+        let mut value = String::new();
+        let mut rest = self.rest;
+        loop {
+            let Some(nul) = rest.find('\0') else {
+                let error_message = format!("unterminated string key part {:?}", self.rest);
+                let error = Error::new(ErrorKind::InvalidData, error_message);
+                return Err(error);
+            };
+            value.push_str(&rest[..nul]);
+            let escape = &rest[nul..];
+            if let Some(after) = escape.strip_prefix(TERMINATOR) {
+                self.rest = after;
+                return Ok(value);
+            }
+            let Some(after) = escape.strip_prefix(ESCAPED_NUL) else {
+                let error_message = format!("invalid escape in string key part {escape:?}");
+                let error = Error::new(ErrorKind::InvalidData, error_message);
+                return Err(error);
+            };
+            value.push('\0');
+            rest = after;
+        }
+    }
+
+    /// Returns whether every part has been read.
+    pub fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+}
+
+/// A value that can be a part of a [`Key`].  Unsigned integers are encoded
+/// like [`KeyEncoder::u64`], signed ones like [`KeyEncoder::i64`], so a
+/// position holding `u32`s sorts like one holding `u64`s, but not like one
+/// holding signed integers: give each position of a key one type.
+pub trait KeyPart {
+    /// Appends the part to `encoder`.
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder;
+}
+
+impl KeyPart for &str {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.str(self)
+    }
+}
+
+impl KeyPart for &String {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.str(self)
+    }
+}
+
+impl KeyPart for String {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.str(&self)
+    }
+}
+
+impl KeyPart for u64 {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.u64(self)
+    }
+}
+
+impl KeyPart for u32 {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.u64(self.into())
+    }
+}
+
+impl KeyPart for i64 {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.i64(self)
+    }
+}
+
+impl KeyPart for i32 {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.i64(self.into())
+    }
+}
+
+impl KeyPart for SystemTime {
+    fn append_to(self, encoder: KeyEncoder) -> KeyEncoder {
+        encoder.timestamp(self)
+    }
+}
+
+/// A composite key built from typed parts, for modelling hierarchical data
+/// without formatting keys by hand:
+///
+/// ```
+/// # use kvstore::Key;
+/// # let mut akv = kvstore::ActionKV::open_in_memory().unwrap();
+/// let key = Key::new().part("user").part(42).part("email");
+/// akv.insert(key.into(), "ada@example.com".to_string()).unwrap();
+/// let user = akv.scan_partial(&Key::new().part("user").part(42));
+/// ```
+///
+/// The parts are encoded by [`KeyEncoder`], so keys sort by their parts in
+/// turn and the keys beginning with some parts are exactly those within the
+/// prefix the parts encode to.  [`KeyDecoder`] reads the parts back.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    key: String,
+}
+
+impl Key {
+    /// Returns a key with no parts.
+    pub fn new() -> Key {
+        Key::default()
+    }
+
+    /// Appends `part` to the key.
+    pub fn part(self, part: impl KeyPart) -> Key {
+        let encoder = KeyEncoder { key: self.key };
+        Key {
+            key: part.append_to(encoder).finish(),
+        }
+    }
+
+    /// Returns the encoded key.
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+}
+
+impl From<Key> for String {
+    fn from(key: Key) -> String {
+        key.key
+    }
+}
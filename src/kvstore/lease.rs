@@ -0,0 +1,110 @@
+use crate::{now, ActionKV, FLAG_LEASE};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+/// The holder of a lease, taken from the newest lease record of its key.
+pub(crate) struct Lease {
+    /// Sequence number of the record that granted the lease.
+    pub(crate) token: u64,
+    /// When the lease lapses, in milliseconds since the Unix epoch.
+    pub(crate) expires_at: u64,
+    /// Offset of the record that granted the lease.
+    pub(crate) position: u64,
+}
+
+impl Lease {
+    /// Returns `true` if the lease has not lapsed at time `at`.
+    pub(crate) fn is_held(&self, at: u64) -> bool {
+        self.expires_at > at
+    }
+}
+
+impl ActionKV {
+    /// Acquires the lease named `key` for `ttl`, unless another holder has it.
+    /// Returns the token needed to release the lease, or `None` if the lease
+    /// is currently held.  Tokens increase with every acquisition.
+    ///
+    /// Leases are stored as special records in the log and live in their own
+    /// namespace: they do not appear among the database's keys.  With
+    /// [`multi_process`](crate::Options::multi_process) set, the check and the
+    /// record are made under the process lock, after reading the leases other
+    /// processes wrote; otherwise processes sharing the database file only see
+    /// each other's leases after reopening it.
+    pub fn acquire(&mut self, key: String, ttl: Duration) -> Result<Option<u64>> {
+        self.locked(|akv| akv.grant_lease(key, ttl))
+    }
+
+    /// Acquires the lease named `key` for `ttl` if it is free, for `acquire`.
+    fn grant_lease(&mut self, key: String, ttl: Duration) -> Result<Option<u64>> {
+        let now = now();
+        if let Some(lease) = self.leases.get(&key) {
+            if lease.is_held(now) {
+                return Ok(None);
+            }
+        }
+
+        let expires_at = now + ttl.as_millis() as u64;
+        let token = self.next_sequence;
+        let position =
+            self.insert_in_database(&key, &expires_at.to_string(), &[], FLAG_LEASE, now)?;
+        let lease = Lease {
+            token,
+            expires_at,
+            position,
+        };
+        self.leases.insert(key, lease);
+        Ok(Some(token))
+    }
+
+    /// Releases the lease named `key` if `token` is the token of its current,
+    /// unexpired holder.  Returns `false` if the lease was not held with that
+    /// token, for example because it already lapsed.
+    pub fn release(&mut self, key: &str, token: u64) -> Result<bool> {
+        self.locked(|akv| akv.end_lease(key, token))
+    }
+
+    /// Releases the lease named `key` if `token` holds it, for `release`.
+    fn end_lease(&mut self, key: &str, token: u64) -> Result<bool> {
+        let now = now();
+        match self.leases.get(key) {
+            Some(lease) if lease.token == token && lease.is_held(now) => {}
+            _ => return Ok(false),
+        }
+
+        self.insert_in_database(key, "0", &[], FLAG_LEASE, now)?;
+        self.leases.remove(key);
+        Ok(true)
+    }
+}
+
+/// Applies a lease record read from the log at `position` to `leases`.  A
+/// record whose value is zero releases the lease.
+pub(crate) fn load(
+    leases: &mut HashMap<String, Lease>,
+    key: String,
+    value: &str,
+    sequence: u64,
+    position: u64,
+) -> Result<()> {
+    let expires_at = match value.parse::<u64>() {
+        Ok(expires_at) => expires_at,
+        Err(_) => {
+            let error_message = format!("invalid lease record for key: {key}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+    };
+
+    if expires_at == 0 {
+        leases.remove(&key);
+    } else {
+        let lease = Lease {
+            token: sequence,
+            expires_at,
+            position,
+        };
+        leases.insert(key, lease);
+    }
+    Ok(())
+}
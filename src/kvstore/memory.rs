@@ -0,0 +1,97 @@
+use crate::ActionKV;
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+
+/// An estimate of the memory the in-memory structures of a database take up,
+/// in bytes, as reported by [`ActionKV::index_memory_estimate`].
+#[derive(Debug, Default)]
+pub struct IndexMemory {
+    /// Number of keys in the index.
+    pub keys: u64,
+    /// The index itself: the keys, and the offset, length, value size and
+    /// timestamp kept for every key.
+    pub index: u64,
+    /// Expiries and leases set on keys.
+    pub expiries: u64,
+    /// References from keys to the blob files holding their values.
+    pub blobs: u64,
+    /// Recency of use of every key, kept when
+    /// [`Options::max_live_bytes`](crate::Options::max_live_bytes) is set.
+    pub lru: u64,
+    /// Blocks of the log held by the block cache, up to
+    /// [`Options::block_cache_size`](crate::Options::block_cache_size).
+    pub block_cache: u64,
+}
+
+impl IndexMemory {
+    /// Returns the estimated total, in bytes.
+    pub fn total(&self) -> u64 {
+        self.index + self.expiries + self.blobs + self.lru + self.block_cache
+    }
+
+    /// Returns the estimated bytes per key, from which the memory needed for
+    /// a larger database with similar keys can be predicted.
+    pub fn per_key(&self) -> u64 {
+        (self.total() - self.block_cache)
+            .checked_div(self.keys)
+            .unwrap_or(0)
+    }
+}
+
+impl ActionKV {
+    /// Estimates the memory taken up by the index and the other per-key
+    /// structures kept in memory, so the memory a database needs can be
+    /// predicted before it grows too large for the machine.  Memory grows
+    /// with the number of keys and their lengths, not with the size of the
+    /// values, except for blob references.  The estimate counts the space
+    /// allocated for every key string and the nodes and tables of the
+    /// collections holding them, assuming B-tree nodes are two-thirds full;
+    /// the allocator's own overhead is not included.
+    pub fn index_memory_estimate(&self) -> IndexMemory {
+        let index = self.database.memory();
+        let expiries = hash_map_bytes(&self.expiries)
+            + self.expiries.keys().map(string_bytes).sum::<u64>()
+            + hash_map_bytes(&self.leases)
+            + self.leases.keys().map(string_bytes).sum::<u64>();
+        let lru = match &self.lru {
+            Some(lru) => lru.borrow().memory(),
+            None => 0,
+        };
+        let block_cache = match &self.block_cache {
+            Some(cache) => cache.borrow().memory(),
+            None => 0,
+        };
+        IndexMemory {
+            keys: self.database.len() as u64,
+            index,
+            expiries,
+            blobs: self.blob_refs.memory(),
+            lru,
+            block_cache,
+        }
+    }
+}
+
+/// Returns the bytes allocated for the contents of `string`.
+pub(crate) fn string_bytes(string: &String) -> u64 {
+    string.capacity() as u64
+}
+
+/// Returns the bytes taken up by the nodes of `map`, not counting what its
+/// keys and values point to.  Nodes hold up to eleven entries and are
+/// assumed to be two-thirds full.
+pub(crate) fn btree_map_bytes<K, V>(map: &BTreeMap<K, V>) -> u64 {
+    let entry = (size_of::<K>() + size_of::<V>()) as u64;
+    map.len() as u64 * entry * 3 / 2
+}
+
+/// Returns the bytes taken up by the table of `map`, not counting what its
+/// keys and values point to.  The table has a control byte for every slot and
+/// keeps one slot in eight free.
+pub(crate) fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> u64 {
+    let slot = (size_of::<K>() + size_of::<V>() + 1) as u64;
+    match map.capacity() as u64 {
+        0 => 0,
+        capacity => (capacity * 8 / 7).next_power_of_two() * slot,
+    }
+}
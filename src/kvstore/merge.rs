@@ -0,0 +1,72 @@
+use crate::ActionKV;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Decides which value wins when a key exists in both databases being merged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the value already in this database.
+    Ours,
+    /// Replace the value with the one from the other database.
+    Theirs,
+    /// Keep whichever value was written most recently.
+    Newest,
+}
+
+impl ActionKV {
+    /// Copies every entry of the database at `other_path` into this database.
+    /// Keys present in both databases are resolved with `policy`; entries
+    /// taken from the other database keep their original timestamps.  Returns
+    /// the number of keys written.
+    pub fn merge_from(&mut self, other_path: &Path, policy: ConflictPolicy) -> Result<usize> {
+        if !other_path.exists() {
+            let error_message = format!("database: {} does not exist", other_path.display());
+            let error = Error::new(ErrorKind::NotFound, error_message);
+            return Err(error);
+        }
+
+        let other = ActionKV::open_with_options(other_path, self.options.clone())?;
+        self.locked(|akv| akv.merge_entries(&other, policy))
+    }
+
+    /// Copies the entries of `other` into this database, resolving conflicts
+    /// with `policy`, for `merge_from`.
+    fn merge_entries(&mut self, other: &ActionKV, policy: ConflictPolicy) -> Result<usize> {
+        let keys: Vec<String> = other.keys().map(String::from).collect();
+
+        let mut merged = 0;
+        for key in keys {
+            let theirs = other.get_record_at_position(other.database[&key].position)?;
+            let timestamp = theirs.timestamp;
+            let user_meta = theirs.user_meta.clone();
+
+            let ours = match self.database.get(&key) {
+                Some(entry) => Some(self.get_record_at_position(entry.position)?),
+                None => None,
+            };
+
+            let take = match (&ours, policy) {
+                (None, _) => true,
+                (Some(_), ConflictPolicy::Ours) => false,
+                (Some(_), ConflictPolicy::Theirs) => true,
+                (Some(ours), ConflictPolicy::Newest) => timestamp > ours.timestamp,
+            };
+            if !take {
+                continue;
+            }
+
+            let value = other.resolve_value(theirs)?;
+            if let Some(ours) = ours {
+                if ours.user_meta == user_meta && self.resolve_value(ours)? == value {
+                    continue;
+                }
+            }
+
+            self.check_limits(&key, &value)?;
+            self.write_value(key, value, &user_meta, timestamp)?;
+            merged += 1;
+        }
+
+        Ok(merged)
+    }
+}
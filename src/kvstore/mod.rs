@@ -1,74 +1,608 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-/// A key/value database with strong durability.  All entries in the database
-/// are stored in non-volatile memory as part of each `insert` and `update`
-/// operation along with a 32-bit CRC value.  Subsequently, each `get` request
-/// does incur some IO cost as the value is stored in the database.
+mod backup;
+mod batch;
+mod blob;
+mod block_cache;
+mod cache;
+mod checkpoint;
+mod checksum;
+mod compact;
+mod delta;
+mod error;
+mod events;
+mod expiry;
+mod hooks;
+mod index;
+mod info;
+mod key;
+mod lease;
+mod memory;
+mod merge;
+mod multi_process;
+mod options;
+mod original;
+mod page;
+mod partial;
+mod passphrase;
+mod range;
+mod read_only;
+mod repair;
+mod sample;
+mod scan;
+mod sharded;
+mod shared;
+mod snapshot;
+mod stall;
+mod stats;
+mod storage;
+mod sys;
+mod top;
+mod transaction;
+mod verify;
+mod visit;
+mod zero_copy;
+
+pub use checkpoint::Checkpoint;
+pub use checksum::Checksum;
+#[doc(hidden)]
+pub use compact::{fail_compaction_at, CompactionStep};
+pub use compact::{CompactionProgress, CompactionReport, PrefixReport};
+pub use error::{KeyNotFound, VersionMismatch};
+pub use events::{ChangeRecord, Changes, Event, EventCursor, EventOp};
+pub use info::{DatabaseInfo, IndexFile, LockState};
+pub use key::{Key, KeyDecoder, KeyEncoder, KeyPart};
+pub use memory::IndexMemory;
+pub use merge::ConflictPolicy;
+pub use options::{AutoCompact, Options, SyncPolicy, WriteStall};
+pub use page::{Cursor, Page};
+pub use scan::Scan;
+pub use sharded::ShardedKV;
+pub use shared::{DurabilityHandle, SharedKV};
+pub use snapshot::ReadView;
+pub use stats::ValueSizeStats;
+pub use top::TopBy;
+pub use transaction::Operation;
+pub use verify::{CorruptRecord, IntegrityReport, Repair};
+
+/// Record flag marking a value that lives in a separate blob file.  The value
+/// stored in the log is the name of that file.
+const FLAG_BLOB: u8 = 0x01;
+
+/// Record flag marking a lease record written by `acquire` or `release`.  The
+/// value is the expiry time of the lease, or zero once it is released.
+const FLAG_LEASE: u8 = 0x02;
+
+/// Record flag marking a checkpoint written by `checkpoint`.  The key and
+/// value are empty.
+const FLAG_CHECKPOINT: u8 = 0x04;
+
+/// Record flag marking an expiry record written by `expire_at` or `persist`.
+/// The value is when the key expires, or zero once the expiry is removed.
+const FLAG_EXPIRY: u8 = 0x08;
+
+/// Record flag marking a tombstone written by `remove_expired`.  The key is
+/// dropped from the index; the value is empty.
+const FLAG_TOMBSTONE: u8 = 0x10;
+
+/// Record flag marking a record carrying user metadata, written by
+/// `insert_with_meta`.  The value begins with the length of the metadata in
+/// one byte, followed by the metadata and then the value itself.  A record
+/// with this flag and empty metadata is reserved for [`FLAG_DELETE`]: other
+/// writes set the flag only for metadata that is not empty, and records
+/// copied by compaction or a backup keep the flags they had.
+const FLAG_META: u8 = 0x20;
+
+/// The flags of a record written by `delete`, which has no flag bit of its
+/// own as all eight are taken: [`FLAG_META`] with empty metadata, so that a
+/// deleted key can be told apart from one set to an empty value.  The key is
+/// indexed with an empty value either way.
+const FLAG_DELETE: u8 = FLAG_META;
+
+/// Record flag marking a passphrase record written by `set_passphrase`.  The
+/// key is empty; the value is the salt and MAC checked at open, or empty once
+/// the passphrase is removed.
+const FLAG_PASSPHRASE: u8 = 0x40;
+
+/// Record flag marking an update stored as a delta against an earlier record
+/// of the key, written when `Options::delta_threshold` is set.  The value is
+/// the delta; see `delta.rs`.
+const FLAG_DELTA: u8 = 0x80;
+
+/// Longest user metadata a record can carry, in bytes.
+pub const MAX_USER_META_LENGTH: usize = u8::MAX as usize;
+
+/// Marks the end of every record.  A record whose header made it to disk but
+/// whose tail did not lacks this trailer, so the torn write is detected rather
+/// than mis-parsed.
+const TRAILER_MAGIC: u32 = 0x414B_5654;
+
+/// Bytes before the key: checksum, key length, value length, flags, timestamp,
+/// and sequence number.
+const HEADER_LENGTH: u64 = 29;
+
+/// Bytes after the value: trailer magic and a copy of the checksum.
+const TRAILER_LENGTH: u64 = 8;
+
+/// Metadata stored with every record, returned by
+/// [`get_with_meta`](ActionKV::get_with_meta).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordMeta {
+    /// Position of the record in the order of all writes to the database.
+    /// Every write gets a higher sequence number than the one before it, and
+    /// compaction preserves them, which makes them usable as a cursor for
+    /// replication, incremental backup and change streams.
+    pub sequence: u64,
+    /// When the record was written, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Application-defined bytes stored with the value by
+    /// [`insert_with_meta`](ActionKV::insert_with_meta), such as a type tag
+    /// or schema version.  Empty for values written without any.
+    pub user_meta: Vec<u8>,
+}
+
+/// Metadata about the current value of a key, kept in the index and
+/// returned by [`key_meta`](ActionKV::key_meta) and
+/// [`keys_with_meta`](ActionKV::keys_with_meta) without reading the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyMeta {
+    /// Length of the value in bytes.
+    pub value_size: u64,
+    /// When the value was written, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Sequence number of the record holding the value, as checked by
+    /// [`update_if_version`](ActionKV::update_if_version).
+    pub sequence: u64,
+}
+
+/// Where the current record of a key lives and how long it is, along with
+/// the metadata listings are sorted by.
+#[derive(Clone, Copy)]
+struct IndexEntry {
+    position: u64,
+    length: u64,
+    meta: KeyMeta,
+}
+
+impl IndexEntry {
+    fn new(
+        position: u64,
+        length: u64,
+        value_size: u64,
+        timestamp: u64,
+        sequence: u64,
+    ) -> IndexEntry {
+        let meta = KeyMeta {
+            value_size,
+            timestamp,
+            sequence,
+        };
+        IndexEntry {
+            position,
+            length,
+            meta,
+        }
+    }
+}
 
 struct KeyValuePair {
+    flags: u8,
+    timestamp: u64,
+    sequence: u64,
     key: String,
     value: String,
+    user_meta: Vec<u8>,
 }
 
+/// A key/value database with strong durability.  All entries in the database
+/// are stored in non-volatile memory as part of each `insert` and `update`
+/// operation along with a 32-bit CRC value.  Subsequently, each `get` request
+/// does incur some IO cost as the value is stored in the database.
 pub struct ActionKV {
-    file: File,
-    database: HashMap<String, u64>,
+    path: PathBuf,
+    storage: storage::Storage,
+    database: index::Index,
+    blob_refs: blob::BlobRefs,
+    leases: HashMap<String, lease::Lease>,
+    expiries: HashMap<String, expiry::Expiry>,
+    options: Options,
+    /// Offset just past the last record; new records are written here.
+    end: u64,
+    /// Length of the file, which exceeds `end` when space is preallocated.
+    allocated: u64,
+    /// Writer used instead of `file` when [`Options::direct_io`] is set.
+    direct: Option<sys::DirectWriter>,
+    /// Records appended under [`SyncPolicy::WriteBehind`] but not yet written
+    /// to the file.  They occupy the offsets just before `end`.
+    pending: Vec<u8>,
+    /// Sequence number given to the next record written.
+    next_sequence: u64,
+    /// When the oldest record in `pending` was appended.
+    pending_since: Option<Instant>,
+    /// Records recovered from [`Options::secondary`] by `get`, waiting to be
+    /// rewritten by the next write.
+    repairs: RefCell<Vec<repair::Repair>>,
+    /// The most recent checkpoint record in the log.
+    last_checkpoint: Option<Checkpoint>,
+    /// The passphrase required to open the database, if one is set.
+    passphrase: Option<passphrase::Passphrase>,
+    /// Recency of use of every key, kept when [`Options::max_live_bytes`]
+    /// makes the database a cache.  Reads update it, hence the `RefCell`.
+    lru: Option<RefCell<cache::Lru>>,
+    /// Reads and writes of every key, counted when
+    /// [`Options::track_access`] is set.  Reads update it, hence the
+    /// `RefCell`.
+    access: Option<RefCell<top::AccessCounts>>,
+    /// Set for databases opened with `open_read_only`, which refuse writes.
+    read_only: bool,
+    /// Hooks registered with `on_write`, `on_read` and `on_delete`.
+    hooks: hooks::Hooks,
+    /// The checksum of every record in the log, set by its format record.
+    checksum: Checksum,
+    /// Recently read blocks of the log, kept when
+    /// [`Options::block_cache_size`] is set.  Reads fill it, hence the
+    /// `RefCell`.
+    block_cache: Option<RefCell<block_cache::BlockCache>>,
+    /// The lock shared with other processes, held when
+    /// [`Options::multi_process`] is set.
+    process_lock: Option<multi_process::ProcessLock>,
+    /// The older, unindexed part of the log, when
+    /// [`Options::index_recent`] is set.
+    partial: Option<partial::Partial>,
+    /// How many times the log was indexed anew after compaction replaced it,
+    /// which moves every record.  An [`EventCursor`] holds it along with its
+    /// offset in the log.
+    generation: u64,
 }
 
 impl ActionKV {
-    /// Opens the database located at `path`.
+    /// Opens the database located at `path` using the default [`Options`].
     pub fn open(path: &Path) -> Result<ActionKV> {
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .append(true)
-            .create(true)
-            .open(path)?;
-        let database = HashMap::new();
-        let mut akv = ActionKV { file, database };
+        ActionKV::open_with_options(path, Options::default())
+    }
+
+    /// Opens the database located at `path` using the supplied `options`.
+    pub fn open_with_options(path: &Path, options: Options) -> Result<ActionKV> {
+        if options.multi_process {
+            return multi_process::open(path, options);
+        }
+        multi_process::remove_leftover(path)?;
+
+        let file = open_log(path, options.file_mode)?;
+        let storage = storage::Storage::File(file);
+        let mut akv = ActionKV::with_storage(path.to_path_buf(), storage, options);
+        akv.load()?;
+        Ok(akv)
+    }
+
+    /// Creates an empty database kept entirely in memory, using the default
+    /// [`Options`].
+    pub fn open_in_memory() -> Result<ActionKV> {
+        ActionKV::open_in_memory_with_options(Options::default())
+    }
+
+    /// Creates an empty database kept entirely in memory, using the supplied
+    /// `options`.  Nothing touches the file system, so the database works
+    /// where there is none, such as in a browser on `wasm32`.  Its contents
+    /// are lost when it is dropped; use [`copy_to`](ActionKV::copy_to) to save
+    /// them to a file where one is available.
+    ///
+    /// [`Options::blob_threshold`] and [`Options::direct_io`] require a
+    /// database file and fail with an error of kind `Unsupported`.  On
+    /// `wasm32-unknown-unknown`, which has no clock, records are stamped with
+    /// a zero timestamp and [`SyncPolicy::WriteBehind`] is not available.
+    pub fn open_in_memory_with_options(options: Options) -> Result<ActionKV> {
+        let storage = storage::Storage::Memory(Vec::new());
+        let mut akv = ActionKV::with_storage(PathBuf::new(), storage, options);
         akv.load()?;
         Ok(akv)
     }
 
+    /// Opens a database whose log is kept in `storage`, using the default
+    /// [`Options`].  Any seekable stream works: an encrypted container, a
+    /// block device partition, or a `Cursor` in tests.  Records already in the
+    /// stream are loaded; an empty stream starts an empty database.
+    pub fn from_storage<S>(storage: S) -> Result<ActionKV>
+    where
+        S: Read + Write + Seek + Send + 'static,
+    {
+        ActionKV::from_storage_with_options(storage, Options::default())
+    }
+
+    /// Opens a database whose log is kept in `storage`, using the supplied
+    /// `options`.
+    ///
+    /// A stream cannot be shortened, so space freed by compaction or left by
+    /// an interrupted write is zeroed instead, and compaction rewrites the
+    /// stream in place: unlike compacting a file, it is not safe against a
+    /// crash.  [`Options::blob_threshold`] and [`Options::direct_io`] require
+    /// a database file and fail with an error of kind `Unsupported`.
+    pub fn from_storage_with_options<S>(storage: S, options: Options) -> Result<ActionKV>
+    where
+        S: Read + Write + Seek + Send + 'static,
+    {
+        let storage = storage::Storage::Stream(RefCell::new(Box::new(storage)));
+        let mut akv = ActionKV::with_storage(PathBuf::new(), storage, options);
+        akv.load()?;
+        Ok(akv)
+    }
+
+    /// Creates a database with an empty index over `storage`, which still has
+    /// to be loaded.
+    fn with_storage(path: PathBuf, storage: storage::Storage, options: Options) -> ActionKV {
+        let access = options.track_access.then(RefCell::default);
+        ActionKV {
+            path,
+            storage,
+            database: index::Index::default(),
+            blob_refs: blob::BlobRefs::default(),
+            leases: HashMap::new(),
+            expiries: HashMap::new(),
+            options,
+            end: 0,
+            allocated: 0,
+            direct: None,
+            next_sequence: 1,
+            pending: Vec::new(),
+            pending_since: None,
+            repairs: RefCell::new(Vec::new()),
+            last_checkpoint: None,
+            passphrase: None,
+            lru: None,
+            access,
+            read_only: false,
+            hooks: hooks::Hooks::default(),
+            checksum: Checksum::Crc32,
+            block_cache: None,
+            process_lock: None,
+            partial: None,
+            generation: 0,
+        }
+    }
+
     /// Deletes the value from the database associated with `key`.  Note that
     /// the key remains in the database but the value empty.
     pub fn delete(&mut self, key: String) -> Result<()> {
-        if !self.database.contains_key(&key) {
-            let error_message = format!("key: {key} not found in database");
-            let error = Error::new(ErrorKind::InvalidData, error_message);
-            return Err(error);
-        };
+        self.locked(|akv| {
+            if !akv.contains_key(&key) {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, &key);
+                return Err(error);
+            };
+
+            akv.run_delete_hooks(&key)?;
+            akv.apply_repairs()?;
+            let value = String::new();
+            let timestamp = now();
+            let sequence = akv.next_sequence;
+            let position = akv.insert_in_database(&key, &value, &[], FLAG_DELETE, timestamp)?;
+            akv.blob_refs.set(&key, None);
+            akv.expiries.remove(&key);
+            // The empty metadata takes up its one-byte length.
+            let length = record_length(&key, &value, &[]) + 1;
+            akv.database.insert(
+                &key,
+                IndexEntry::new(position, length, 0, timestamp, sequence),
+            );
+            akv.cache_written(&key, 0)
+        })
+    }
+
+    /// Removes `key` from the database and returns its value, or `None` if it
+    /// does not exist or has expired.  The value is read and a tombstone
+    /// dropping the key from the index written in one step, under the process
+    /// lock when the database is shared with [`Options::multi_process`], so
+    /// exactly one of several callers taking the same key gets its value.
+    /// This makes it the building block for work queues and one-time tokens.
+    pub fn take(&mut self, key: String) -> Result<Option<String>> {
+        self.locked(|akv| {
+            if !akv.contains_key(&key) {
+                return Ok(None);
+            }
+
+            let value = akv.get(key.clone())?;
+            akv.run_delete_hooks(&key)?;
+            akv.apply_repairs()?;
+            akv.write_tombstones(&[key])?;
+            Ok(Some(value))
+        })
+    }
 
-        let value = String::new();
-        let _ = self.insert_in_database(&key, &value);
+    /// Removes `keys` from the database, writing a tombstone for each in a
+    /// single append.
+    fn write_tombstones(&mut self, keys: &[String]) -> Result<()> {
+        let timestamp = now();
+        let mut records = Vec::new();
+        for key in keys {
+            let sequence = self.next_sequence;
+            ActionKV::write_record(
+                &mut records,
+                self.checksum,
+                key,
+                "",
+                &[],
+                FLAG_TOMBSTONE,
+                timestamp,
+                sequence,
+            )?;
+            self.next_sequence += 1;
+        }
+        // Tombstones follow writes already let through, or free up space.
+        self.checkpoint_if_due()?;
+        self.append_records(&records)?;
+
+        for key in keys {
+            self.blob_refs.set(key, None);
+            self.expiries.remove(key);
+            self.database.remove(key);
+            self.remove_older(key);
+            if let Some(lru) = &mut self.lru {
+                lru.get_mut().forget(key);
+            }
+        }
         Ok(())
     }
 
     /// Retrieves `key` from the database and returns is associated `value`. If
     /// the key does not exist an error is returned.
+    ///
+    /// If the record fails its checksum and [`Options::secondary`] is set, the
+    /// value is fetched from the secondary copy instead and rewritten into this
+    /// database by the next write or [`flush`](ActionKV::flush).
     pub fn get(&self, key: String) -> Result<String> {
         let position = match self.database.get(&key) {
-            Some(position) => position,
-            None => {
-                let error_message = format!("key: {key} not found in database");
-                let error = Error::new(ErrorKind::InvalidData, error_message);
+            Some(entry) if !self.is_expired(&key) => entry.position,
+            None if self.partial.is_some() && !self.is_expired(&key) => {
+                return self.get_older(&key).map(|(value, _)| value);
+            }
+            _ => {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, &key);
                 return Err(error);
             }
         };
+        self.cache_read(&key);
 
-        let akv = self.get_record_at_position(*position)?;
-        Ok(akv.value)
+        let verify = !self.options.skip_read_checksums;
+        let result = self
+            .read_record_at(position, verify)
+            .and_then(|akv| self.resolve_value(akv));
+        let value = match result {
+            Err(e) if e.kind() == ErrorKind::InvalidData => self.read_repair(&key, e)?,
+            result => result?,
+        };
+        self.run_read_hooks(&key, value)
+    }
+
+    /// Retrieves `key` from the database like [`get`](ActionKV::get), but
+    /// returns `default` instead of an error if the key does not exist or has
+    /// expired.  Other errors, such as a damaged record, are still returned.
+    pub fn get_or(&self, key: String, default: String) -> Result<String> {
+        match self.get(key) {
+            Err(e) if e.get_ref().is_some_and(|e| e.is::<KeyNotFound>()) => Ok(default),
+            result => result,
+        }
+    }
+
+    /// Retrieves `key` from the database like [`get`](ActionKV::get), along
+    /// with the metadata stored in its record.
+    pub fn get_with_meta(&self, key: String) -> Result<(String, RecordMeta)> {
+        let position = match self.database.get(&key) {
+            Some(entry) if !self.is_expired(&key) => entry.position,
+            None if self.partial.is_some() && !self.is_expired(&key) => {
+                return self.get_older(&key);
+            }
+            _ => {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, &key);
+                return Err(error);
+            }
+        };
+        self.cache_read(&key);
+
+        let akv = self.read_record_at(position, !self.options.skip_read_checksums)?;
+        let meta = RecordMeta {
+            sequence: akv.sequence,
+            timestamp: akv.timestamp,
+            user_meta: akv.user_meta.clone(),
+        };
+        let value = self.resolve_value(akv)?;
+        let value = self.run_read_hooks(&key, value)?;
+        Ok((value, meta))
+    }
+
+    /// Returns the sequence number of the most recent write, or zero for an
+    /// empty database.
+    pub fn last_sequence(&self) -> u64 {
+        self.next_sequence - 1
     }
 
     /// Creaes or updates an entry in the database with the `key` and `value`
-    /// association.
+    /// association.  An error of kind `InvalidInput` is returned if either the
+    /// key or the value is larger than the limits configured in [`Options`].
+    ///
+    /// Values larger than [`Options::blob_threshold`] are written to their own
+    /// blob file and only a reference to that file is appended to the log.
     pub fn insert(&mut self, key: String, value: String) -> Result<()> {
-        let position = self.insert_in_database(&key, &value)?;
-        self.database.insert(key, position);
-        Ok(())
+        self.insert_with_meta(key, value, Vec::new())
+    }
+
+    /// Creates or updates an entry like [`insert`](ActionKV::insert), storing
+    /// `user_meta` in the record alongside the value.  The metadata is opaque
+    /// to the database and is returned by
+    /// [`get_with_meta`](ActionKV::get_with_meta) until the key is written
+    /// again.  An error of kind `InvalidInput` is returned if `user_meta` is
+    /// longer than [`MAX_USER_META_LENGTH`] bytes.
+    pub fn insert_with_meta(
+        &mut self,
+        key: String,
+        value: String,
+        user_meta: Vec<u8>,
+    ) -> Result<()> {
+        let value = self.run_write_hooks(&key, value)?;
+        self.check_limits(&key, &value)?;
+        if user_meta.len() > MAX_USER_META_LENGTH {
+            let error_message = format!(
+                "user metadata size: {} exceeds maximum: {MAX_USER_META_LENGTH}",
+                user_meta.len()
+            );
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+        self.locked(|akv| {
+            akv.apply_repairs()?;
+            akv.write_value(key, value, &user_meta, now())
+        })
+    }
+
+    /// Writes `value` for `key` like [`insert`](ActionKV::insert) and returns
+    /// the value it replaced, or `None` if the key did not exist.  The old
+    /// value is read before anything is written, so a failed read leaves the
+    /// database unchanged.
+    pub fn insert_fetch(&mut self, key: String, value: String) -> Result<Option<String>> {
+        self.check_limits(&key, &value)?;
+        self.locked(|akv| {
+            let previous = match akv.contains_key(&key) {
+                true => Some(akv.get(key.clone())?),
+                false => None,
+            };
+            akv.insert(key, value)?;
+            Ok(previous)
+        })
+    }
+
+    /// Writes `value` for `key` only if the key does not exist yet, and
+    /// returns whether it was written.  Used through a [`SharedKV`], where the
+    /// check and the write happen under one lock, exactly one of several
+    /// callers racing for the same key wins, which makes it a building block
+    /// for leader election and one-time initialization.
+    pub fn insert_nx(&mut self, key: String, value: String) -> Result<bool> {
+        self.locked(|akv| {
+            if akv.contains_key(&key) {
+                return Ok(false);
+            }
+            akv.insert(key, value)?;
+            Ok(true)
+        })
+    }
+
+    /// Creates the entry for `key` with `value`.  Unlike
+    /// [`insert`](ActionKV::insert), an error of kind `AlreadyExists` is
+    /// returned, and nothing written, if the key already exists, so an
+    /// accidental overwrite is reported instead of clobbering the old value.
+    pub fn insert_new(&mut self, key: String, value: String) -> Result<()> {
+        self.locked(|akv| {
+            if akv.contains_key(&key) {
+                let error_message = format!("key: {key} already exists in database");
+                let error = Error::new(ErrorKind::AlreadyExists, error_message);
+                return Err(error);
+            }
+            akv.insert(key, value)
+        })
     }
 
     /// Creaes or updates an entry in the database with the `key` and `value`
@@ -80,93 +614,1005 @@ impl ActionKV {
         Ok(())
     }
 
-    /// Reads the database file located at `path` into memory.
+    /// Updates the entry for `key` with `value`.  Unlike
+    /// [`update`](ActionKV::update), an error of kind `NotFound` is returned,
+    /// and nothing written, if the key does not exist, so a mistyped key is
+    /// reported instead of silently creating a new entry.
+    pub fn update_strict(&mut self, key: String, value: String) -> Result<()> {
+        self.locked(|akv| {
+            if !akv.contains_key(&key) {
+                let error = KeyNotFound::error(ErrorKind::NotFound, &key);
+                return Err(error);
+            }
+            akv.insert(key, value)
+        })
+    }
+
+    /// Returns `true` if the database holds an entry for `key` that has not
+    /// expired.  On a database opened with [`Options::index_recent`], a key
+    /// missing from the index is looked for by scanning the older part of the
+    /// log backward, so a miss reads all of it.
+    pub fn contains_key(&self, key: &str) -> bool {
+        if self.is_expired(key) {
+            return false;
+        }
+        self.database.contains_key(key) || matches!(self.find_older(key), Ok(Some(_)))
+    }
+
+    /// Returns an iterator over every key in the database, in ascending key
+    /// order.  Expired keys are skipped.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        let now = now();
+        self.database
+            .keys()
+            .filter(move |key| !self.is_expired_at(key, now))
+    }
+
+    /// Returns an iterator over every key in the database, in descending key
+    /// order.  With keys that sort by time, such as zero-padded timestamps,
+    /// this yields the newest entries first.
+    pub fn iter_rev(&self) -> impl Iterator<Item = &str> {
+        let now = now();
+        self.database
+            .keys()
+            .rev()
+            .filter(move |key| !self.is_expired_at(key, now))
+    }
+
+    /// Returns an iterator over the keys within `range`, in ascending key
+    /// order.  The iterator is double-ended, so `range(..).rev()` walks the
+    /// range backwards and `take(n)` on it stops after `n` keys without
+    /// visiting the rest of the range:
+    ///
+    /// ```
+    /// # let mut akv = kvstore::ActionKV::open_in_memory().unwrap();
+    /// # akv.insert("session:0001".to_string(), String::new()).unwrap();
+    /// let latest: Vec<&str> = akv.range("session:".."session;").rev().take(10).collect();
+    /// ```
+    pub fn range<'a, R>(&self, range: R) -> impl DoubleEndedIterator<Item = &str>
+    where
+        R: RangeBounds<&'a str>,
+    {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let now = now();
+        self.database
+            .range((start, end))
+            .filter(move |(key, _)| !self.is_expired_at(key, now))
+            .map(|(key, _)| key)
+    }
+
+    /// Returns an iterator over every key in the database along with the
+    /// size, write time and sequence number of its value, in ascending key
+    /// order.  The metadata is kept in the index, so no values are read.
+    pub fn keys_with_meta(&self) -> impl Iterator<Item = (&str, KeyMeta)> {
+        let now = now();
+        self.database
+            .iter()
+            .filter(move |(key, _)| !self.is_expired_at(key, now))
+            .map(|(key, entry)| (key, entry.meta))
+    }
+
+    /// Returns the size, write time and sequence number of the value of
+    /// `key`.  They are kept in the index, so nothing is read from the log and
+    /// no checksum is checked; keys older than the indexed part of a database
+    /// opened with [`Options::index_recent`] are the exception.  An error is
+    /// returned if the key does not exist.
+    pub fn key_meta(&self, key: &str) -> Result<KeyMeta> {
+        match self.database.get(key) {
+            Some(entry) if !self.is_expired(key) => Ok(entry.meta),
+            None if self.partial.is_some() && !self.is_expired(key) => {
+                let (value, meta) = self.get_older(key)?;
+                let meta = KeyMeta {
+                    value_size: value.len() as u64,
+                    timestamp: meta.timestamp,
+                    sequence: meta.sequence,
+                };
+                Ok(meta)
+            }
+            _ => {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the length in bytes of the value of `key`, as stored, from the
+    /// index.  See [`key_meta`](ActionKV::key_meta).
+    pub fn value_len(&self, key: &str) -> Result<u64> {
+        self.key_meta(key).map(|meta| meta.value_size)
+    }
+
+    /// Appends a record associating `key` with `value` and `user_meta`,
+    /// stamped with `timestamp`, and updates the index to point at it.
+    fn write_value(
+        &mut self,
+        key: String,
+        value: String,
+        user_meta: &[u8],
+        timestamp: u64,
+    ) -> Result<()> {
+        let value_size = value.len() as u64;
+        let sequence = self.next_sequence;
+        let (position, length) = if self.spills(&value) {
+            let name = self.store_blob(value.as_bytes())?;
+            let position = self.insert_in_database(&key, &name, user_meta, FLAG_BLOB, timestamp)?;
+            self.blob_refs.set(&key, Some(&name));
+            (position, record_length(&key, &name, user_meta))
+        } else if let Some(delta) = self.delta_for(&key, &value) {
+            let position =
+                self.insert_in_database(&key, &delta, user_meta, FLAG_DELTA, timestamp)?;
+            self.blob_refs.set(&key, None);
+            (position, record_length(&key, &delta, user_meta))
+        } else {
+            let position = self.insert_in_database(&key, &value, user_meta, 0, timestamp)?;
+            self.blob_refs.set(&key, None);
+            (position, record_length(&key, &value, user_meta))
+        };
+
+        self.expiries.remove(&key);
+        let entry = IndexEntry::new(position, length, value_size, timestamp, sequence);
+        self.database.insert(&key, entry);
+        self.cache_written(&key, value_size)
+    }
+
+    /// Updates `key` only if it has not been written since the caller read it:
+    /// its current sequence number, as returned by
+    /// [`get_with_meta`](ActionKV::get_with_meta), must equal
+    /// `expected_sequence`.  An `expected_sequence` of zero requires that the
+    /// key does not exist yet, or has expired.  Otherwise nothing is written
+    /// and an error carrying a [`VersionMismatch`] is returned.
+    pub fn update_if_version(
+        &mut self,
+        key: String,
+        expected_sequence: u64,
+        value: String,
+    ) -> Result<()> {
+        self.locked(|akv| {
+            let actual = match akv.key_meta(&key) {
+                Ok(meta) => meta.sequence,
+                Err(e) if e.get_ref().is_some_and(|e| e.is::<KeyNotFound>()) => 0,
+                Err(e) => return Err(e),
+            };
+
+            if actual != expected_sequence {
+                let mismatch = VersionMismatch {
+                    key,
+                    expected: expected_sequence,
+                    actual,
+                };
+                return Err(Error::other(mismatch));
+            }
+
+            akv.insert(key, value)
+        })
+    }
+
+    /// Returns `true` if `value` is larger than [`Options::blob_threshold`] and
+    /// belongs in a blob file.
+    fn spills(&self, value: &str) -> bool {
+        match self.options.blob_threshold {
+            Some(threshold) => value.len() > threshold as usize,
+            None => false,
+        }
+    }
+
+    /// Verifies that `key` and `value` are within the configured size limits.
+    fn check_limits(&self, key: &str, value: &str) -> Result<()> {
+        let max_key_size = self.options.max_key_size as usize;
+        if key.len() > max_key_size {
+            let error_message = format!("key size: {} exceeds maximum: {max_key_size}", key.len());
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+
+        let max_value_size = self.options.max_value_size as usize;
+        if value.len() > max_value_size {
+            let error_message = format!(
+                "value size: {} exceeds maximum: {max_value_size}",
+                value.len()
+            );
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value held by the record `akv`, reading it from its blob
+    /// file if the value was spilled out of the log.
+    fn resolve_value(&self, akv: KeyValuePair) -> Result<String> {
+        if akv.flags & FLAG_BLOB != 0 {
+            let dir = self.blob_dir()?;
+            let value = blob::read_blob(&dir, &akv.value)?;
+            return Ok(String::from_utf8_lossy(&value).to_string());
+        }
+        if akv.flags & FLAG_DELTA != 0 {
+            return Ok(self.rebuild(akv)?.0);
+        }
+
+        Ok(akv.value)
+    }
+
+    /// Returns the directory holding this database's blob files.  A database
+    /// kept in memory has none.
+    fn blob_dir(&self) -> Result<PathBuf> {
+        self.storage.file("blob storage")?;
+        Ok(blob::blob_dir(&self.path))
+    }
+
+    /// Writes `value` to a blob file and returns the name of that file.  With
+    /// [`Options::dedup`] enabled an existing blob holding the same bytes is
+    /// reused instead of writing a second copy.
+    fn store_blob(&mut self, value: &[u8]) -> Result<String> {
+        self.check_writable()?;
+        let dir = self.blob_dir()?;
+
+        if self.options.dedup {
+            let name = blob::content_name(value);
+            if !dir.join(&name).exists() {
+                blob::write_blob(&dir, &name, value, self.options.file_mode)?;
+                return Ok(name);
+            }
+
+            // Only share the blob if it really holds the same bytes; a hash
+            // collision falls through to a uniquely named blob below.
+            if let Ok(existing) = blob::read_blob(&dir, &name) {
+                if existing == value {
+                    return Ok(name);
+                }
+            }
+        }
+
+        // Blobs are named after the log offset of the record referencing
+        // them.  Compaction moves records but keeps blob names, so an older
+        // blob may already be using this offset.  Processes sharing the file
+        // pick names under the lock.
+        self.locked(|akv| {
+            let position = akv.end;
+            let mut name = format!("{position:016x}");
+            let mut attempt = 0;
+            while dir.join(&name).exists() {
+                attempt += 1;
+                name = format!("{position:016x}-{attempt}");
+            }
+            blob::write_blob(&dir, &name, value, akv.options.file_mode)?;
+            Ok(name)
+        })
+    }
+
+    /// Reads the database file located at `path` into memory.  A record cut
+    /// short at the end of the file, left behind by an interrupted write, is
+    /// left out of the index and cut off by the next write, which replaces
+    /// it.  Checksums are verified
+    /// unless [`Options::secondary`] is set.
     fn load(&mut self) -> Result<()> {
-        let mut file = BufReader::new(&self.file);
+        // Blocks of a replaced log are stale.  A log in memory is read
+        // without syscalls to save.
+        let in_memory = matches!(self.storage, storage::Storage::Memory(_));
+        self.block_cache = match self.options.block_cache_size {
+            Some(size) if !in_memory => Some(RefCell::new(block_cache::BlockCache::new(size))),
+            _ => None,
+        };
+        let max_record_size = self.options.max_record_size;
+        if original::is_original(&mut self.storage.reader(), max_record_size)? {
+            return Err(original::error(&self.path));
+        }
+        self.checksum = checksum::read_format(&mut self.storage.reader(), max_record_size)?;
+        let start = match self.options.index_recent {
+            Some(recent) => self.load_partial(recent)?,
+            None => self.load_index()?,
+        };
+        let start = start.max(self.checksum.first_record());
+        let end = self.index_records(start)?;
+        self.database.shrink_to_fit();
+        self.check_passphrase()?;
+
+        self.end = end;
+        self.allocated = self.storage.len()?;
+
+        if self.options.direct_io && !self.read_only {
+            let file = self.storage.file("direct IO")?;
+            let direct = sys::DirectWriter::open(&self.path, file, self.end)?;
+            self.direct = Some(direct);
+        }
+        if self.end == 0 && !self.read_only {
+            self.start_log()?;
+        }
+
+        self.rebuild_lru();
+        Ok(())
+    }
+
+    /// Adds the records from offset `start` to the end of the log to the
+    /// index, and returns the offset just past the last complete record.
+    /// Checksums are verified unless [`Options::secondary`] is set.
+    fn index_records(&mut self, start: u64) -> Result<u64> {
+        // With a secondary copy to repair from, damaged records are left for
+        // `get` to detect instead of failing the open.
+        let verify = self.options.secondary.is_none().then_some(self.checksum);
+        let length = self.storage.len()?.saturating_sub(start);
+        match verify {
+            Some(checksum) if length >= verify::PARALLEL_LENGTH && verify::workers() > 1 => {
+                verify::in_parallel(checksum, |check| self.index_from(start, None, check))
+            }
+            _ => self.index_from(start, verify, &mut |_, _| {}),
+        }
+    }
+
+    /// Indexes the records from offset `start` like `index_records`, checking
+    /// checksums with `verify` if given and passing every record read to
+    /// `check`.
+    fn index_from(
+        &mut self,
+        start: u64,
+        verify: Option<Checksum>,
+        check: &mut dyn FnMut(u64, RawRecord),
+    ) -> Result<u64> {
+        let mut file = self.storage.reader();
+        let mut current_position = file.seek(SeekFrom::Start(start))?;
 
         loop {
-            let current_position = file.stream_position()?;
-            let maybe_kv = ActionKV::process_record(&mut file);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
+            let maybe_record =
+                ActionKV::read_raw_record(&mut file, self.options.max_record_size, verify);
+            let kv = match maybe_record {
+                Ok(record) => {
+                    let kv = record.decode()?;
+                    check(current_position, record);
+                    kv
+                }
                 Err(e) => match e.kind() {
                     std::io::ErrorKind::UnexpectedEof => {
+                        if !self.is_torn_tail(current_position)? {
+                            let error_message =
+                                format!("corrupt record at offset {current_position}: {e}");
+                            let error = Error::new(ErrorKind::InvalidData, error_message);
+                            return Err(error);
+                        }
                         break;
                     }
                     _ => return Err(e),
                 },
             };
-            self.database.insert(kv.key, current_position);
+            self.next_sequence = self.next_sequence.max(kv.sequence + 1);
+            let next_position = file.stream_position()?;
+
+            if let Some(length) = transaction::length(&kv)? {
+                // A transaction cut short by a crash is dropped whole, along
+                // with the record starting it.
+                if !self.is_transaction_complete(next_position, length, verify)? {
+                    break;
+                }
+            } else if kv.flags & FLAG_CHECKPOINT != 0 {
+                // The record compaction leaves with the highest sequence
+                // number has a key, and only raises `next_sequence`, above.
+                if kv.key.is_empty() {
+                    self.last_checkpoint = Some(Checkpoint {
+                        sequence: kv.sequence,
+                        timestamp: kv.timestamp,
+                        position: current_position,
+                    });
+                }
+            } else if kv.flags & FLAG_PASSPHRASE != 0 {
+                passphrase::load(&mut self.passphrase, kv.value, current_position);
+            } else if kv.flags & FLAG_EXPIRY != 0 {
+                expiry::load(&mut self.expiries, kv.key, &kv.value, current_position)?;
+            } else if kv.flags & FLAG_TOMBSTONE != 0 {
+                self.blob_refs.set(&kv.key, None);
+                self.expiries.remove(&kv.key);
+                self.database.remove(&kv.key);
+                if let Some(partial) = &mut self.partial {
+                    partial.remove(&kv.key);
+                }
+            } else if kv.flags & FLAG_LEASE != 0 {
+                lease::load(
+                    &mut self.leases,
+                    kv.key,
+                    &kv.value,
+                    kv.sequence,
+                    current_position,
+                )?;
+            } else {
+                let (blob, value_size) = match kv.flags & FLAG_BLOB {
+                    0 if kv.flags & FLAG_DELTA != 0 => (None, delta::value_size(&kv.value)?),
+                    0 => (None, kv.value.len() as u64),
+                    _ => {
+                        let dir = blob::blob_dir(&self.path);
+                        (Some(kv.value.as_str()), blob::value_size(&dir, &kv.value))
+                    }
+                };
+                self.blob_refs.set(&kv.key, blob);
+                self.expiries.remove(&kv.key);
+                let length = next_position - current_position;
+                let entry = IndexEntry::new(
+                    current_position,
+                    length,
+                    value_size,
+                    kv.timestamp,
+                    kv.sequence,
+                );
+                self.database.insert(&kv.key, entry);
+            }
+            current_position = next_position;
         }
 
-        Ok(())
+        Ok(current_position)
+    }
+
+    /// Returns `true` if the incomplete record at `position` is the tail of the
+    /// log: no complete record, with a valid header, trailer and checksum,
+    /// starts anywhere after it.  A record followed by one was damaged in the
+    /// middle of the log rather than torn by an interrupted write, even if
+    /// its length fields claim space past the end of the file.
+    fn is_torn_tail(&self, position: u64) -> Result<bool> {
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(position))?;
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest)?;
+
+        let max_record_size = self.options.max_record_size;
+        let followed = (1..rest.len()).any(|start| {
+            ActionKV::parse_raw_record(&rest[start..], max_record_size, Some(self.checksum)).is_ok()
+        });
+        Ok(!followed)
     }
 
     /// Rerieve the record stored in the database at byte offset `position`.
     fn get_record_at_position(&self, position: u64) -> Result<KeyValuePair> {
-        let mut file = std::io::BufReader::new(&self.file);
+        self.read_record_at(position, true)
+    }
+
+    /// Reads the record at `position` like `get_record_at_position`, checking
+    /// its checksum only when `verify` is set.
+    fn read_record_at(&self, position: u64, verify: bool) -> Result<KeyValuePair> {
+        self.read_raw_record_at(position, verify)?.decode()
+    }
+
+    /// Reads the record at `position` without decoding it, checking its
+    /// checksum only when `verify` is set.
+    fn read_raw_record_at(&self, position: u64, verify: bool) -> Result<RawRecord> {
+        let verify = verify.then_some(self.checksum);
+        let flushed = self.end - self.pending.len() as u64;
+        if position >= flushed {
+            let mut pending = &self.pending[(position - flushed) as usize..];
+            return ActionKV::read_raw_record(&mut pending, self.options.max_record_size, verify);
+        }
+
+        if let Some(cache) = &self.block_cache {
+            let mut file = block_cache::BlockReader::new(self, cache, position, flushed);
+            return ActionKV::read_raw_record(&mut file, self.options.max_record_size, verify);
+        }
+
+        let mut file = self.storage.reader();
         file.seek(SeekFrom::Start(position))?;
-        let akv = ActionKV::process_record(&mut file)?;
-        Ok(akv)
+        ActionKV::read_raw_record(&mut file, self.options.max_record_size, verify)
+    }
+
+    /// Calls `visit` with the offset, encoded length, and contents of every
+    /// record in the log, oldest first, including records not yet flushed.
+    /// The format record is skipped.
+    fn scan_log<F>(&self, visit: F) -> Result<()>
+    where
+        F: FnMut(u64, u64, KeyValuePair) -> Result<()>,
+    {
+        self.scan_log_from(self.checksum.first_record(), visit)
+    }
+
+    /// Calls `visit` like [`scan_log`](ActionKV::scan_log) for the records
+    /// from the one at offset `start` on.
+    fn scan_log_from<F>(&self, start: u64, mut visit: F) -> Result<()>
+    where
+        F: FnMut(u64, u64, KeyValuePair) -> Result<()>,
+    {
+        let max_record_size = self.options.max_record_size;
+        let flushed = self.end - self.pending.len() as u64;
+        let start = start.max(self.checksum.first_record());
+
+        let mut file = self.storage.reader();
+        let mut position = file.seek(SeekFrom::Start(start.min(flushed)))?;
+        while position < flushed {
+            let akv = ActionKV::process_record(&mut file, max_record_size, Some(self.checksum))?;
+            let next_position = file.stream_position()?;
+            visit(position, next_position - position, akv)?;
+            position = next_position;
+        }
+
+        let mut pending = std::io::Cursor::new(&self.pending);
+        pending.set_position(start.saturating_sub(flushed));
+        while (pending.position() as usize) < self.pending.len() {
+            let start = pending.position();
+            let akv = ActionKV::process_record(&mut pending, max_record_size, Some(self.checksum))?;
+            visit(flushed + start, pending.position() - start, akv)?;
+        }
+
+        Ok(())
     }
 
     /// Writes a new record in the database for the `key`/`value` pair.
-    fn insert_in_database(&mut self, key: &str, value: &str) -> Result<u64> {
+    fn insert_in_database(
+        &mut self,
+        key: &str,
+        value: &str,
+        user_meta: &[u8],
+        flags: u8,
+        timestamp: u64,
+    ) -> Result<u64> {
+        let sequence = self.next_sequence;
+        let mut record = Vec::new();
+        ActionKV::write_record(
+            &mut record,
+            self.checksum,
+            key,
+            value,
+            user_meta,
+            flags,
+            timestamp,
+            sequence,
+        )?;
+        self.next_sequence += 1;
+        self.append(&record)
+    }
+
+    /// Appends the encoded `records` to the log as a single write, flushing
+    /// as the sync policy requires, and returns the offset they start at.
+    /// Fails if the log is past a limit of [`Options::write_stall`], and
+    /// writes a checkpoint first if one is due.
+    fn append(&mut self, records: &[u8]) -> Result<u64> {
+        self.check_write_stall()?;
+        self.checkpoint_if_due()?;
+        self.append_records(records)
+    }
+
+    /// Appends the encoded `records` to the log like
+    /// [`append`](ActionKV::append), without checking for a due checkpoint.
+    fn append_records(&mut self, records: &[u8]) -> Result<u64> {
+        self.check_writable()?;
+        // Processes sharing the file move its end; the lock settles where.
+        self.locked(|akv| akv.write_records(records))
+    }
+
+    /// Appends the encoded `records` at `end`, holding the process lock if
+    /// there is one.
+    fn write_records(&mut self, records: &[u8]) -> Result<u64> {
+        let length = records.len() as u64;
+        let current_position = self.end;
+
+        match self.options.sync {
+            SyncPolicy::WriteBehind {
+                max_bytes,
+                max_delay,
+            } => {
+                if self.pending.is_empty() {
+                    self.pending_since = Some(Instant::now());
+                }
+                self.pending.extend_from_slice(records);
+                self.end += length;
+
+                let expired = match self.pending_since {
+                    Some(since) => since.elapsed() >= max_delay,
+                    None => false,
+                };
+                if self.pending.len() as u64 >= max_bytes || expired {
+                    self.flush()?;
+                }
+            }
+            policy => {
+                self.write_at(current_position, records)?;
+                self.end += length;
+                if policy == SyncPolicy::Always {
+                    self.storage.sync_data()?;
+                }
+            }
+        }
+
+        Ok(current_position)
+    }
+
+    /// Writes buffered records to the log and flushes the log to stable
+    /// storage, after rewriting any records recovered by read repair.  Only
+    /// [`SyncPolicy::WriteBehind`] buffers records; with the other policies
+    /// there is never anything to write.  Buffered records are also flushed
+    /// when the database is dropped.
+    pub fn flush(&mut self) -> Result<()> {
+        self.apply_repairs()?;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let start = self.end - pending.len() as u64;
+        let result = self.write_at(start, &pending);
+        if let Err(e) = result {
+            self.pending = pending;
+            return Err(e);
+        }
+
+        self.pending_since = None;
+        self.storage.sync_data()
+    }
+
+    /// Writes `bytes` to the file at offset `position`, growing the file first
+    /// if it is preallocated, or cutting off the torn record past `position`
+    /// if it is not.
+    fn write_at(&mut self, position: u64, bytes: &[u8]) -> Result<()> {
+        let padded = self.options.preallocate.is_some() || self.options.direct_io;
+        if !padded && self.allocated > position {
+            self.storage.set_len(position)?;
+            self.allocated = position;
+        }
+        self.reserve(position + bytes.len() as u64)?;
+        match &mut self.direct {
+            Some(direct) => direct.write_at(position, bytes),
+            None => self.storage.write_at(position, bytes),
+        }
+    }
+
+    /// Returns a second handle to the log file, used to flush it to stable
+    /// storage without borrowing the database.
+    fn sync_handle(&self) -> Result<File> {
+        self.storage.file("group commit")?.try_clone()
+    }
+
+    /// Makes sure the file is at least `required` bytes long.  When
+    /// [`Options::preallocate`] is set the file is grown a whole extent at a
+    /// time; otherwise writes simply extend the file.
+    fn reserve(&mut self, required: u64) -> Result<()> {
+        let extent = match self.options.preallocate {
+            Some(extent) if extent > 0 => extent,
+            _ => return Ok(()),
+        };
+
+        if required <= self.allocated {
+            return Ok(());
+        }
+
+        let allocated = required.div_ceil(extent) * extent;
+        self.storage.preallocate(allocated)?;
+        self.allocated = allocated;
+        Ok(())
+    }
+
+    /// Encodes a record for the `key`/`value` pair into `file`.  The
+    /// `checksum` covers the `flags` byte, `timestamp` and `sequence` as well
+    /// as the key and value.  A non-empty `user_meta` is stored ahead of the
+    /// value and sets [`FLAG_META`], as is an empty one when `flags` already
+    /// include it, as they do for a [`FLAG_DELETE`] record.
+    #[allow(clippy::too_many_arguments)]
+    fn write_record<W: Write>(
+        file: &mut W,
+        checksum: Checksum,
+        key: &str,
+        value: &str,
+        user_meta: &[u8],
+        flags: u8,
+        timestamp: u64,
+        sequence: u64,
+    ) -> Result<()> {
+        let flags = match user_meta.is_empty() {
+            true => flags,
+            false => flags | FLAG_META,
+        };
+        let meta_length = match flags & FLAG_META {
+            0 => 0,
+            _ => user_meta.len() + 1,
+        };
         let key_length = key.len();
-        let value_length = value.len();
+        let value_length = value.len() + meta_length;
         let data_length = key_length + value_length;
-        let mut data = Vec::with_capacity(data_length);
+        let mut data = Vec::with_capacity(data_length + 17);
+
+        data.push(flags);
+        data.extend_from_slice(&timestamp.to_be_bytes());
+        data.extend_from_slice(&sequence.to_be_bytes());
 
         for byte in key.bytes() {
             data.push(byte);
         }
 
+        if meta_length > 0 {
+            data.push(user_meta.len() as u8);
+            data.extend_from_slice(user_meta);
+        }
+
         for byte in value.bytes() {
             data.push(byte);
         }
 
-        let crc = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
-        let checksum = crc.checksum(&data);
+        let checksum = checksum.checksum(&[&data]);
 
-        let mut file = BufWriter::new(&mut self.file);
-        let next_byte = SeekFrom::End(0);
-        let current_position = file.stream_position()?;
-        file.seek(next_byte)?;
         file.write_u32::<BigEndian>(checksum)?;
         file.write_u32::<BigEndian>(key_length as u32)?;
         file.write_u32::<BigEndian>(value_length as u32)?;
         file.write_all(&data)?;
+        file.write_u32::<BigEndian>(TRAILER_MAGIC)?;
+        file.write_u32::<BigEndian>(checksum)?;
 
-        Ok(current_position)
+        Ok(())
+    }
+
+    /// Loads an entry `key`/`value` pair from the database.  Length fields
+    /// that overflow or describe a payload larger than `max_record_size` are
+    /// reported as corruption (`InvalidData`), while a payload cut short by the
+    /// end of the file or missing its trailer is reported as `UnexpectedEof`.
+    /// The checksum is only checked when `verify` gives the one to check it
+    /// with.
+    fn process_record<R: std::io::Read>(
+        file: &mut R,
+        max_record_size: u64,
+        verify: Option<Checksum>,
+    ) -> Result<KeyValuePair> {
+        ActionKV::read_raw_record(file, max_record_size, verify)?.decode()
+    }
+
+    /// Reads a record like `process_record`, leaving its key, metadata and
+    /// value undecoded.
+    fn read_raw_record<R: std::io::Read>(
+        file: &mut R,
+        max_record_size: u64,
+        verify: Option<Checksum>,
+    ) -> Result<RawRecord> {
+        let header = RecordHeader::read(file, max_record_size)?;
+
+        let data_length = header.data_length;
+        let mut data = Vec::with_capacity(data_length as usize);
+        file.by_ref().take(data_length).read_to_end(&mut data)?;
+        if data.len() as u64 != data_length {
+            let error_message = format!(
+                "truncated record: expected {data_length} bytes, found {}",
+                data.len()
+            );
+            let error = Error::new(ErrorKind::UnexpectedEof, error_message);
+            return Err(error);
+        }
+
+        header.read_trailer(file)?;
+        let record = header.record(data);
+        if let Some(checksum) = verify {
+            record.verify(checksum)?;
+        }
+        Ok(record)
+    }
+
+    /// Reads a record like `read_raw_record` from the start of `bytes`,
+    /// leaving its key, metadata and value in `bytes` rather than copying
+    /// them.
+    fn parse_raw_record(
+        bytes: &[u8],
+        max_record_size: u64,
+        verify: Option<Checksum>,
+    ) -> Result<RawRecord<&[u8]>> {
+        let mut rest = bytes;
+        let header = RecordHeader::read(&mut rest, max_record_size)?;
+
+        let data_length = header.data_length as usize;
+        if rest.len() < data_length {
+            let error_message = format!(
+                "truncated record: expected {data_length} bytes, found {}",
+                rest.len()
+            );
+            let error = Error::new(ErrorKind::UnexpectedEof, error_message);
+            return Err(error);
+        }
+        let (data, mut rest) = rest.split_at(data_length);
+
+        header.read_trailer(&mut rest)?;
+        let record = header.record(data);
+        if let Some(checksum) = verify {
+            record.verify(checksum)?;
+        }
+        Ok(record)
     }
+}
+
+/// The fixed-size header of a record, read before its key and value.
+struct RecordHeader {
+    saved_checksum: u32,
+    flags: u8,
+    timestamp: u64,
+    sequence: u64,
+    key_length: usize,
+    data_length: u64,
+}
 
-    /// Loads an entry `key`/`value` pair from the database.
-    fn process_record<R: std::io::Read>(file: &mut R) -> Result<KeyValuePair> {
+impl RecordHeader {
+    /// Reads the header of a record from `file`.  Length fields that
+    /// overflow or describe a payload larger than `max_record_size` are
+    /// reported as corruption (`InvalidData`).
+    fn read<R: std::io::Read>(file: &mut R, max_record_size: u64) -> Result<RecordHeader> {
         let saved_checksum = file.read_u32::<BigEndian>()?;
         let key_length = file.read_u32::<BigEndian>()?;
         let value_length = file.read_u32::<BigEndian>()?;
 
-        let data_length = key_length + value_length;
-        let mut data = Vec::with_capacity(data_length as usize);
-        file.by_ref()
-            .take(data_length as u64)
-            .read_to_end(&mut data)?;
+        // Preallocated space past the end of the log reads back as zeros.
+        if saved_checksum == 0 && key_length == 0 && value_length == 0 {
+            let error_message = "end of log: preallocated space";
+            let error = Error::new(ErrorKind::UnexpectedEof, error_message);
+            return Err(error);
+        }
+        let flags = file.read_u8()?;
+        let timestamp = file.read_u64::<BigEndian>()?;
+        let sequence = file.read_u64::<BigEndian>()?;
+
+        let data_length = match key_length.checked_add(value_length) {
+            Some(data_length) => data_length as u64,
+            None => {
+                let error_message = format!(
+                    "record length overflow: key_length={key_length} value_length={value_length}"
+                );
+                let error = Error::new(ErrorKind::InvalidData, error_message);
+                return Err(error);
+            }
+        };
+
+        if data_length > max_record_size {
+            let error_message =
+                format!("record size: {data_length} exceeds maximum: {max_record_size}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+
+        Ok(RecordHeader {
+            saved_checksum,
+            flags,
+            timestamp,
+            sequence,
+            key_length: key_length as usize,
+            data_length,
+        })
+    }
 
-        let crc = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
-        let checksum = crc.checksum(&data);
-        if saved_checksum != checksum {
+    /// Reads the trailer following the key and value from `file`, reporting
+    /// a record whose trailer is missing as torn (`UnexpectedEof`).
+    fn read_trailer<R: std::io::Read>(&self, file: &mut R) -> Result<()> {
+        let trailer_magic = file.read_u32::<BigEndian>()?;
+        let trailer_checksum = file.read_u32::<BigEndian>()?;
+        if trailer_magic != TRAILER_MAGIC || trailer_checksum != self.saved_checksum {
+            let error_message = "torn record: trailer missing";
+            let error = Error::new(ErrorKind::UnexpectedEof, error_message);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Returns the record with this header and its key, metadata and value
+    /// in `data`.
+    fn record<D>(&self, data: D) -> RawRecord<D> {
+        RawRecord {
+            saved_checksum: self.saved_checksum,
+            flags: self.flags,
+            timestamp: self.timestamp,
+            sequence: self.sequence,
+            key_length: self.key_length,
+            data,
+        }
+    }
+}
+
+/// A record as read from the log, with its key, user metadata and value
+/// still in one buffer.
+struct RawRecord<D = Vec<u8>> {
+    saved_checksum: u32,
+    flags: u8,
+    timestamp: u64,
+    sequence: u64,
+    key_length: usize,
+    data: D,
+}
+
+impl<D: AsRef<[u8]>> RawRecord<D> {
+    /// Checks the record against the checksum stored with it.
+    fn verify(&self, checksum: Checksum) -> Result<()> {
+        let covered: [&[u8]; 4] = [
+            &[self.flags],
+            &self.timestamp.to_be_bytes(),
+            &self.sequence.to_be_bytes(),
+            self.data.as_ref(),
+        ];
+        let checksum = checksum.checksum(&covered);
+        if self.saved_checksum != checksum {
             let error_message = format!(
                 "checksum mismatch: expected=0x{:0x} actual=0x{:0x}",
-                saved_checksum, checksum
+                self.saved_checksum, checksum
             );
             let error = Error::new(ErrorKind::InvalidData, error_message);
             return Err(error);
         }
+        Ok(())
+    }
+
+    /// Returns the offset in `data` where the value starts, past the key and
+    /// any user metadata.
+    fn value_start(&self) -> Result<usize> {
+        if self.flags & FLAG_META == 0 {
+            return Ok(self.key_length);
+        }
+        let data = self.data.as_ref();
+        let meta_length = data.get(self.key_length).map(|length| *length as usize + 1);
+        match meta_length {
+            Some(meta_length) if self.key_length + meta_length <= data.len() => {
+                Ok(self.key_length + meta_length)
+            }
+            _ => {
+                let error = Error::new(ErrorKind::InvalidData, "truncated user metadata");
+                Err(error)
+            }
+        }
+    }
 
-        let (key_data, value_data) = data.split_at(key_length as usize);
+    /// Decodes the key and value of the record.
+    fn decode(&self) -> Result<KeyValuePair> {
+        let (key_data, user_meta, value_data) = self.split()?;
         let key = String::from_utf8_lossy(key_data).to_string();
         let value = String::from_utf8_lossy(value_data).to_string();
-        let kvp = KeyValuePair { key, value };
+        let kvp = KeyValuePair {
+            flags: self.flags,
+            timestamp: self.timestamp,
+            sequence: self.sequence,
+            key,
+            value,
+            user_meta: user_meta.to_vec(),
+        };
         Ok(kvp)
     }
+
+    /// Splits `data` into the key, the user metadata and the value.
+    fn split(&self) -> Result<(&[u8], &[u8], &[u8])> {
+        let value_start = self.value_start()?;
+        let data = self.data.as_ref();
+        let key = &data[..self.key_length];
+        let user_meta = match self.flags & FLAG_META {
+            0 => &[][..],
+            _ => &data[self.key_length + 1..value_start],
+        };
+        Ok((key, user_meta, &data[value_start..]))
+    }
+}
+
+impl Drop for ActionKV {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Returns the encoded length of a record holding `key`, `value` and
+/// `user_meta`.
+fn record_length(key: &str, value: &str, user_meta: &[u8]) -> u64 {
+    HEADER_LENGTH + key.len() as u64 + value.len() as u64 + meta_length(user_meta) + TRAILER_LENGTH
+}
+
+/// Returns the bytes `user_meta` takes up in a record: nothing if it is empty,
+/// otherwise the metadata and its one-byte length.
+fn meta_length(user_meta: &[u8]) -> u64 {
+    match user_meta.len() {
+        0 => 0,
+        length => length as u64 + 1,
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch, used to stamp
+/// every record written to the database.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn now() -> u64 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// The standard library has no clock on `wasm32-unknown-unknown`, so records
+/// written there are stamped with zero.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn now() -> u64 {
+    0
+}
+
+/// Opens the log file at `path` for reading and writing, creating it with the
+/// permission bits `mode` if it does not exist.
+fn open_log(path: &Path, mode: Option<u32>) -> Result<File> {
+    sys::with_mode(&mut std::fs::OpenOptions::new(), mode)
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
 }
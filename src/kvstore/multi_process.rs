@@ -0,0 +1,164 @@
+use crate::storage::Storage;
+use crate::{compact, sys, ActionKV, LockState, Options, SyncPolicy};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// The advisory lock that processes sharing a database with
+/// [`Options::multi_process`] hold while they write.  It is taken on a file of
+/// its own next to the database, which compaction never replaces, and can be
+/// acquired again by the holder; it is released once released as many times.
+pub(crate) struct ProcessLock {
+    file: File,
+    depth: u32,
+}
+
+impl ProcessLock {
+    /// Opens the lock file of the database at `path`, creating it with the
+    /// permission bits `mode` if needed.
+    fn open(path: &Path, mode: Option<u32>) -> Result<ProcessLock> {
+        let file = sys::with_mode(&mut OpenOptions::new(), mode)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(path))?;
+        Ok(ProcessLock { file, depth: 0 })
+    }
+
+    /// Blocks until the lock is held, and returns `true` if it was not held
+    /// already.
+    fn acquire(&mut self) -> Result<bool> {
+        self.depth += 1;
+        if self.depth > 1 {
+            return Ok(false);
+        }
+        if let Err(e) = self.file.lock() {
+            self.depth -= 1;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    /// Releases one acquisition of the lock.
+    fn release(&mut self) -> Result<()> {
+        self.depth -= 1;
+        match self.depth {
+            0 => self.file.unlock(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the path of the lock file of the database at `path`: for
+/// `kvstore.db` it is `kvstore.db.lock`.
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = OsString::from(path.as_os_str());
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Opens the database at `path` for sharing with other processes, as
+/// [`Options::multi_process`] describes.  The log is loaded under the lock, so
+/// no other process is appending to it or compacting it meanwhile.
+pub(crate) fn open(path: &Path, options: Options) -> Result<ActionKV> {
+    if options.preallocate.is_some() || options.direct_io {
+        let error_message = "multi-process databases cannot be preallocated or use direct IO";
+        let error = Error::new(ErrorKind::InvalidInput, error_message);
+        return Err(error);
+    }
+    if let SyncPolicy::WriteBehind { .. } = options.sync {
+        let error_message = "multi-process databases cannot buffer writes";
+        let error = Error::new(ErrorKind::InvalidInput, error_message);
+        return Err(error);
+    }
+
+    let mut lock = ProcessLock::open(path, options.file_mode)?;
+    lock.acquire()?;
+    let opened = compact::remove_leftover(path).and_then(|()| {
+        let file = crate::open_log(path, options.file_mode)?;
+        let mut akv = ActionKV::with_storage(path.to_path_buf(), Storage::File(file), options);
+        akv.load()?;
+        Ok(akv)
+    });
+    lock.release()?;
+
+    let mut akv = opened?;
+    akv.process_lock = Some(lock);
+    Ok(akv)
+}
+
+/// Removes the temporary file left by an interrupted compaction like
+/// [`compact::remove_leftover`], for a database opened without
+/// [`Options::multi_process`].  The file is left alone while a process
+/// sharing the database holds its lock, as that process may be compacting.
+pub(crate) fn remove_leftover(path: &Path) -> Result<()> {
+    let file = match File::open(lock_path(path)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return compact::remove_leftover(path),
+        Err(e) => return Err(e),
+    };
+    match file.try_lock() {
+        Ok(()) => {
+            let removed = compact::remove_leftover(path);
+            file.unlock()?;
+            removed
+        }
+        Err(TryLockError::WouldBlock) => Ok(()),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+impl ActionKV {
+    /// Returns whether another process holds the lock of the database, as
+    /// reported by [`info`](ActionKV::info).
+    pub(crate) fn lock_state(&self) -> Result<LockState> {
+        // While this handle holds the lock, no other process can.
+        if let Some(lock) = &self.process_lock {
+            if lock.depth > 0 {
+                return Ok(LockState::Free);
+            }
+        }
+
+        let file = match File::open(lock_path(&self.path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(LockState::Unshared),
+            Err(e) => return Err(e),
+        };
+        match file.try_lock() {
+            Ok(()) => Ok(LockState::Free),
+            Err(TryLockError::WouldBlock) => Ok(LockState::Held),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// Runs `operation` while holding the lock that processes sharing the
+    /// database with [`Options::multi_process`] take for every write, after
+    /// indexing the records they appended since.  A sequence of reads and
+    /// writes run this way, such as checking that a key is missing before
+    /// inserting it, sees no other process's writes in between.  Single
+    /// writes take the lock themselves.  Without `multi_process`, `operation`
+    /// simply runs.
+    pub fn locked<T, F>(&mut self, operation: F) -> Result<T>
+    where
+        F: FnOnce(&mut ActionKV) -> Result<T>,
+    {
+        let acquired = match &mut self.process_lock {
+            Some(lock) => lock.acquire()?,
+            None => return operation(self),
+        };
+
+        let caught_up = match acquired {
+            true => self.catch_up(),
+            false => Ok(()),
+        };
+        let result = caught_up.and_then(|()| operation(self));
+        let released = match &mut self.process_lock {
+            Some(lock) => lock.release(),
+            None => Ok(()),
+        };
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,267 @@
+use crate::Checksum;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Settings that control how an [`ActionKV`](crate::ActionKV) database behaves
+/// once it is opened.  Start from `Options::default()` and override the fields
+/// you care about:
+///
+/// ```
+/// let options = kvstore::Options {
+///     max_value_size: 1024 * 1024,
+///     ..kvstore::Options::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// When written records are flushed to stable storage.
+    pub sync: SyncPolicy,
+    /// Largest key, in bytes, accepted by `insert` and `update`.
+    pub max_key_size: u32,
+    /// Largest value, in bytes, accepted by `insert` and `update`.
+    pub max_value_size: u32,
+    /// Largest record payload (key plus value), in bytes, accepted while
+    /// reading the database.  Records claiming to be larger are treated as
+    /// corruption rather than trusted with an allocation of that size.
+    pub max_record_size: u64,
+    /// Values larger than this many bytes are written to their own file in a
+    /// `<database>.blobs` directory next to the database, keeping the log
+    /// itself small.  `None` keeps every value in the log.
+    pub blob_threshold: Option<u32>,
+    /// Names blob files after a hash of their contents so that identical
+    /// values spilled by [`blob_threshold`](Options::blob_threshold) are stored
+    /// once and shared by every key holding them.
+    pub dedup: bool,
+    /// Grows the database file in extents of this many bytes instead of one
+    /// record at a time, reducing fragmentation and file-system metadata
+    /// updates under heavy writes.  `None` disables preallocation.
+    pub preallocate: Option<u64>,
+    /// Writes records with `O_DIRECT`, bypassing the page cache (Linux only).
+    /// The file is padded with zeros to a whole 4 KiB block after each write.
+    pub direct_io: bool,
+    /// After compaction and full passes over the log, advises the operating
+    /// system that the file's cached pages can be dropped, so a large database
+    /// does not evict the page cache of the services running beside it.
+    pub drop_page_cache: bool,
+    /// A replica or backup of this database.  When a record read by `get`
+    /// fails its checksum, the value is fetched from this copy and rewritten
+    /// locally, so a single bad sector does not take a key offline.  With a
+    /// secondary set, opening the database does not verify checksums, leaving
+    /// damaged records to be found and repaired by `get`.
+    pub secondary: Option<PathBuf>,
+    /// Skips checking the checksum of the record read by `get` and
+    /// `get_with_meta`, trusting the check made when the database was opened,
+    /// for read-heavy workloads.  Damage to the file after it was opened then
+    /// goes unnoticed until [`verify_all`](crate::ActionKV::verify_all) runs.
+    pub skip_read_checksums: bool,
+    /// Keeps up to this many bytes of recently read parts of the log file in
+    /// memory, in 32 KiB blocks, so reads of records written near each other
+    /// do not each go to the file.  Compaction writes keys in order, which
+    /// puts keys sharing a prefix side by side.  `None` reads every record
+    /// from the file.
+    pub block_cache_size: Option<u64>,
+    /// The checksum protecting the records of a database created with these
+    /// options.  An existing database keeps the checksum it was created with.
+    pub checksum: Checksum,
+    /// When a [`SharedKV`](crate::SharedKV) compacts the database in the
+    /// background.  `None` leaves compaction to explicit calls.
+    pub auto_compact: Option<AutoCompact>,
+    /// Writes a [checkpoint](crate::ActionKV::checkpoint) each time this many
+    /// bytes have been appended to the log since the last one, bounding how
+    /// much of the log the next open has to scan.  `None` leaves checkpoints
+    /// to explicit calls.
+    pub checkpoint_interval: Option<u64>,
+    /// How often a [`SharedKV`](crate::SharedKV) writes a
+    /// [checkpoint](crate::ActionKV::checkpoint) in the background, if
+    /// anything was written since the last one, so that a long-lived process
+    /// that dies without closing the database leaves at most this much of the
+    /// log to scan on the next open.  `None` leaves checkpoints to explicit
+    /// calls and [`checkpoint_interval`](Options::checkpoint_interval).
+    pub checkpoint_period: Option<Duration>,
+    /// How often a [`SharedKV`](crate::SharedKV) removes expired keys in the
+    /// background with [`remove_expired`](crate::ActionKV::remove_expired),
+    /// so they stop taking up space in the index and are reclaimed by the next
+    /// compaction.  `None` leaves expired keys in place until removed
+    /// explicitly or overwritten.
+    pub sweep_expired: Option<Duration>,
+    /// Turns the database into a persistent cache holding at most this many
+    /// bytes of keys and values.  Every read and write marks a key as used,
+    /// and once a write takes the total over the limit the least recently
+    /// used keys are removed.  `None` never evicts.
+    pub max_live_bytes: Option<u64>,
+    /// The passphrase to open a database protected with
+    /// [`set_passphrase`](crate::ActionKV::set_passphrase).  Ignored by
+    /// databases without one.
+    pub passphrase: Option<String>,
+    /// Permission bits, such as `0o600`, given to the database file and the
+    /// files created beside it (blobs, the saved index, compacted copies and
+    /// backups) when they are created.  Existing files keep their permissions,
+    /// and the process umask still applies.  Ignored on platforms without Unix
+    /// permissions.  `None` uses the platform default.
+    pub file_mode: Option<u32>,
+    /// Stores an update to a value of at least this many bytes as the
+    /// difference from the key's previous version when that difference is
+    /// small, so rewriting a large value with a few bytes changed only
+    /// appends those bytes.  Reads rebuild the value from the chain of
+    /// versions, which is bounded and removed by compaction.  `None` always
+    /// writes values in full.
+    pub delta_threshold: Option<u32>,
+    /// Lets several processes, such as short-lived command-line invocations
+    /// or workers, open the database for writing at once, without a server.
+    /// Each write takes an advisory lock on a `<database>.lock` file, indexes
+    /// the records other processes appended since, and releases the lock once
+    /// its own records are written; compaction holds the lock throughout.
+    /// Reads see other processes' writes after the next write or
+    /// [`refresh`](crate::ActionKV::refresh).  Cannot be combined with
+    /// [`preallocate`](Options::preallocate), [`direct_io`](Options::direct_io)
+    /// or [`SyncPolicy::WriteBehind`].
+    pub multi_process: bool,
+    /// Indexes only the records starting in the last this many bytes of the
+    /// log when the database is opened, for archives that rarely read old data
+    /// and should not pay for indexing it in open time or memory.  If no record
+    /// starts there, indexing starts at the nearest record before.  The values
+    /// of older records are not read at open, nor their checksums checked, and
+    /// the expiries, leases and tombstones among them are saved next to the
+    /// database, in `kvstore.db.older` for `kvstore.db`, so that the next open
+    /// only reads the older records appended since.  `get`, `get_with_meta`
+    /// and `contains_key` find older keys by scanning the log backward from
+    /// the indexed part, which costs a read of the log back to the key's
+    /// newest record, and of the whole older part for a key that is not
+    /// there.  Listing, counting and scanning keys
+    /// see the indexed keys only.  Compaction, copies and checkpoints, which
+    /// would leave the older keys behind, fail with an error of kind
+    /// `Unsupported`, and the option cannot be combined with
+    /// [`multi_process`](Options::multi_process),
+    /// [`auto_compact`](Options::auto_compact),
+    /// [`checkpoint_interval`](Options::checkpoint_interval) or
+    /// [`checkpoint_period`](Options::checkpoint_period).  `None` indexes the
+    /// whole log.
+    pub index_recent: Option<u64>,
+    /// Limits past which writes stall, so a flood of writes cannot grow the
+    /// log, or the writes waiting for a flush, without bound.  `None` lets
+    /// writes through whatever the state of the database.
+    pub write_stall: Option<WriteStall>,
+    /// Counts the reads and writes of every key made through this handle,
+    /// for [`top`](crate::ActionKV::top) to rank keys by.  The counts are
+    /// kept in memory from the time the database is opened.
+    pub track_access: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            sync: SyncPolicy::Never,
+            max_key_size: 64 * 1024,
+            max_value_size: 256 * 1024 * 1024,
+            max_record_size: 64 * 1024 + 256 * 1024 * 1024,
+            blob_threshold: None,
+            dedup: false,
+            preallocate: None,
+            direct_io: false,
+            drop_page_cache: false,
+            secondary: None,
+            skip_read_checksums: false,
+            block_cache_size: None,
+            checksum: Checksum::Crc32c,
+            auto_compact: None,
+            checkpoint_interval: None,
+            checkpoint_period: None,
+            sweep_expired: None,
+            max_live_bytes: None,
+            passphrase: None,
+            file_mode: None,
+            delta_threshold: None,
+            multi_process: false,
+            index_recent: None,
+            write_stall: None,
+            track_access: false,
+        }
+    }
+}
+
+/// When records written to the database are flushed to stable storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave flushing to the operating system.  A crash of the machine may
+    /// lose recently acknowledged writes.
+    Never,
+    /// Flush every write to stable storage before acknowledging it.  Writers
+    /// sharing a [`SharedKV`](crate::SharedKV) are acknowledged together by a
+    /// single flush.
+    Always,
+    /// Buffer writes in memory and flush them in batches.  Writes return at
+    /// memory speed, but a crash loses the writes that were still buffered:
+    /// at most `max_bytes` of records, appended within the last `max_delay`.
+    ///
+    /// The buffer is flushed once it holds `max_bytes`, when a write finds the
+    /// oldest buffered record older than `max_delay`, on
+    /// [`ActionKV::flush`](crate::ActionKV::flush), and when the database is
+    /// dropped.  A [`SharedKV`](crate::SharedKV) additionally runs a
+    /// background thread that flushes every `max_delay`, so the window holds
+    /// even when writes stop.
+    WriteBehind {
+        /// Largest number of bytes left unflushed.
+        max_bytes: u64,
+        /// Longest time a write is left unflushed.
+        max_delay: Duration,
+    },
+}
+
+/// When the background compactor of a [`SharedKV`](crate::SharedKV) runs, and
+/// how hard it may hit the disk.  Every `interval` the compactor checks the
+/// log and compacts it if it is at least `min_file_size` bytes long and at
+/// least `min_dead_ratio` of it is taken up by superseded records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoCompact {
+    /// Fraction of the log, between 0 and 1, that superseded records must
+    /// take up before it is compacted.
+    pub min_dead_ratio: f64,
+    /// Logs smaller than this many bytes are never compacted automatically.
+    pub min_file_size: u64,
+    /// How often the compactor checks whether to run.
+    pub interval: Duration,
+    /// Limits how fast a compaction writes the new log, in bytes per second.
+    /// Reads and writes go on while the live records are copied, so
+    /// throttling only makes the compaction take longer.  `None` writes at
+    /// full speed.
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for AutoCompact {
+    fn default() -> Self {
+        AutoCompact {
+            min_dead_ratio: 0.5,
+            min_file_size: 64 * 1024 * 1024,
+            interval: Duration::from_secs(60),
+            max_bytes_per_sec: None,
+        }
+    }
+}
+
+/// Limits past which writes to the database stall, set in
+/// [`Options::write_stall`].  A write that finds the log over a limit fails
+/// with an error of kind `WouldBlock` before appending anything; a write to a
+/// [`SharedKV`](crate::SharedKV) first waits up to `max_wait` for the
+/// background compactor, or another thread, to bring the log back under it.
+/// Compaction itself, and the tombstones written by expiry sweeps and cache
+/// eviction, are never stalled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStall {
+    /// Largest number of writes to a [`SharedKV`](crate::SharedKV), such as
+    /// those made with [`insert_async`](crate::SharedKV::insert_async), left
+    /// waiting for a flush.  A write that finds this many flushes the log
+    /// itself before going ahead.  Ignored by [`ActionKV`](crate::ActionKV)
+    /// and with [`SyncPolicy::WriteBehind`], whose `max_bytes` bounds its
+    /// buffer already.
+    pub max_unsynced_writes: Option<u64>,
+    /// Largest number of bytes of superseded records, which only compaction
+    /// reclaims, the log may hold.
+    pub max_dead_bytes: Option<u64>,
+    /// Largest size of the log in bytes.  Once it is reached, only
+    /// compaction makes room for further writes, deletes included.
+    pub max_file_size: Option<u64>,
+    /// How long a write to a [`SharedKV`](crate::SharedKV) waits for the
+    /// log to come back under the limits before failing.  Zero fails at
+    /// once.
+    pub max_wait: Duration,
+}
@@ -0,0 +1,117 @@
+use crate::checksum::CRC_32;
+use crate::{ActionKV, Options};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+/// Reads a record in the original format from `file`: a CRC-32 of the key
+/// and value, their lengths, and the key and value themselves, with no
+/// flags, timestamp, sequence number or trailer.  A record cut short by the
+/// end of the file is reported as `UnexpectedEof`.
+fn read_record<R: Read>(file: &mut R, max_record_size: u64) -> Result<(Vec<u8>, Vec<u8>)> {
+    let saved_checksum = file.read_u32::<BigEndian>()?;
+    let key_length = file.read_u32::<BigEndian>()? as u64;
+    let value_length = file.read_u32::<BigEndian>()? as u64;
+
+    let data_length = key_length + value_length;
+    if data_length > max_record_size {
+        let error_message =
+            format!("record size: {data_length} exceeds maximum: {max_record_size}");
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let mut data = Vec::with_capacity(data_length as usize);
+    file.by_ref().take(data_length).read_to_end(&mut data)?;
+    if data.len() as u64 != data_length {
+        let error_message = format!(
+            "truncated record: expected {data_length} bytes, found {}",
+            data.len()
+        );
+        let error = Error::new(ErrorKind::UnexpectedEof, error_message);
+        return Err(error);
+    }
+
+    let checksum = CRC_32.checksum(&data);
+    if saved_checksum != checksum {
+        let error_message = format!(
+            "checksum mismatch: expected=0x{:0x} actual=0x{:0x}",
+            saved_checksum, checksum
+        );
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let value = data.split_off(key_length as usize);
+    Ok((data, value))
+}
+
+/// Returns `true` if the log in `reader` starts with a record in the
+/// original format rather than the current one, whose header is 29 bytes
+/// long and whose records end with a trailer.  An empty log is in neither.
+pub(crate) fn is_original<R: Read + Seek>(reader: &mut R, max_record_size: u64) -> Result<bool> {
+    reader.seek(SeekFrom::Start(0))?;
+    let current = ActionKV::read_raw_record(reader, max_record_size, None).is_ok();
+    reader.seek(SeekFrom::Start(0))?;
+    let original = !current && read_record(reader, max_record_size).is_ok();
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(original)
+}
+
+/// Returns the error opening a log in the original format fails with.
+pub(crate) fn error(path: &Path) -> Error {
+    let error_message = format!(
+        "database: {} uses the original record format, without flags, sequence numbers or \
+         trailers; import it into a new database with `migrate --from kvstore`",
+        path.display()
+    );
+    Error::new(ErrorKind::Unsupported, error_message)
+}
+
+impl ActionKV {
+    /// Calls `visit` with the key and latest value of every entry of the
+    /// database at `path`, written in the original record format by version
+    /// 0.1 of this crate, in key order.  Such a log cannot be opened; its
+    /// entries are copied into a new database instead.  A deleted key keeps
+    /// the empty value the original format stored for it, and a record cut
+    /// short at the end of the log is ignored, as it was then.  Records are
+    /// limited to the default [`Options::max_record_size`].
+    pub fn for_each_original<F>(path: &Path, mut visit: F) -> Result<()>
+    where
+        F: FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+        let max_record_size = Options::default().max_record_size;
+        if file.get_ref().metadata()?.len() > 0 && !is_original(&mut file, max_record_size)? {
+            let error_message = format!(
+                "database: {} is not in the original record format",
+                path.display()
+            );
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+
+        let mut entries = BTreeMap::new();
+        loop {
+            let position = file.stream_position()?;
+            match read_record(&mut file, max_record_size) {
+                Ok((key, value)) => {
+                    entries.insert(key, value);
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    let error_message = format!("corrupt record at offset {position}: {e}");
+                    let error = Error::new(ErrorKind::InvalidData, error_message);
+                    return Err(error);
+                }
+            }
+        }
+
+        for (key, value) in entries {
+            visit(key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{now, ActionKV};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Bound;
+use std::str::FromStr;
+
+/// Marks where the next page of a [`scan_page`](ActionKV::scan_page) starts.
+/// A cursor converts to and from a plain string of hex digits, so it can be
+/// handed to a client and passed back with the next request; no iterator is
+/// held open in between.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    /// The last key of the previous page.
+    after: String,
+}
+
+/// The entries returned by [`scan_page`](ActionKV::scan_page), with the cursor
+/// of the following page.
+pub type Page = (Vec<(String, String)>, Option<Cursor>);
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.after.bytes() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Cursor> {
+        let invalid = || {
+            let error_message = format!("invalid cursor: {s}");
+            Error::new(ErrorKind::InvalidInput, error_message)
+        };
+
+        if !s.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let mut bytes = Vec::with_capacity(s.len() / 2);
+        for i in (0..s.len()).step_by(2) {
+            let digits = s.get(i..i + 2).ok_or_else(invalid)?;
+            let byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+            bytes.push(byte);
+        }
+
+        let after = String::from_utf8(bytes).map_err(|_| invalid())?;
+        Ok(Cursor { after })
+    }
+}
+
+impl ActionKV {
+    /// Returns up to `limit` entries whose keys start with `prefix`, in key
+    /// order, beginning after `cursor` or at the first matching key.  The
+    /// returned cursor resumes the scan where this page ended, and is `None`
+    /// once no matching keys remain.
+    ///
+    /// Keys written between pages are picked up if they sort after the
+    /// cursor; keys deleted between pages are simply not returned.
+    pub fn scan_page(&self, prefix: &str, cursor: Option<&Cursor>, limit: usize) -> Result<Page> {
+        let start = match cursor {
+            Some(cursor) if cursor.after.as_str() >= prefix => {
+                Bound::Excluded(cursor.after.as_str())
+            }
+            _ => Bound::Included(prefix),
+        };
+        let entries = self
+            .scan_from(prefix, start)
+            .take(limit)
+            .collect::<Result<Vec<_>>>()?;
+
+        let now = now();
+        let next = match entries.last() {
+            Some((last, _)) => self
+                .database
+                .range((Bound::Excluded(last.as_str()), Bound::Unbounded))
+                .map(|(key, _)| key)
+                .take_while(|key| key.starts_with(prefix))
+                .any(|key| !self.is_expired_at(key, now))
+                .then(|| Cursor {
+                    after: last.clone(),
+                }),
+            None => None,
+        };
+        Ok((entries, next))
+    }
+}
@@ -0,0 +1,580 @@
+use crate::checkpoint::{checked_body, read_string, write_string};
+use crate::expiry::Expiry;
+use crate::lease::Lease;
+use crate::passphrase::Passphrase;
+use crate::storage::Storage;
+use crate::{expiry, lease, passphrase, transaction, ActionKV, Checkpoint, KeyValuePair};
+use crate::{KeyNotFound, RawRecord, RecordHeader, RecordMeta};
+use crate::{FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE, FLAG_TOMBSTONE};
+use crate::{HEADER_LENGTH, TRAILER_LENGTH, TRAILER_MAGIC};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Bytes of the log read at a time while looking for a record to start
+/// indexing from, or while scanning the older part of the log backward.
+const SCAN_WINDOW: u64 = 1024 * 1024;
+
+/// Flags of the records that describe keys rather than hold their values.
+const METADATA_FLAGS: u8 =
+    FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_LEASE | FLAG_PASSPHRASE | FLAG_TOMBSTONE;
+
+/// Identifies a summary of the older part of the log, written by
+/// [`ActionKV::load_partial`].
+const SUMMARY_MAGIC: u32 = 0x414B_564F;
+
+/// Version of the summary file layout.
+const SUMMARY_VERSION: u8 = 1;
+
+/// The older part of the log of a database opened with
+/// [`Options::index_recent`](crate::Options::index_recent), whose records are
+/// not indexed.
+pub(crate) struct Partial {
+    /// Offset of the first indexed record.  Records before it are older.
+    boundary: u64,
+    /// Keys removed by a tombstone, whose older records no longer hold their
+    /// value.
+    removed: HashSet<String>,
+}
+
+impl Partial {
+    /// Notes that the older records of `key`, if any, were removed by a
+    /// tombstone.
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.removed.insert(key.to_string());
+    }
+}
+
+/// What the records before an offset in the log hold besides values, saved
+/// so that the next partial open only reads the records after it.
+struct Summary {
+    /// Offset of the first record not summarized.
+    boundary: u64,
+    /// Sequence number of the record at `boundary`, to tell whether the log
+    /// still holds the records summarized.
+    sequence: u64,
+    next_sequence: u64,
+    checkpoint: Option<Checkpoint>,
+    passphrase: Option<Passphrase>,
+    leases: HashMap<String, Lease>,
+    expiries: HashMap<String, Expiry>,
+    removed: HashSet<String>,
+}
+
+/// Returns the file holding the summary of the older part of the log of the
+/// database at `path`.  For a database named `kvstore.db` this is
+/// `kvstore.db.older`.
+pub(crate) fn summary_path(path: &Path) -> PathBuf {
+    let mut summary = OsString::from(path.as_os_str());
+    summary.push(".older");
+    PathBuf::from(summary)
+}
+
+/// Removes the summary of the older part of the log of the database at
+/// `path`, once it no longer describes the log.
+pub(crate) fn remove_summary(path: &Path) -> Result<()> {
+    match std::fs::remove_file(summary_path(path)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl ActionKV {
+    /// Prepares to index only the last `recent` bytes of the log, and returns
+    /// the offset to index from.  The older records are read for the
+    /// passphrase, expiries, leases and tombstones they hold, skipping their
+    /// values.  What they hold is saved next to the database, so the next open
+    /// only reads the older records appended since.  The summary is a cache,
+    /// ignored if it is damaged or the log no longer holds the records it
+    /// covers, as after compaction.
+    pub(crate) fn load_partial(&mut self, recent: u64) -> Result<u64> {
+        if self.options.multi_process
+            || self.options.auto_compact.is_some()
+            || self.options.checkpoint_interval.is_some()
+            || self.options.checkpoint_period.is_some()
+        {
+            let error_message = "a partially indexed database cannot be shared between processes, \
+                                 compacted automatically or checkpointed";
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+
+        let boundary = self.recent_start(recent)?;
+        let (start, mut removed) = match self.read_summary(boundary)? {
+            Some(summary) => (summary.boundary, self.apply_summary(summary)),
+            None => (self.checksum.first_record(), HashSet::new()),
+        };
+        self.load_older_metadata(start, boundary, &mut removed)?;
+        if boundary > start && !self.read_only {
+            self.save_summary(boundary, &removed)?;
+        }
+        self.partial = Some(Partial { boundary, removed });
+        Ok(boundary)
+    }
+
+    /// Returns an error of kind `Unsupported` for `operation` if the database
+    /// was opened with [`Options::index_recent`](crate::Options::index_recent):
+    /// writing out the index would leave the older keys behind.
+    pub(crate) fn check_fully_indexed(&self, operation: &str) -> Result<()> {
+        if self.partial.is_some() {
+            let error_message =
+                format!("{operation} is not available on a partially indexed database");
+            let error = Error::new(ErrorKind::Unsupported, error_message);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Notes that the older records of `key`, if any, were removed by a
+    /// tombstone.
+    pub(crate) fn remove_older(&mut self, key: &str) {
+        if let Some(partial) = &mut self.partial {
+            partial.remove(key);
+        }
+    }
+
+    /// Retrieves `key` like [`get_with_meta`](ActionKV::get_with_meta) from
+    /// the older part of the log of a partially indexed database.
+    pub(crate) fn get_older(&self, key: &str) -> Result<(String, RecordMeta)> {
+        let akv = match self.find_older(key)? {
+            Some(akv) => akv,
+            None => {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, key);
+                return Err(error);
+            }
+        };
+        let meta = RecordMeta {
+            sequence: akv.sequence,
+            timestamp: akv.timestamp,
+            user_meta: akv.user_meta.clone(),
+        };
+        let value = self.resolve_value(akv)?;
+        let value = self.run_read_hooks(key, value)?;
+        Ok((value, meta))
+    }
+
+    /// Returns the newest record of `key` in the older part of the log of a
+    /// partially indexed database, scanning it backward from the first
+    /// indexed record.  Returns `None` if the key has no value there.
+    pub(crate) fn find_older(&self, key: &str) -> Result<Option<KeyValuePair>> {
+        let partial = match &self.partial {
+            Some(partial) if !partial.removed.contains(key) => partial,
+            _ => return Ok(None),
+        };
+
+        let first_record = self.checksum.first_record();
+        let mut end = partial.boundary;
+        let mut window = SCAN_WINDOW;
+        let mut buffer = Vec::new();
+        while end > first_record {
+            let start = end.saturating_sub(window).max(first_record);
+            let mut file = self.storage.reader();
+            file.seek(SeekFrom::Start(start))?;
+            buffer.clear();
+            file.take(end - start).read_to_end(&mut buffer)?;
+
+            // The window may start inside a record; the records of the window
+            // are those that follow one another up to `end`.
+            let from_start = start == first_record;
+            let found = match from_start {
+                true => self.chain(&buffer, 0).map(|records| (0, records)),
+                false => (0..buffer.len())
+                    .find_map(|offset| Some((offset, self.chain(&buffer, offset)?))),
+            };
+            let (offset, records) = match found {
+                Some(found) => found,
+                None if from_start => {
+                    let error_message = format!("corrupt record before offset {end}");
+                    let error = Error::new(ErrorKind::InvalidData, error_message);
+                    return Err(error);
+                }
+                // A record longer than the window.
+                None => {
+                    window *= 2;
+                    continue;
+                }
+            };
+
+            for record in records.iter().rev() {
+                let (record_key, _, _) = record.split()?;
+                if record_key != key.as_bytes() || record.flags & METADATA_FLAGS != 0 {
+                    continue;
+                }
+                return record.decode().map(Some);
+            }
+            end = start + offset as u64;
+            window = SCAN_WINDOW;
+        }
+        Ok(None)
+    }
+
+    /// Returns the records that follow one another from `offset` in `buffer`
+    /// to its end, or `None` if a record there is damaged or ends past it.
+    fn chain<'a>(&self, buffer: &'a [u8], offset: usize) -> Option<Vec<RawRecord<&'a [u8]>>> {
+        let max_record_size = self.options.max_record_size;
+        let mut records = Vec::new();
+        let mut offset = offset;
+        while offset < buffer.len() {
+            let bytes = &buffer[offset..];
+            let (saved_checksum, record_length) = claimed_length(bytes, max_record_size)?;
+            let trailer = bytes.get(record_length as usize - TRAILER_LENGTH as usize..)?;
+            if !is_trailer(trailer, saved_checksum) {
+                return None;
+            }
+            let record = ActionKV::parse_raw_record(bytes, max_record_size, Some(self.checksum));
+            records.push(record.ok()?);
+            offset += record_length as usize;
+        }
+        Some(records)
+    }
+
+    /// Returns the offset of the first complete record starting in the last
+    /// `recent` bytes of the log or, if none does, in the bytes before them.
+    fn recent_start(&self, recent: u64) -> Result<u64> {
+        let first_record = self.checksum.first_record();
+        let length = self.storage.len()?;
+        let mut start = length.saturating_sub(recent).max(first_record);
+        let mut end = length;
+        let mut window = SCAN_WINDOW;
+        loop {
+            if let Some(position) = self.first_record_in(start, end, length)? {
+                return Ok(position);
+            }
+            if start == first_record {
+                return Ok(first_record);
+            }
+            end = start;
+            start = start.saturating_sub(window).max(first_record);
+            window *= 2;
+        }
+    }
+
+    /// Returns the offset of the first complete record starting between
+    /// `start` and `end` in the log of `length` bytes.
+    fn first_record_in(&self, start: u64, end: u64, length: u64) -> Result<Option<u64>> {
+        let mut buffer = Vec::new();
+        let mut start = start;
+        while start < end {
+            let mut file = self.storage.reader();
+            file.seek(SeekFrom::Start(start))?;
+            buffer.clear();
+            file.take(SCAN_WINDOW).read_to_end(&mut buffer)?;
+
+            // A header cut off by the end of the chunk is checked with the
+            // next one.
+            let mut scanned = buffer.len() as u64;
+            if start + scanned < length {
+                scanned -= HEADER_LENGTH.min(scanned - 1);
+            }
+            let scanned = scanned.min(end - start);
+            for offset in 0..scanned as usize {
+                let position = start + offset as u64;
+                if self.is_record_at(&buffer[offset..], position, length)? {
+                    return Ok(Some(position));
+                }
+            }
+            start += scanned;
+        }
+        Ok(None)
+    }
+
+    /// Returns `true` if a complete record whose checksum passes starts at
+    /// `position`, where the log holds `bytes` and is `length` bytes long.
+    /// The trailer is checked before the record is read in full, so most
+    /// offsets are ruled out without reading anything else.
+    fn is_record_at(&self, bytes: &[u8], position: u64, length: u64) -> Result<bool> {
+        let max_record_size = self.options.max_record_size;
+        let (saved_checksum, record_length) = match claimed_length(bytes, max_record_size) {
+            Some(claimed) if position + claimed.1 <= length => claimed,
+            _ => return Ok(false),
+        };
+
+        let verify = Some(self.checksum);
+        let trailer_start = record_length - TRAILER_LENGTH;
+        if let Some(trailer) = bytes.get(trailer_start as usize..) {
+            if !is_trailer(trailer, saved_checksum) {
+                return Ok(false);
+            }
+            let record = ActionKV::parse_raw_record(bytes, max_record_size, verify);
+            return Ok(record.is_ok());
+        }
+
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(position + trailer_start))?;
+        let mut trailer = [0; TRAILER_LENGTH as usize];
+        file.read_exact(&mut trailer)?;
+        if !is_trailer(&trailer, saved_checksum) {
+            return Ok(false);
+        }
+        file.seek(SeekFrom::Start(position))?;
+        let record = ActionKV::read_raw_record(&mut file, max_record_size, verify);
+        Ok(record.is_ok())
+    }
+
+    /// Reads the records from `start` to `boundary` for the passphrase,
+    /// expiries, leases, checkpoints and tombstones they hold, and adds the
+    /// keys removed by tombstones to `removed`.  The values of other records
+    /// are skipped unread, and checksums are not checked.
+    fn load_older_metadata(
+        &mut self,
+        start: u64,
+        boundary: u64,
+        removed: &mut HashSet<String>,
+    ) -> Result<()> {
+        let max_record_size = self.options.max_record_size;
+        let mut file = self.storage.reader();
+        let mut position = file.seek(SeekFrom::Start(start))?;
+        while position < boundary {
+            let record_start = position;
+            let header = RecordHeader::read(&mut file, max_record_size)?;
+            self.next_sequence = self.next_sequence.max(header.sequence + 1);
+            position += HEADER_LENGTH + header.data_length + TRAILER_LENGTH;
+
+            if header.flags & METADATA_FLAGS == 0 {
+                let mut key = vec![0; header.key_length];
+                file.read_exact(&mut key)?;
+                let skipped = header.data_length - header.key_length as u64 + TRAILER_LENGTH;
+                file.seek_relative(skipped as i64)?;
+                if !removed.is_empty() || !self.expiries.is_empty() {
+                    let key = String::from_utf8_lossy(&key);
+                    removed.remove(key.as_ref());
+                    self.expiries.remove(key.as_ref());
+                }
+                continue;
+            }
+
+            let mut data = vec![0; header.data_length as usize];
+            file.read_exact(&mut data)?;
+            header.read_trailer(&mut file)?;
+            let kv = header.record(data).decode()?;
+            // Only a transaction at the tail of the log can be incomplete.
+            if transaction::length(&kv)?.is_some() {
+                continue;
+            } else if kv.flags & FLAG_CHECKPOINT != 0 {
+                // The record carrying the highest sequence number has a key.
+                if kv.key.is_empty() {
+                    self.last_checkpoint = Some(Checkpoint {
+                        sequence: kv.sequence,
+                        timestamp: kv.timestamp,
+                        position: record_start,
+                    });
+                }
+            } else if kv.flags & FLAG_PASSPHRASE != 0 {
+                passphrase::load(&mut self.passphrase, kv.value, record_start);
+            } else if kv.flags & FLAG_EXPIRY != 0 {
+                expiry::load(&mut self.expiries, kv.key, &kv.value, record_start)?;
+            } else if kv.flags & FLAG_TOMBSTONE != 0 {
+                self.expiries.remove(&kv.key);
+                removed.insert(kv.key);
+            } else {
+                lease::load(
+                    &mut self.leases,
+                    kv.key,
+                    &kv.value,
+                    kv.sequence,
+                    record_start,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves what the records before `boundary` hold, as just loaded, along
+    /// with the keys `removed` by their tombstones.
+    fn save_summary(&self, boundary: u64, removed: &HashSet<String>) -> Result<()> {
+        if !matches!(self.storage, Storage::File(_)) {
+            return Ok(());
+        }
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(boundary))?;
+        let header = RecordHeader::read(&mut file, self.options.max_record_size)?;
+
+        let mut data = Vec::new();
+        data.write_u32::<BigEndian>(SUMMARY_MAGIC)?;
+        data.write_u8(SUMMARY_VERSION)?;
+        data.write_u64::<BigEndian>(boundary)?;
+        data.write_u64::<BigEndian>(header.sequence)?;
+        data.write_u64::<BigEndian>(self.next_sequence)?;
+
+        match self.last_checkpoint {
+            Some(checkpoint) => {
+                data.write_u8(1)?;
+                data.write_u64::<BigEndian>(checkpoint.sequence)?;
+                data.write_u64::<BigEndian>(checkpoint.timestamp)?;
+                data.write_u64::<BigEndian>(checkpoint.position)?;
+            }
+            None => data.write_u8(0)?,
+        }
+
+        match &self.passphrase {
+            Some(passphrase) => {
+                write_string(&mut data, &passphrase.verifier)?;
+                data.write_u64::<BigEndian>(passphrase.position)?;
+            }
+            None => {
+                write_string(&mut data, "")?;
+                data.write_u64::<BigEndian>(0)?;
+            }
+        }
+
+        data.write_u64::<BigEndian>(self.leases.len() as u64)?;
+        for (key, lease) in &self.leases {
+            write_string(&mut data, key)?;
+            data.write_u64::<BigEndian>(lease.token)?;
+            data.write_u64::<BigEndian>(lease.expires_at)?;
+            data.write_u64::<BigEndian>(lease.position)?;
+        }
+
+        data.write_u64::<BigEndian>(self.expiries.len() as u64)?;
+        for (key, expiry) in &self.expiries {
+            write_string(&mut data, key)?;
+            data.write_u64::<BigEndian>(expiry.expires_at)?;
+            data.write_u64::<BigEndian>(expiry.position)?;
+        }
+
+        data.write_u64::<BigEndian>(removed.len() as u64)?;
+        for key in removed {
+            write_string(&mut data, key)?;
+        }
+
+        self.save_file(&summary_path(&self.path), data)
+    }
+
+    /// Reads the summary saved by an earlier open, or returns `None` if there
+    /// is none, it is damaged, it covers records past `boundary`, or the log
+    /// no longer holds the records it covers.
+    fn read_summary(&self, boundary: u64) -> Result<Option<Summary>> {
+        if !matches!(self.storage, Storage::File(_)) {
+            return Ok(None);
+        }
+
+        let data = match std::fs::read(summary_path(&self.path)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let summary = match read_summary(&data) {
+            Ok(summary) if summary.boundary <= boundary => summary,
+            _ => return Ok(None),
+        };
+
+        // The summary only describes this log if the record it ends at is
+        // still there.
+        if summary.boundary >= self.storage.len()? {
+            return Ok(None);
+        }
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(summary.boundary))?;
+        let record =
+            ActionKV::process_record(&mut file, self.options.max_record_size, Some(self.checksum));
+        match record {
+            Ok(kv) if kv.sequence == summary.sequence => Ok(Some(summary)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Loads what `summary` holds, and returns the keys removed by the
+    /// tombstones it covers.
+    fn apply_summary(&mut self, summary: Summary) -> HashSet<String> {
+        self.next_sequence = self.next_sequence.max(summary.next_sequence);
+        self.last_checkpoint = summary.checkpoint;
+        self.passphrase = summary.passphrase;
+        self.leases = summary.leases;
+        self.expiries = summary.expiries;
+        summary.removed
+    }
+}
+
+/// Parses a summary file, checking its magic, version and checksum.
+fn read_summary(data: &[u8]) -> Result<Summary> {
+    let mut body = checked_body(data)?;
+    let magic = body.read_u32::<BigEndian>()?;
+    let version = body.read_u8()?;
+    if magic != SUMMARY_MAGIC || version != SUMMARY_VERSION {
+        let error_message =
+            format!("unsupported summary file: magic=0x{magic:0x} version={version}");
+        let error = Error::new(ErrorKind::InvalidData, error_message);
+        return Err(error);
+    }
+
+    let boundary = body.read_u64::<BigEndian>()?;
+    let sequence = body.read_u64::<BigEndian>()?;
+    let next_sequence = body.read_u64::<BigEndian>()?;
+    let checkpoint = match body.read_u8()? {
+        0 => None,
+        _ => Some(Checkpoint {
+            sequence: body.read_u64::<BigEndian>()?,
+            timestamp: body.read_u64::<BigEndian>()?,
+            position: body.read_u64::<BigEndian>()?,
+        }),
+    };
+
+    let mut passphrase = None;
+    let verifier = read_string(&mut body)?;
+    passphrase::load(&mut passphrase, verifier, body.read_u64::<BigEndian>()?);
+
+    let mut leases = HashMap::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        let key = read_string(&mut body)?;
+        let lease = Lease {
+            token: body.read_u64::<BigEndian>()?,
+            expires_at: body.read_u64::<BigEndian>()?,
+            position: body.read_u64::<BigEndian>()?,
+        };
+        leases.insert(key, lease);
+    }
+
+    let mut expiries = HashMap::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        let key = read_string(&mut body)?;
+        let expiry = Expiry {
+            expires_at: body.read_u64::<BigEndian>()?,
+            position: body.read_u64::<BigEndian>()?,
+        };
+        expiries.insert(key, expiry);
+    }
+
+    let mut removed = HashSet::new();
+    for _ in 0..body.read_u64::<BigEndian>()? {
+        removed.insert(read_string(&mut body)?);
+    }
+
+    Ok(Summary {
+        boundary,
+        sequence,
+        next_sequence,
+        checkpoint,
+        passphrase,
+        leases,
+        expiries,
+        removed,
+    })
+}
+
+/// Returns the checksum and the length of the record `bytes` start with, as
+/// its header claims, or `None` if the header is cut off or claims a length
+/// no record can have.
+fn claimed_length(bytes: &[u8], max_record_size: u64) -> Option<(u32, u64)> {
+    let mut header = bytes;
+    let saved_checksum = header.read_u32::<BigEndian>().ok()?;
+    let key_length = header.read_u32::<BigEndian>().ok()?;
+    let value_length = header.read_u32::<BigEndian>().ok()?;
+    let data_length = key_length as u64 + value_length as u64;
+    if saved_checksum == 0 || data_length > max_record_size {
+        return None;
+    }
+    Some((saved_checksum, HEADER_LENGTH + data_length + TRAILER_LENGTH))
+}
+
+/// Returns `true` if `bytes` start with the trailer of a record whose header
+/// holds `saved_checksum`.
+fn is_trailer(mut bytes: &[u8], saved_checksum: u32) -> bool {
+    let trailer_magic = bytes.read_u32::<BigEndian>();
+    let trailer_checksum = bytes.read_u32::<BigEndian>();
+    matches!(
+        (trailer_magic, trailer_checksum),
+        (Ok(TRAILER_MAGIC), Ok(checksum)) if checksum == saved_checksum
+    )
+}
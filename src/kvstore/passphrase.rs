@@ -0,0 +1,141 @@
+use crate::{now, ActionKV, FLAG_PASSPHRASE};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{Error, ErrorKind, Result};
+
+/// The message authenticated with the key stretched from the passphrase.
+const MAC_MESSAGE: &[u8] = b"kvstore passphrase";
+
+/// The passphrase record of a database, taken from the newest record written
+/// by [`ActionKV::set_passphrase`].
+pub(crate) struct Passphrase {
+    /// The salt and MAC, hex-encoded and separated by a colon.
+    pub(crate) verifier: String,
+    /// Offset of the record that set the passphrase.
+    pub(crate) position: u64,
+}
+
+impl ActionKV {
+    /// Requires `passphrase` to open the database from now on, or, with
+    /// `None`, removes the requirement.  Every later open has to supply the
+    /// passphrase in [`Options::passphrase`], or fails with an error of kind
+    /// `PermissionDenied`.
+    ///
+    /// The passphrase is stretched with Argon2id and checked against a MAC
+    /// stored in a record of the log, so a copy of the file cannot be opened
+    /// without it through this crate or its command line.  Values are not
+    /// encrypted, though: the protection is against casual access, not
+    /// against someone reading the file directly.
+    ///
+    /// [`Options::passphrase`]: crate::Options::passphrase
+    pub fn set_passphrase(&mut self, passphrase: Option<&str>) -> Result<()> {
+        let verifier = match passphrase {
+            Some(passphrase) => {
+                let salt = new_salt();
+                let mac = mac(passphrase, &salt)?;
+                format!("{}:{}", hex(&salt), hex(&mac))
+            }
+            None => String::new(),
+        };
+
+        self.locked(|akv| {
+            akv.apply_repairs()?;
+            let position = akv.insert_in_database("", &verifier, &[], FLAG_PASSPHRASE, now())?;
+            load(&mut akv.passphrase, verifier, position);
+            Ok(())
+        })
+    }
+
+    /// Checks [`Options::passphrase`] against the passphrase record of the
+    /// database, if it has one.
+    ///
+    /// [`Options::passphrase`]: crate::Options::passphrase
+    pub(crate) fn check_passphrase(&self) -> Result<()> {
+        let verifier = match &self.passphrase {
+            Some(passphrase) => &passphrase.verifier,
+            None => return Ok(()),
+        };
+        let passphrase = match &self.options.passphrase {
+            Some(passphrase) => passphrase,
+            None => {
+                let error_message = "database is protected by a passphrase";
+                let error = Error::new(ErrorKind::PermissionDenied, error_message);
+                return Err(error);
+            }
+        };
+
+        let (salt, saved_mac) = match verifier.split_once(':') {
+            Some((salt, mac)) => (unhex(salt)?, unhex(mac)?),
+            None => {
+                let error = Error::new(ErrorKind::InvalidData, "invalid passphrase record");
+                return Err(error);
+            }
+        };
+        let mut mac = keyed_mac(passphrase, &salt)?;
+        mac.update(MAC_MESSAGE);
+        if mac.verify_slice(&saved_mac).is_err() {
+            let error = Error::new(ErrorKind::PermissionDenied, "incorrect passphrase");
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+/// Applies a passphrase record read from the log at `position`.  A record
+/// whose value is empty removes the passphrase.
+pub(crate) fn load(passphrase: &mut Option<Passphrase>, verifier: String, position: u64) {
+    *passphrase = match verifier.is_empty() {
+        true => None,
+        false => Some(Passphrase { verifier, position }),
+    };
+}
+
+/// Returns a fresh 16-byte salt.  The salt only has to be unique, which the
+/// randomly seeded hasher of the standard library provides without pulling in
+/// an operating system random number generator.
+fn new_salt() -> Vec<u8> {
+    let mut salt = Vec::with_capacity(16);
+    for _ in 0..2 {
+        let random = RandomState::new().hash_one(now());
+        salt.extend_from_slice(&random.to_be_bytes());
+    }
+    salt
+}
+
+/// Returns the MAC stored for `passphrase` with `salt`.
+fn mac(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = keyed_mac(passphrase, salt)?;
+    mac.update(MAC_MESSAGE);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Stretches `passphrase` with `salt` into the key of an HMAC.
+fn keyed_mac(passphrase: &str, salt: &[u8]) -> Result<Hmac<Sha256>> {
+    let mut key = [0; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+    Hmac::<Sha256>::new_from_slice(&key).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+/// Formats `bytes` as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parses lowercase or uppercase hex into bytes.
+fn unhex(hex: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid passphrase record");
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            let digits = hex.get(i..i + 2).ok_or_else(invalid)?;
+            u8::from_str_radix(digits, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
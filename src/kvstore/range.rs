@@ -0,0 +1,84 @@
+use crate::{blob, ActionKV, RecordHeader, FLAG_BLOB, FLAG_DELTA, FLAG_META};
+use std::fs::File;
+use std::io::{BufReader, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+impl ActionKV {
+    /// Reads up to `length` bytes of the value of `key`, starting `offset`
+    /// bytes into it, so a header can be pulled out of a large value without
+    /// reading all of it.  The range is cut short at the end of the value, and
+    /// an offset past the end returns no bytes.  An error is returned if the
+    /// key does not exist.
+    ///
+    /// Only the requested bytes are read, from the log or from the value's
+    /// blob file, so the checksum of the record is not checked.  Values
+    /// rebuilt from deltas, values passed through read hooks and records still
+    /// buffered by [`SyncPolicy::WriteBehind`](crate::SyncPolicy::WriteBehind)
+    /// are read in full through [`get`](ActionKV::get) and then sliced.
+    pub fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let flushed = self.end - self.pending.len() as u64;
+        let position = match self.database.get(key) {
+            Some(entry) if !self.is_expired(key) && !self.has_read_hooks() => entry.position,
+            _ => return self.get_range_of_value(key, offset, length),
+        };
+        if position >= flushed {
+            return self.get_range_of_value(key, offset, length);
+        }
+
+        let mut file = self.storage.reader();
+        file.seek(SeekFrom::Start(position))?;
+        let header = RecordHeader::read(&mut file, self.options.max_record_size)?;
+        if header.flags & FLAG_DELTA != 0 {
+            return self.get_range_of_value(key, offset, length);
+        }
+        self.cache_read(key);
+
+        // The metadata, when there is any, follows the key with its length.
+        let mut value_start = header.key_length as u64;
+        file.seek_relative(header.key_length as i64)?;
+        if header.flags & FLAG_META != 0 {
+            let mut meta_length = [0; 1];
+            file.read_exact(&mut meta_length)?;
+            file.seek_relative(meta_length[0] as i64)?;
+            value_start += 1 + meta_length[0] as u64;
+        }
+        let value_length = header.data_length.saturating_sub(value_start);
+
+        if header.flags & FLAG_BLOB != 0 {
+            let mut name = String::new();
+            file.take(value_length).read_to_string(&mut name)?;
+            return read_blob_range(&self.blob_dir()?, &name, offset, length);
+        }
+
+        let offset = offset.min(value_length);
+        file.seek_relative(offset as i64)?;
+        let mut range = Vec::new();
+        file.take(length.min(value_length - offset))
+            .read_to_end(&mut range)?;
+        Ok(range)
+    }
+
+    /// Reads the range of the value of `key` for `get_range` by reading the
+    /// whole value with `get`.
+    fn get_range_of_value(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let value = self.get(key.to_string())?.into_bytes();
+        let start = (offset as usize).min(value.len());
+        let end = start.saturating_add(length as usize).min(value.len());
+        Ok(value[start..end].to_vec())
+    }
+}
+
+/// Reads up to `length` bytes of the value held by the blob file `name`
+/// inside `dir`, starting `offset` bytes into it, without checking the
+/// checksum of the blob.
+fn read_blob_range(dir: &Path, name: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let mut file = BufReader::new(File::open(dir.join(name))?);
+    let value_length = blob::value_size(dir, name);
+    let offset = offset.min(value_length);
+    // The value follows the checksum of the blob.
+    file.seek(SeekFrom::Start(4 + offset))?;
+    let mut range = Vec::new();
+    file.take(length.min(value_length - offset))
+        .read_to_end(&mut range)?;
+    Ok(range)
+}
@@ -0,0 +1,87 @@
+use crate::storage::Storage;
+use crate::{sys, ActionKV, Options};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+impl ActionKV {
+    /// Opens the database at `path` for reading only, using the supplied
+    /// `options`.  Unlike [`open_with_options`](ActionKV::open_with_options),
+    /// the file is never written, so it is safe to open while another process
+    /// appends to it: a record the writer has only half written is left
+    /// alone, and [`refresh`](ActionKV::refresh) picks it up once complete.
+    /// Every write fails with an error of kind `PermissionDenied`.  An error
+    /// of kind `NotFound` is returned if the database does not exist.
+    pub fn open_read_only(path: &Path, options: Options) -> Result<ActionKV> {
+        if !path.exists() {
+            let error_message = format!("database: {} does not exist", path.display());
+            let error = Error::new(ErrorKind::NotFound, error_message);
+            return Err(error);
+        }
+
+        let storage = Storage::File(File::open(path)?);
+        let mut akv = ActionKV::with_storage(path.to_path_buf(), storage, options);
+        akv.read_only = true;
+        akv.load()?;
+        Ok(akv)
+    }
+
+    /// Returns `true` if the database was opened with
+    /// [`open_read_only`](ActionKV::open_read_only).
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Catches up with the writes another process has made since the database
+    /// was opened or last refreshed, by indexing only the records appended
+    /// since.  If the writer compacted the database in the meantime, which
+    /// replaces the file, the new file is opened and indexed in full.
+    /// Databases neither opened read-only nor shared with
+    /// [`Options::multi_process`](crate::Options::multi_process) have nothing
+    /// to catch up with.
+    pub fn refresh(&mut self) -> Result<()> {
+        if self.process_lock.is_some() {
+            return self.locked(|_| Ok(()));
+        }
+        if !self.read_only {
+            return Ok(());
+        }
+        self.catch_up()
+    }
+
+    /// Indexes the records appended to the log by other processes, reopening
+    /// the file if it was replaced.  A writable database must hold the
+    /// process lock, and leaves a record another process left half written for
+    /// its next write to cut off.
+    pub(crate) fn catch_up(&mut self) -> Result<()> {
+        let file = self.storage.file("refresh")?;
+        // A log that was empty may since have been given a format record.
+        let replaced = !sys::is_same_file(file, &self.path)?
+            || self.storage.len()? < self.end
+            || self.end == 0;
+        if replaced {
+            let file = match self.read_only {
+                true => File::open(&self.path)?,
+                false => crate::open_log(&self.path, self.options.file_mode)?,
+            };
+            self.storage = Storage::File(file);
+            return self.reload();
+        }
+
+        self.end = self.index_records(self.end)?;
+        self.allocated = self.storage.len()?;
+        self.rebuild_lru();
+        Ok(())
+    }
+
+    /// Returns an error of kind `PermissionDenied` if the database was opened
+    /// read-only.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            let error_message = format!("database: {} is open read-only", self.path.display());
+            let error = Error::new(ErrorKind::PermissionDenied, error_message);
+            return Err(error);
+        }
+        Ok(())
+    }
+}
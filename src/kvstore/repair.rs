@@ -0,0 +1,93 @@
+use crate::{ActionKV, Options};
+use std::io::{Error, Result};
+use std::path::Path;
+
+/// A record fetched from the secondary copy that still has to be rewritten
+/// into this database.
+pub(crate) struct Repair {
+    key: String,
+    value: String,
+    user_meta: Vec<u8>,
+    timestamp: u64,
+}
+
+impl ActionKV {
+    /// Recovers `key` after reading it failed with `error`, by fetching the
+    /// value from [`Options::secondary`].  The record is queued to be
+    /// rewritten locally by the next write or flush.  If the secondary cannot
+    /// supply the key, the original `error` is returned.
+    pub(crate) fn read_repair(&self, key: &str, error: Error) -> Result<String> {
+        let secondary = match &self.options.secondary {
+            Some(secondary) => secondary,
+            None => return Err(error),
+        };
+
+        match self.fetch_from_secondary(secondary, key) {
+            Ok(Some((value, user_meta, timestamp))) => {
+                let repair = Repair {
+                    key: key.to_string(),
+                    value: value.clone(),
+                    user_meta,
+                    timestamp,
+                };
+                self.repairs.borrow_mut().push(repair);
+                Ok(value)
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Rewrites the records queued by read repair, keeping the timestamps they
+    /// had in the secondary copy.
+    pub(crate) fn apply_repairs(&mut self) -> Result<()> {
+        if self.repairs.get_mut().is_empty() {
+            return Ok(());
+        }
+        self.locked(|akv| {
+            let repairs = std::mem::take(akv.repairs.get_mut());
+            for repair in repairs {
+                // Rewriting the value would clear the key's expiry, so restore it.
+                let expiry = akv.expiries.get(&repair.key).map(|e| e.expires_at);
+                let key = repair.key.clone();
+                akv.write_value(
+                    repair.key,
+                    repair.value,
+                    &repair.user_meta,
+                    repair.timestamp,
+                )?;
+                if let Some(expires_at) = expiry {
+                    akv.write_expiry(&key, expires_at)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Reads the current value, user metadata and timestamp of `key` from the
+    /// database at `path`.
+    fn fetch_from_secondary(
+        &self,
+        path: &Path,
+        key: &str,
+    ) -> Result<Option<(String, Vec<u8>, u64)>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let options = Options {
+            secondary: None,
+            ..self.options.clone()
+        };
+        let other = ActionKV::open_with_options(path, options)?;
+        let position = match other.database.get(key) {
+            Some(entry) => entry.position,
+            None => return Ok(None),
+        };
+
+        let akv = other.get_record_at_position(position)?;
+        let timestamp = akv.timestamp;
+        let user_meta = akv.user_meta.clone();
+        let value = other.resolve_value(akv)?;
+        Ok(Some((value, user_meta, timestamp)))
+    }
+}
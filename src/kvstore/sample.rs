@@ -0,0 +1,79 @@
+use crate::{now, ActionKV};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::hash::BuildHasher;
+
+/// Random numbers drawn from the randomly seeded hasher of the standard
+/// library, which is random enough for sampling without pulling in a random
+/// number generator.
+struct Random {
+    state: RandomState,
+    counter: u64,
+}
+
+impl Random {
+    fn new() -> Random {
+        Random {
+            state: RandomState::new(),
+            counter: 0,
+        }
+    }
+
+    /// Returns a number below `bound`, which must not be zero.
+    fn below(&mut self, bound: usize) -> usize {
+        self.counter += 1;
+        (self.state.hash_one(self.counter) % bound as u64) as usize
+    }
+}
+
+impl ActionKV {
+    /// Returns `n` distinct keys chosen uniformly at random from the index,
+    /// in key order, or every key if there are no more than `n`.  Keys are
+    /// picked by their position in the index, so no record is read and the
+    /// cost grows with `n` and the number of index blocks rather than with
+    /// the size of the database.  Useful for monitoring, warming a cache, or
+    /// estimating value statistics by reading the values of a sample.
+    /// Expired keys are never returned.
+    pub fn sample(&self, n: usize) -> Vec<&str> {
+        let len = self.database.len();
+        let now = now();
+        if n >= len {
+            return self
+                .database
+                .keys()
+                .filter(|key| !self.is_expired_at(key, now))
+                .collect();
+        }
+
+        // Floyd's algorithm draws `n` distinct positions with `n` draws.
+        let mut random = Random::new();
+        let mut drawn = BTreeSet::new();
+        for bound in len - n..len {
+            let position = random.below(bound + 1);
+            if !drawn.insert(position) {
+                drawn.insert(bound);
+            }
+        }
+        let mut keys: Vec<&str> = self
+            .database
+            .nth_keys(drawn.iter().copied())
+            .into_iter()
+            .filter(|key| !self.is_expired_at(key, now))
+            .collect();
+
+        // Expired keys stay in the index until swept; each one drawn is
+        // replaced by a further draw.
+        while keys.len() < n && drawn.len() < len {
+            let position = random.below(len);
+            if !drawn.insert(position) {
+                continue;
+            }
+            let key = self.database.nth_keys([position])[0];
+            if !self.is_expired_at(key, now) {
+                keys.push(key);
+            }
+        }
+        keys.sort_unstable();
+        keys
+    }
+}
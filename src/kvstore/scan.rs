@@ -0,0 +1,131 @@
+use crate::{index, now, ActionKV, IndexEntry, Key, KeyValuePair};
+use std::io::{ErrorKind, Read, Result, Seek, SeekFrom};
+use std::ops::Bound;
+
+/// How much of the log a [`Scan`] reads at a time.
+const READ_AHEAD: u64 = 1024 * 1024;
+
+/// An iterator over the entries whose keys start with a prefix, in key order,
+/// returned by [`ActionKV::scan`].
+///
+/// The log is read ahead in large chunks and records are decoded from the
+/// chunk, so a scan over a compacted database, which holds its records in key
+/// order, reads the file sequentially instead of once per key.
+pub struct Scan<'a> {
+    akv: &'a ActionKV,
+    keys: index::Range<'a>,
+    prefix: String,
+    now: u64,
+    read_ahead: ReadAhead,
+}
+
+/// The chunk of the log read ahead by a [`Scan`] or
+/// [`for_each`](ActionKV::for_each), and the offset it starts at.
+#[derive(Default)]
+pub(crate) struct ReadAhead {
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl ActionKV {
+    /// Returns an iterator over the keys starting with `prefix` and their
+    /// values, in key order, reading the log ahead in large chunks.  Each item
+    /// is read like [`get`](ActionKV::get): expired keys are skipped, read
+    /// hooks run, and damaged records are repaired from
+    /// [`Options::secondary`](crate::Options::secondary) when it is set.
+    /// Prefer it to calling `get` for every key when reading many entries,
+    /// such as for an export.
+    pub fn scan(&self, prefix: &str) -> Scan<'_> {
+        self.scan_from(prefix, Bound::Included(prefix))
+    }
+
+    /// Returns a [`Scan`] of the [`Key`]s that begin with the parts of
+    /// `leading`, in the order of their remaining parts.  `user/42` covers
+    /// `user/42/email` but not `user/420`, whose second part differs.
+    pub fn scan_partial(&self, leading: &Key) -> Scan<'_> {
+        self.scan(leading.as_str())
+    }
+
+    /// Returns a [`Scan`] of the keys starting with `prefix` from `start`.
+    pub(crate) fn scan_from(&self, prefix: &str, start: Bound<&str>) -> Scan<'_> {
+        Scan {
+            akv: self,
+            keys: self.database.range((start, Bound::Unbounded)),
+            prefix: prefix.to_string(),
+            now: now(),
+            read_ahead: ReadAhead::default(),
+        }
+    }
+}
+
+impl Scan<'_> {
+    /// Reads the value of `key` from its record at `entry`.
+    fn read(&mut self, key: &str, entry: IndexEntry) -> Result<String> {
+        self.akv.cache_read(key);
+        let result = self
+            .read_record(entry)
+            .and_then(|akv| self.akv.resolve_value(akv));
+        let value = match result {
+            Err(e) if e.kind() == ErrorKind::InvalidData => self.akv.read_repair(key, e)?,
+            result => result?,
+        };
+        self.akv.run_read_hooks(key, value)
+    }
+
+    /// Reads the record at `entry`, from the chunk read ahead if it holds it.
+    /// Records not yet flushed are read from the write buffer.
+    fn read_record(&mut self, entry: IndexEntry) -> Result<KeyValuePair> {
+        let akv = self.akv;
+        let verify = !akv.options.skip_read_checksums;
+        let Some(mut record) = self.read_ahead.record(akv, entry)? else {
+            return akv.read_record_at(entry.position, verify);
+        };
+        let verify = verify.then_some(akv.checksum);
+        ActionKV::process_record(&mut record, akv.options.max_record_size, verify)
+    }
+}
+
+impl ReadAhead {
+    /// Returns the bytes of the log from the record at `entry` on, from the
+    /// chunk read ahead if it holds the record.  Otherwise the chunk is
+    /// refilled starting at the record.  Returns `None` for a record not yet
+    /// flushed, which is only in the write buffer.
+    pub(crate) fn record(&mut self, akv: &ActionKV, entry: IndexEntry) -> Result<Option<&[u8]>> {
+        let flushed = akv.end - akv.pending.len() as u64;
+        let end = entry.position + entry.length;
+        if end > flushed {
+            return Ok(None);
+        }
+
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if entry.position < self.buffer_start || end > buffer_end {
+            let length = READ_AHEAD.max(entry.length).min(flushed - entry.position);
+            let mut file = akv.storage.reader();
+            file.seek(SeekFrom::Start(entry.position))?;
+            self.buffer.clear();
+            file.take(length).read_to_end(&mut self.buffer)?;
+            self.buffer_start = entry.position;
+        }
+
+        let offset = (entry.position - self.buffer_start) as usize;
+        Ok(Some(&self.buffer[offset..]))
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, entry) = self.keys.next()?;
+            if !key.starts_with(&self.prefix) {
+                return None;
+            }
+            if self.akv.is_expired_at(key, self.now) {
+                continue;
+            }
+            let result = self.read(key, *entry);
+            return Some(result.map(|value| (key.to_string(), value)));
+        }
+    }
+}
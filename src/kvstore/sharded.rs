@@ -0,0 +1,119 @@
+use crate::checksum::CRC_32;
+use crate::{Options, SharedKV};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A database spread over several log files, or shards, in one directory.
+/// Each key lives in the shard picked by a hash of the key, and every shard is
+/// a [`SharedKV`] with its own lock, group commit and compaction, so writers
+/// to different shards proceed in parallel and no single file grows too
+/// large.
+///
+/// The number of shards is fixed when the directory is created and recorded in
+/// a `shards` file inside it; keys cannot be redistributed afterwards.
+#[derive(Clone)]
+pub struct ShardedKV {
+    shards: Arc<[SharedKV]>,
+}
+
+impl ShardedKV {
+    /// Opens the sharded database in the directory `dir` using the supplied
+    /// `options` for every shard, creating it with `shards` shards if it does
+    /// not exist.  An error of kind `InvalidInput` is returned if `shards` is
+    /// zero or differs from the number the directory was created with.
+    pub fn open(dir: &Path, shards: usize, options: Options) -> Result<ShardedKV> {
+        if shards == 0 {
+            let error = Error::new(ErrorKind::InvalidInput, "a sharded database needs a shard");
+            return Err(error);
+        }
+
+        std::fs::create_dir_all(dir)?;
+        let count_path = dir.join("shards");
+        match std::fs::read_to_string(&count_path) {
+            Ok(count) => {
+                let count = count.trim();
+                if count.parse::<usize>().ok() != Some(shards) {
+                    let error_message = format!(
+                        "database: {} has {count} shards, not {shards}",
+                        dir.display()
+                    );
+                    let error = Error::new(ErrorKind::InvalidInput, error_message);
+                    return Err(error);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                std::fs::write(&count_path, format!("{shards}\n"))?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let shards = (0..shards)
+            .map(|shard| SharedKV::open(&shard_path(dir, shard), options.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ShardedKV {
+            shards: shards.into(),
+        })
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the index of the shard holding `key`.
+    pub fn shard_of(&self, key: &str) -> usize {
+        CRC_32.checksum(key.as_bytes()) as usize % self.shards.len()
+    }
+
+    /// Returns the shard with the given index, for operations such as
+    /// compacting or checkpointing one shard at a time.
+    pub fn shard(&self, index: usize) -> &SharedKV {
+        &self.shards[index]
+    }
+
+    /// Retrieves the value associated with `key`.  See [`ActionKV::get`].
+    ///
+    /// [`ActionKV::get`]: crate::ActionKV::get
+    pub fn get(&self, key: String) -> Result<String> {
+        self.shards[self.shard_of(&key)].get(key)
+    }
+
+    /// Creates or updates the entry for `key`.  See [`ActionKV::insert`].
+    ///
+    /// [`ActionKV::insert`]: crate::ActionKV::insert
+    pub fn insert(&self, key: String, value: String) -> Result<()> {
+        self.shards[self.shard_of(&key)].insert(key, value)
+    }
+
+    /// Deletes the value associated with `key`.  See [`ActionKV::delete`].
+    ///
+    /// [`ActionKV::delete`]: crate::ActionKV::delete
+    pub fn delete(&self, key: String) -> Result<()> {
+        self.shards[self.shard_of(&key)].delete(key)
+    }
+
+    /// Returns every key in the database, in ascending order.
+    pub fn keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            keys.extend(shard.keys()?);
+        }
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// Compacts every shard in turn.  Only the shard being compacted is
+    /// locked, so the others keep serving reads and writes.
+    pub fn compact(&self) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.compact()?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the log file of shard `shard` in the directory `dir`.
+fn shard_path(dir: &Path, shard: usize) -> PathBuf {
+    dir.join(format!("shard-{shard:03}.db"))
+}
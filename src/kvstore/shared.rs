@@ -0,0 +1,694 @@
+use crate::compact::ProgressFn;
+use crate::{ActionKV, AutoCompact, CompactionProgress, KeyMeta, Options, SyncPolicy};
+use bytes::Bytes;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once, Weak};
+use std::time::{Duration, Instant};
+
+/// A handle to an [`ActionKV`] database that can be cloned and shared between
+/// threads.  Operations on the database are serialized by a lock.
+///
+/// With [`SyncPolicy::Always`] writes use group commit: a writer appends its
+/// record, releases the lock, and then waits for a flush covering it.  One
+/// waiting writer performs the flush on behalf of every record appended before
+/// it started, so concurrent writers share a single `fsync` instead of paying
+/// for one each, while no write is acknowledged before it is durable.  With
+/// [`SyncPolicy::WriteBehind`] a background thread flushes the buffered writes
+/// every `max_delay`.  With [`Options::auto_compact`] set, another background
+/// thread compacts the database as that policy dictates, with
+/// [`Options::sweep_expired`] set, one removes expired keys, and with
+/// [`Options::checkpoint_period`] set, one writes checkpoints.
+#[derive(Clone)]
+pub struct SharedKV {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    database: Mutex<ActionKV>,
+    commit: Mutex<Commit>,
+    committed: Condvar,
+    sync: SyncPolicy,
+    /// Starts the background thread that flushes writes made with
+    /// [`SharedKV::insert_async`].
+    syncer: Once,
+    /// Held while the database is compacted, so compactions never overlap.
+    compacting: Mutex<()>,
+}
+
+/// A write made with [`SharedKV::insert_async`], which becomes durable once
+/// the log is flushed to stable storage after it.
+pub struct DurabilityHandle {
+    shared: SharedKV,
+    ticket: u64,
+}
+
+/// Bookkeeping for group commit.  Writes are numbered in the order they are
+/// appended; `synced` is the highest numbered write known to be durable.
+struct Commit {
+    file: Arc<File>,
+    appended: u64,
+    synced: u64,
+    syncing: bool,
+}
+
+impl SharedKV {
+    /// Opens the database located at `path` using the supplied `options`.
+    pub fn open(path: &Path, options: Options) -> Result<SharedKV> {
+        // Group commit performs the flushes for `Always` itself.
+        let sync = options.sync;
+        let auto_compact = options.auto_compact;
+        let sweep_expired = options.sweep_expired;
+        let checkpoint_period = options.checkpoint_period;
+        let options = match sync {
+            SyncPolicy::Always => Options {
+                sync: SyncPolicy::Never,
+                ..options
+            },
+            _ => options,
+        };
+
+        let database = ActionKV::open_with_options(path, options)?;
+        let commit = Commit {
+            file: Arc::new(database.sync_handle()?),
+            appended: 0,
+            synced: 0,
+            syncing: false,
+        };
+
+        let inner = Inner {
+            database: Mutex::new(database),
+            commit: Mutex::new(commit),
+            committed: Condvar::new(),
+            sync,
+            syncer: Once::new(),
+            compacting: Mutex::new(()),
+        };
+        let inner = Arc::new(inner);
+
+        if let SyncPolicy::WriteBehind { max_delay, .. } = sync {
+            let weak = Arc::downgrade(&inner);
+            std::thread::spawn(move || flush_periodically(weak, max_delay));
+        }
+
+        if let Some(policy) = auto_compact {
+            let weak = Arc::downgrade(&inner);
+            std::thread::spawn(move || compact_periodically(weak, policy));
+        }
+
+        if let Some(interval) = sweep_expired {
+            let weak = Arc::downgrade(&inner);
+            std::thread::spawn(move || sweep_periodically(weak, interval));
+        }
+
+        if let Some(period) = checkpoint_period {
+            let weak = Arc::downgrade(&inner);
+            std::thread::spawn(move || checkpoint_periodically(weak, period));
+        }
+
+        Ok(SharedKV { inner })
+    }
+
+    /// Opens the database at `path` for reading only, using the supplied
+    /// `options`, to follow a database another process writes to.  See
+    /// [`ActionKV::open_read_only`].  No background threads are started, and
+    /// every write fails.
+    pub fn open_read_only(path: &Path, options: Options) -> Result<SharedKV> {
+        let database = ActionKV::open_read_only(path, options)?;
+        let commit = Commit {
+            file: Arc::new(database.sync_handle()?),
+            appended: 0,
+            synced: 0,
+            syncing: false,
+        };
+
+        let inner = Inner {
+            database: Mutex::new(database),
+            commit: Mutex::new(commit),
+            committed: Condvar::new(),
+            sync: SyncPolicy::Never,
+            syncer: Once::new(),
+            compacting: Mutex::new(()),
+        };
+        Ok(SharedKV {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Catches up with the writes another process has made since the database
+    /// was opened or last refreshed.  See [`ActionKV::refresh`].
+    pub fn refresh(&self) -> Result<()> {
+        let mut database = self.database()?;
+        database.refresh()?;
+        self.reopen_sync_handle(&database)
+    }
+
+    /// Starts a background thread that calls [`refresh`](SharedKV::refresh)
+    /// whenever the file system reports a change to the database file,
+    /// including its replacement by a compaction, so reads follow the writer
+    /// without polling.  The directory holding the database is watched until
+    /// every handle is dropped.  Requires the `notify` feature.
+    #[cfg(feature = "notify")]
+    pub fn refresh_on_change(&self) -> Result<()> {
+        use notify::{RecursiveMode, Watcher};
+
+        let path = self.database()?.path.canonicalize()?;
+        let dir = match path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => path.clone(),
+        };
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(Error::other)?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(Error::other)?;
+
+        let weak = Arc::downgrade(&self.inner);
+        std::thread::spawn(move || refresh_on_events(weak, watcher, events, path));
+        Ok(())
+    }
+
+    /// Registers a hook called before every write.  See
+    /// [`ActionKV::on_write`].
+    pub fn on_write<F>(&self, hook: F) -> Result<()>
+    where
+        F: Fn(&str, String) -> Result<String> + Send + 'static,
+    {
+        self.database()?.on_write(hook);
+        Ok(())
+    }
+
+    /// Registers a hook called after every read.  See [`ActionKV::on_read`].
+    pub fn on_read<F>(&self, hook: F) -> Result<()>
+    where
+        F: Fn(&str, String) -> Result<String> + Send + 'static,
+    {
+        self.database()?.on_read(hook);
+        Ok(())
+    }
+
+    /// Registers a hook called before every delete.  See
+    /// [`ActionKV::on_delete`].
+    pub fn on_delete<F>(&self, hook: F) -> Result<()>
+    where
+        F: Fn(&str) -> Result<()> + Send + 'static,
+    {
+        self.database()?.on_delete(hook);
+        Ok(())
+    }
+
+    /// Retrieves the value associated with `key`.  See [`ActionKV::get`].
+    pub fn get(&self, key: String) -> Result<String> {
+        self.database()?.get(key)
+    }
+
+    /// Retrieves `key` from the database, or `default` if it does not exist.
+    /// See [`ActionKV::get_or`].
+    pub fn get_or(&self, key: String, default: String) -> Result<String> {
+        self.database()?.get_or(key, default)
+    }
+
+    /// Retrieves `key` from the database without copying the value.  See
+    /// [`ActionKV::get_bytes`].
+    pub fn get_bytes(&self, key: &str) -> Result<Bytes> {
+        self.database()?.get_bytes(key)
+    }
+
+    /// Reads up to `length` bytes of the value of `key`, starting `offset`
+    /// bytes into it.  See [`ActionKV::get_range`].
+    pub fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.database()?.get_range(key, offset, length)
+    }
+
+    /// Returns the size, write time and sequence number of the value of
+    /// `key`.  See [`ActionKV::key_meta`].
+    pub fn key_meta(&self, key: &str) -> Result<KeyMeta> {
+        self.database()?.key_meta(key)
+    }
+
+    /// Returns the length in bytes of the value of `key`.  See
+    /// [`ActionKV::value_len`].
+    pub fn value_len(&self, key: &str) -> Result<u64> {
+        self.database()?.value_len(key)
+    }
+
+    /// Returns every key in the database, in ascending order.  See
+    /// [`ActionKV::keys`].
+    pub fn keys(&self) -> Result<Vec<String>> {
+        let database = self.database()?;
+        Ok(database.keys().map(str::to_string).collect())
+    }
+
+    /// Creates or updates the entry for `key`.  See [`ActionKV::insert`].
+    pub fn insert(&self, key: String, value: String) -> Result<()> {
+        self.write(|database| database.insert(key, value))
+    }
+
+    /// Creates or updates the entry for `key` like [`insert`](SharedKV::insert),
+    /// but returns as soon as the record is appended, without waiting for it
+    /// to reach stable storage.  A background thread flushes the log after
+    /// every batch of such writes, and the returned handle reports when the
+    /// flush covering this one is done, so a caller can pipeline writes and
+    /// still know exactly which are durable.  The write is visible to reads
+    /// at once.
+    ///
+    /// Returns an error of kind `InvalidInput` with
+    /// [`SyncPolicy::WriteBehind`], whose buffered writes are not in the file
+    /// for a flush to cover.
+    pub fn insert_async(&self, key: String, value: String) -> Result<DurabilityHandle> {
+        if let SyncPolicy::WriteBehind { .. } = self.inner.sync {
+            let error_message = "insert_async cannot be used with write-behind buffering";
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+
+        let ticket = self.append(|database| database.insert(key, value))?;
+        self.inner.syncer.call_once(|| {
+            let weak = Arc::downgrade(&self.inner);
+            std::thread::spawn(move || sync_in_background(weak));
+        });
+        self.inner.committed.notify_all();
+        Ok(DurabilityHandle {
+            shared: self.clone(),
+            ticket,
+        })
+    }
+
+    /// Writes `value` for `key` and returns the value it replaced, as one
+    /// atomic step.  See [`ActionKV::insert_fetch`].
+    pub fn insert_fetch(&self, key: String, value: String) -> Result<Option<String>> {
+        let mut previous = None;
+        self.write(|database| {
+            previous = database.insert_fetch(key, value)?;
+            Ok(())
+        })?;
+        Ok(previous)
+    }
+
+    /// Removes `key` and returns its value, as one atomic step.  See
+    /// [`ActionKV::take`].
+    pub fn take(&self, key: String) -> Result<Option<String>> {
+        let mut value = None;
+        self.write(|database| {
+            value = database.take(key)?;
+            Ok(())
+        })?;
+        Ok(value)
+    }
+
+    /// Writes `value` for `key` only if the key does not exist yet, and
+    /// returns whether it was written.  See [`ActionKV::insert_nx`].
+    pub fn insert_nx(&self, key: String, value: String) -> Result<bool> {
+        let mut inserted = false;
+        self.write(|database| {
+            inserted = database.insert_nx(key, value)?;
+            Ok(())
+        })?;
+        Ok(inserted)
+    }
+
+    /// Deletes the value associated with `key`.  See [`ActionKV::delete`].
+    pub fn delete(&self, key: String) -> Result<()> {
+        self.write(|database| database.delete(key))
+    }
+
+    /// Rewrites `key` with a fresh timestamp, making it expire `ttl` from now
+    /// if given.  See [`ActionKV::touch`].
+    pub fn touch(&self, key: &str, ttl: Option<Duration>) -> Result<()> {
+        self.write(|database| database.touch(key, ttl))
+    }
+
+    /// Removes every expired key and returns how many were removed.  See
+    /// [`ActionKV::remove_expired`].
+    pub fn remove_expired(&self) -> Result<u64> {
+        let mut removed = 0;
+        self.write(|database| {
+            removed = database.remove_expired()?;
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Compacts the database.  See [`ActionKV::compact`].  Reads and writes
+    /// go on while the compaction runs, as described for
+    /// [`compact_with_progress`](SharedKV::compact_with_progress).
+    pub fn compact(&self) -> Result<()> {
+        self.compact_online(None, &mut |_| ControlFlow::Continue(()))
+    }
+
+    /// Compacts the database, reporting progress to `progress`, which can
+    /// cancel the compaction.  See [`ActionKV::compact_with_progress`].
+    ///
+    /// The live records are copied from a read-only snapshot of the log,
+    /// without holding the database lock, so reads and writes go on
+    /// meanwhile.  The lock is only taken to catch the snapshot up when the
+    /// compaction starts, and at the end to copy the records written since
+    /// and switch to the compacted log, which is indexed before the lock is
+    /// released.  A database shared with [`Options::multi_process`] stays
+    /// locked throughout, since other processes write to it too.
+    pub fn compact_with_progress<F>(&self, mut progress: F) -> Result<()>
+    where
+        F: FnMut(&CompactionProgress) -> ControlFlow<()>,
+    {
+        self.compact_online(None, &mut progress)
+    }
+
+    /// Compacts the database if `policy` calls for it, throttled as the
+    /// policy allows.  Returns `true` if the database was compacted.
+    fn compact_if_needed(&self, policy: &AutoCompact) -> Result<bool> {
+        if !self.database()?.should_compact(policy) {
+            return Ok(false);
+        }
+
+        let max_bytes_per_sec = policy.max_bytes_per_sec;
+        self.compact_online(max_bytes_per_sec, &mut |_| ControlFlow::Continue(()))?;
+        Ok(true)
+    }
+
+    /// Compacts the database while it keeps taking reads and writes, as
+    /// described for [`compact_with_progress`](SharedKV::compact_with_progress),
+    /// writing the new log at no more than `max_bytes_per_sec`.
+    fn compact_online(
+        &self,
+        max_bytes_per_sec: Option<u64>,
+        progress: ProgressFn<'_>,
+    ) -> Result<()> {
+        let _compacting = self.inner.compacting.lock().map_err(|_| poisoned())?;
+        let (path, options) = {
+            let mut database = self.database()?;
+            database.check_writable()?;
+            database.check_fully_indexed("compaction")?;
+            if database.options.multi_process {
+                database.run_compaction(max_bytes_per_sec, progress)?;
+                return self.reopen_sync_handle(&database);
+            }
+            (database.path.clone(), database.options.clone())
+        };
+
+        // Opening the snapshot reads the whole log, so it is done unlocked
+        // and then caught up with the writes made meanwhile.
+        let mut snapshot = ActionKV::open_read_only(&path, options)?;
+        {
+            let mut database = self.database()?;
+            database.flush()?;
+            snapshot.refresh()?;
+        }
+        let file = snapshot.write_compacted_log(max_bytes_per_sec, progress)?;
+
+        let mut database = self.database()?;
+        database.finish_online_compaction(&snapshot, file)?;
+        self.reopen_sync_handle(&database)
+    }
+
+    /// Points group commit at the log file of `database` after compaction
+    /// replaced it.
+    fn reopen_sync_handle(&self, database: &ActionKV) -> Result<()> {
+        let mut commit = self.commit()?;
+        commit.file = Arc::new(database.sync_handle()?);
+        Ok(())
+    }
+
+    /// Runs the write `operation` under the database lock and, with
+    /// [`SyncPolicy::Always`], waits until a flush covers it.
+    fn write<F>(&self, operation: F) -> Result<()>
+    where
+        F: FnOnce(&mut ActionKV) -> Result<()>,
+    {
+        let ticket = self.append(operation)?;
+        if self.inner.sync == SyncPolicy::Always {
+            self.wait_durable(ticket)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the write `operation` under the database lock and returns the
+    /// number a flush has to cover for it to be durable.
+    fn append<F>(&self, operation: F) -> Result<u64>
+    where
+        F: FnOnce(&mut ActionKV) -> Result<()>,
+    {
+        let mut database = self.database_for_write()?;
+        operation(&mut database)?;
+        let mut commit = self.commit()?;
+        // Another process sharing the file may have replaced it by
+        // compacting.
+        if database.options.multi_process {
+            commit.file = Arc::new(database.sync_handle()?);
+        }
+        commit.appended += 1;
+        Ok(commit.appended)
+    }
+
+    /// Blocks until write number `ticket` is durable, flushing the log if no
+    /// other writer is already doing so.
+    fn wait_durable(&self, ticket: u64) -> Result<()> {
+        let mut commit = self.commit()?;
+        loop {
+            if commit.synced >= ticket {
+                return Ok(());
+            }
+
+            if commit.syncing {
+                commit = match self.inner.committed.wait(commit) {
+                    Ok(commit) => commit,
+                    Err(_) => return Err(poisoned()),
+                };
+                continue;
+            }
+
+            // Become the leader: flush everything appended so far.
+            commit.syncing = true;
+            let target = commit.appended;
+            let file = Arc::clone(&commit.file);
+            drop(commit);
+
+            let result = file.sync_data();
+
+            commit = self.commit()?;
+            commit.syncing = false;
+            if result.is_ok() {
+                commit.synced = commit.synced.max(target);
+            }
+            self.inner.committed.notify_all();
+            result?;
+        }
+    }
+
+    /// Locks the database for a write once it is within the limits of
+    /// [`Options::write_stall`], flushing the log first if too many writes
+    /// wait for a flush, and waiting up to `max_wait` for the log to come
+    /// back under its other limits before failing with `WouldBlock`.
+    fn database_for_write(&self) -> Result<MutexGuard<'_, ActionKV>> {
+        let started = Instant::now();
+        loop {
+            let database = self.database()?;
+            let Some(stall) = database.options.write_stall else {
+                return Ok(database);
+            };
+
+            if let Some(max_unsynced_writes) = stall.max_unsynced_writes {
+                let commit = self.commit()?;
+                let buffered = matches!(self.inner.sync, SyncPolicy::WriteBehind { .. });
+                if !buffered && commit.appended - commit.synced >= max_unsynced_writes {
+                    let ticket = commit.appended;
+                    drop(commit);
+                    drop(database);
+                    self.wait_durable(ticket)?;
+                    continue;
+                }
+            }
+
+            let waited = started.elapsed();
+            match database.check_write_stall() {
+                Err(e) if e.kind() == ErrorKind::WouldBlock && waited < stall.max_wait => {
+                    drop(database);
+                    std::thread::sleep(STALL_POLL.min(stall.max_wait - waited));
+                }
+                result => return result.map(|()| database),
+            }
+        }
+    }
+
+    fn database(&self) -> Result<MutexGuard<'_, ActionKV>> {
+        self.inner.database.lock().map_err(|_| poisoned())
+    }
+
+    fn commit(&self) -> Result<MutexGuard<'_, Commit>> {
+        self.inner.commit.lock().map_err(|_| poisoned())
+    }
+}
+
+impl DurabilityHandle {
+    /// Returns `true` once the write is on stable storage.
+    pub fn is_durable(&self) -> Result<bool> {
+        let commit = self.shared.commit()?;
+        Ok(commit.synced >= self.ticket)
+    }
+
+    /// Blocks until the write is on stable storage, flushing the log itself
+    /// if the background thread is not already doing so.  Returns the error
+    /// of the flush if it fails.
+    pub fn wait(&self) -> Result<()> {
+        self.shared.wait_durable(self.ticket)
+    }
+}
+
+/// How often a write stalled by [`Options::write_stall`] checks whether the
+/// log is back under its limits.
+const STALL_POLL: Duration = Duration::from_millis(10);
+
+/// How long the background thread started by [`SharedKV::insert_async`]
+/// waits for writes before checking whether every handle was dropped, and
+/// after a failed flush before trying again.
+const SYNCER_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Body of the background thread started by [`SharedKV::insert_async`].
+/// Flushes the log whenever writes are waiting to be made durable, until
+/// every handle is dropped.
+fn sync_in_background(inner: Weak<Inner>) {
+    loop {
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let target = {
+            let commit = match inner.commit.lock() {
+                Ok(commit) => commit,
+                Err(_) => return,
+            };
+            let timeout = SYNCER_TIMEOUT;
+            let waited = inner
+                .committed
+                .wait_timeout_while(commit, timeout, |commit| {
+                    commit.syncing || commit.synced >= commit.appended
+                });
+            match waited {
+                Ok((commit, _)) if !commit.syncing && commit.synced < commit.appended => {
+                    commit.appended
+                }
+                Ok(_) => continue,
+                Err(_) => return,
+            }
+        };
+        // A failed flush leaves the writes pending; the next pass, or a
+        // handle waiting on them, tries again.
+        let shared = SharedKV { inner };
+        if shared.wait_durable(target).is_err() {
+            drop(shared);
+            std::thread::sleep(SYNCER_TIMEOUT);
+        }
+    }
+}
+
+/// Body of the background flusher started for [`SyncPolicy::WriteBehind`].
+/// Flushes the database every `interval` until every handle is dropped.
+fn flush_periodically(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // A failed flush keeps the records buffered; the next write that
+        // fills the buffer retries and reports the error to its caller.
+        if let Ok(mut database) = inner.database.lock() {
+            let _ = database.flush();
+        };
+    }
+}
+
+/// Body of the background compactor started for [`Options::auto_compact`].
+/// Checks the database every `policy.interval` until every handle is dropped.
+fn compact_periodically(inner: Weak<Inner>, policy: AutoCompact) {
+    loop {
+        std::thread::sleep(policy.interval);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // A failed compaction leaves the database as it was; the next check
+        // tries again.
+        let shared = SharedKV { inner };
+        let _ = shared.compact_if_needed(&policy);
+    }
+}
+
+/// Body of the background sweeper started for [`Options::sweep_expired`].
+/// Removes expired keys every `interval` until every handle is dropped.
+fn sweep_periodically(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // Keys that fail to be removed stay expired; the next sweep tries
+        // again.
+        let shared = SharedKV { inner };
+        let _ = shared.remove_expired();
+    }
+}
+
+/// Body of the background checkpointer started for
+/// [`Options::checkpoint_period`].  Writes a checkpoint every `period` in which
+/// the database was written to, until every handle is dropped.
+fn checkpoint_periodically(inner: Weak<Inner>, period: Duration) {
+    loop {
+        std::thread::sleep(period);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // A failed checkpoint leaves the previous one in place; the next
+        // period tries again.
+        if let Ok(mut database) = inner.database.lock() {
+            let _ = database.checkpoint_if_written();
+        };
+    }
+}
+
+/// Body of the watcher started by [`SharedKV::refresh_on_change`].  Refreshes
+/// the database after every batch of file system events touching `path`, and
+/// returns once every handle is dropped.  `watcher` is kept alive for as long
+/// as events are wanted.
+#[cfg(feature = "notify")]
+fn refresh_on_events(
+    inner: Weak<Inner>,
+    watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    path: std::path::PathBuf,
+) {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let touches = |event: notify::Result<notify::Event>| match event {
+        Ok(event) => event.paths.contains(&path),
+        Err(_) => false,
+    };
+    loop {
+        let changed = match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => touches(event),
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => break,
+        };
+        if !changed {
+            continue;
+        }
+        // One refresh catches up with every event already queued.
+        let _ = events.try_iter().count();
+        // A failed refresh is retried on the next change.
+        let shared = SharedKV { inner };
+        let _ = shared.refresh();
+    }
+    drop(watcher);
+}
+
+/// The error returned once a thread panicked while holding a lock.
+fn poisoned() -> Error {
+    Error::other("database lock poisoned by a panicked thread")
+}
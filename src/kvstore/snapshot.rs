@@ -0,0 +1,131 @@
+use crate::{
+    ActionKV, KeyNotFound, FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE,
+    FLAG_TOMBSTONE,
+};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// A consistent, read-only view of an [`ActionKV`] database as it was right
+/// after the write with a given sequence number.  Created by
+/// [`ActionKV::read_at`].
+pub struct ReadView<'a> {
+    akv: &'a ActionKV,
+    sequence: u64,
+    index: HashMap<String, u64>,
+}
+
+impl ActionKV {
+    /// Opens a read view of the database as of sequence number `sequence`.
+    /// Every key resolves to its newest record written at or before that
+    /// sequence, so several keys can be read consistently while the view is
+    /// held.  The log is scanned to build the view.
+    ///
+    /// Compaction discards superseded records, so a view of a sequence older
+    /// than the last compaction only sees the versions that survived it.
+    pub fn read_at(&self, sequence: u64) -> Result<ReadView<'_>> {
+        // A tombstone is the newest record of a removed key, with no position.
+        let mut newest: HashMap<String, (u64, Option<u64>)> = HashMap::new();
+        self.scan_log(|position, _, akv| {
+            let skipped = FLAG_LEASE | FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_PASSPHRASE;
+            if akv.sequence > sequence || akv.flags & skipped != 0 {
+                return Ok(());
+            }
+            let newer = match newest.get(&akv.key) {
+                Some((seen, _)) => akv.sequence > *seen,
+                None => true,
+            };
+            if newer {
+                let position = (akv.flags & FLAG_TOMBSTONE == 0).then_some(position);
+                newest.insert(akv.key, (akv.sequence, position));
+            }
+            Ok(())
+        })?;
+
+        let index = newest
+            .into_iter()
+            .filter_map(|(key, (_, position))| Some((key, position?)))
+            .collect();
+        Ok(ReadView {
+            akv: self,
+            sequence,
+            index,
+        })
+    }
+
+    /// Retrieves the value `key` held at `timestamp`, in milliseconds since
+    /// the Unix epoch: the value of its newest record written at or before
+    /// that time.  An error is returned if the key did not exist then, or had
+    /// been removed.  The log is scanned to find the key's history, so only
+    /// versions still in the log can be returned: compaction discards the
+    /// values overwritten before it ran.  Expiries are not taken into
+    /// account.
+    pub fn get_as_of(&self, key: String, timestamp: u64) -> Result<String> {
+        // A tombstone is the newest record of a removed key, with no position.
+        let mut newest: Option<(u64, Option<u64>)> = None;
+        self.scan_log(|position, _, akv| {
+            let skipped = FLAG_LEASE | FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_PASSPHRASE;
+            if akv.key != key || akv.timestamp > timestamp || akv.flags & skipped != 0 {
+                return Ok(());
+            }
+            let newer = match newest {
+                Some((seen, _)) => akv.sequence > seen,
+                None => true,
+            };
+            if newer {
+                let position = (akv.flags & FLAG_TOMBSTONE == 0).then_some(position);
+                newest = Some((akv.sequence, position));
+            }
+            Ok(())
+        })?;
+
+        let position = match newest {
+            Some((_, Some(position))) => position,
+            _ => {
+                let not_found = KeyNotFound {
+                    key: key.to_string(),
+                    as_of: Some(timestamp),
+                };
+                let error = Error::new(ErrorKind::InvalidData, not_found);
+                return Err(error);
+            }
+        };
+
+        let akv = self.get_record_at_position(position)?;
+        let value = self.resolve_value(akv)?;
+        self.run_read_hooks(&key, value)
+    }
+}
+
+impl ReadView<'_> {
+    /// Returns the sequence number this view was opened at.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Retrieves the value `key` had as of this view's sequence number.  If
+    /// the key did not exist yet an error is returned.
+    pub fn get(&self, key: String) -> Result<String> {
+        let position = match self.index.get(&key) {
+            Some(position) => position,
+            None => {
+                let error = KeyNotFound::error(ErrorKind::InvalidData, &key);
+                return Err(error);
+            }
+        };
+
+        let akv = self.akv.get_record_at_position(*position)?;
+        let value = self.akv.resolve_value(akv)?;
+        self.akv.run_read_hooks(&key, value)
+    }
+
+    /// Returns `true` if `key` existed as of this view's sequence number.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Returns an iterator over every key that existed as of this view's
+    /// sequence number, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.index.keys().map(|key| key.as_str())
+    }
+}
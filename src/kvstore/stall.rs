@@ -0,0 +1,38 @@
+use crate::ActionKV;
+use std::io::{Error, ErrorKind, Result};
+
+impl ActionKV {
+    /// Fails with an error of kind `WouldBlock` if the log is past a limit
+    /// of [`Options::write_stall`](crate::Options::write_stall), so that the
+    /// write about to be appended is refused.
+    pub(crate) fn check_write_stall(&self) -> Result<()> {
+        let Some(stall) = &self.options.write_stall else {
+            return Ok(());
+        };
+
+        if let Some(max_file_size) = stall.max_file_size {
+            if self.end >= max_file_size {
+                let error_message = format!(
+                    "write stalled: the log is {} bytes, at its limit of {}",
+                    self.end, max_file_size
+                );
+                let error = Error::new(ErrorKind::WouldBlock, error_message);
+                return Err(error);
+            }
+        }
+
+        if let Some(max_dead_bytes) = stall.max_dead_bytes {
+            let dead = self.end.saturating_sub(self.database.live_length());
+            if dead >= max_dead_bytes {
+                let error_message = format!(
+                    "write stalled: {} bytes of superseded records await compaction, at the limit of {}",
+                    dead, max_dead_bytes
+                );
+                let error = Error::new(ErrorKind::WouldBlock, error_message);
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{now, ActionKV};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+/// How value sizes are distributed across the database, as reported by
+/// [`ActionKV::value_size_stats`].
+#[derive(Debug, Default)]
+pub struct ValueSizeStats {
+    /// Number of keys in the database.
+    pub keys: u64,
+    /// Combined size of every value, in bytes.
+    pub total_bytes: u64,
+    /// Number of values in each size class, keyed by the smallest size in the
+    /// class: zero, then powers of two.  A class holds the sizes below twice
+    /// its lower bound.
+    pub histogram: BTreeMap<u64, u64>,
+    /// The keys holding the largest values with their sizes, largest first
+    /// and then in key order.
+    pub largest: Vec<(String, u64)>,
+}
+
+impl ActionKV {
+    /// Reports the distribution of value sizes and the `top` keys with the
+    /// largest values, to help find what is taking up space.  The sizes are
+    /// kept in the index as records are loaded and written, so no values are
+    /// read.
+    pub fn value_size_stats(&self, top: usize) -> ValueSizeStats {
+        let mut stats = ValueSizeStats::default();
+        let mut largest = BinaryHeap::with_capacity(top + 1);
+
+        let now = now();
+        for (key, entry) in &self.database {
+            if self.is_expired_at(key, now) {
+                continue;
+            }
+            let size = entry.meta.value_size;
+            stats.keys += 1;
+            stats.total_bytes += size;
+            *stats.histogram.entry(size_class(size)).or_insert(0) += 1;
+
+            // A min-heap of the largest values seen so far.
+            largest.push(Reverse((size, key)));
+            if largest.len() > top {
+                largest.pop();
+            }
+        }
+
+        let mut largest: Vec<(u64, &str)> = largest
+            .into_iter()
+            .map(|Reverse(largest)| largest)
+            .collect();
+        largest.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        stats.largest = largest
+            .into_iter()
+            .map(|(size, key)| (key.to_string(), size))
+            .collect();
+        stats
+    }
+}
+
+/// Returns the lower bound of the size class holding `size`.
+fn size_class(size: u64) -> u64 {
+    match size {
+        0 => 0,
+        size => 1 << size.ilog2(),
+    }
+}
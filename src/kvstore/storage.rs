@@ -0,0 +1,209 @@
+//! The medium holding the log of a database: a file on disk, a buffer in
+//! memory where there is no file system, or a stream supplied by the embedder.
+
+use crate::sys;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+
+/// Any seekable byte stream that can hold the log, as accepted by
+/// [`ActionKV::from_storage`](crate::ActionKV::from_storage).
+pub(crate) trait Stream: Read + Write + Seek + Send {}
+
+impl<T: Read + Write + Seek + Send> Stream for T {}
+
+/// Where the log of an [`ActionKV`](crate::ActionKV) database is kept.
+pub(crate) enum Storage {
+    /// The database file opened by `open`.
+    File(File),
+    /// A buffer created by `open_in_memory`.  The log is lost when the
+    /// database is dropped.
+    Memory(Vec<u8>),
+    /// A stream passed to `from_storage`.  Reads go through `&self`, so the
+    /// stream sits in a `RefCell` and is only borrowed for a single call.
+    Stream(RefCell<Box<dyn Stream>>),
+}
+
+/// Reads the log from any offset; created by [`Storage::reader`].
+pub(crate) enum StorageReader<'a> {
+    File(&'a File),
+    Memory(Cursor<&'a [u8]>),
+    Stream {
+        stream: &'a RefCell<Box<dyn Stream>>,
+        position: u64,
+    },
+}
+
+impl Storage {
+    /// Returns a buffered reader over the log.  Callers seek to the offset
+    /// they want before reading.
+    pub(crate) fn reader(&self) -> BufReader<StorageReader<'_>> {
+        let reader = match self {
+            Storage::File(file) => StorageReader::File(file),
+            Storage::Memory(buffer) => StorageReader::Memory(Cursor::new(buffer)),
+            Storage::Stream(stream) => StorageReader::Stream {
+                stream,
+                position: 0,
+            },
+        };
+        BufReader::new(reader)
+    }
+
+    /// Returns the length of the log, including any preallocated space.
+    pub(crate) fn len(&self) -> Result<u64> {
+        match self {
+            Storage::File(file) => Ok(file.metadata()?.len()),
+            Storage::Memory(buffer) => Ok(buffer.len() as u64),
+            Storage::Stream(stream) => stream.borrow_mut().seek(SeekFrom::End(0)),
+        }
+    }
+
+    /// Shortens the log to `length` bytes.  A stream cannot be shortened, so
+    /// the bytes past `length` are zeroed instead, which reads back as the end
+    /// of the log.
+    pub(crate) fn set_len(&mut self, length: u64) -> Result<()> {
+        match self {
+            Storage::File(file) => file.set_len(length),
+            Storage::Memory(buffer) => {
+                buffer.resize(length as usize, 0);
+                Ok(())
+            }
+            Storage::Stream(stream) => {
+                let stream = stream.get_mut();
+                let end = stream.seek(SeekFrom::End(0))?;
+                if end > length {
+                    stream.seek(SeekFrom::Start(length))?;
+                    write_zeros(stream, end - length)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Grows the log to `length` bytes, reserving the space up front.
+    pub(crate) fn preallocate(&mut self, length: u64) -> Result<()> {
+        match self {
+            Storage::File(file) => sys::preallocate(file, length),
+            Storage::Memory(buffer) => {
+                if length as usize > buffer.len() {
+                    buffer.resize(length as usize, 0);
+                }
+                Ok(())
+            }
+            Storage::Stream(stream) => {
+                let stream = stream.get_mut();
+                let end = stream.seek(SeekFrom::End(0))?;
+                if length > end {
+                    write_zeros(stream, length - end)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes `bytes` at offset `position`, extending the log if needed.
+    pub(crate) fn write_at(&mut self, position: u64, bytes: &[u8]) -> Result<()> {
+        match self {
+            Storage::File(file) => {
+                file.seek(SeekFrom::Start(position))?;
+                file.write_all(bytes)
+            }
+            Storage::Memory(buffer) => {
+                let start = position as usize;
+                let end = start + bytes.len();
+                if end > buffer.len() {
+                    buffer.resize(end, 0);
+                }
+                buffer[start..end].copy_from_slice(bytes);
+                Ok(())
+            }
+            Storage::Stream(stream) => {
+                let stream = stream.get_mut();
+                stream.seek(SeekFrom::Start(position))?;
+                stream.write_all(bytes)
+            }
+        }
+    }
+
+    /// Flushes written records to stable storage.  There is nothing to flush
+    /// for a log kept in memory, and a stream is only asked to flush its own
+    /// buffers.
+    pub(crate) fn sync_data(&self) -> Result<()> {
+        match self {
+            Storage::File(file) => file.sync_data(),
+            Storage::Memory(_) => Ok(()),
+            Storage::Stream(stream) => stream.borrow_mut().flush(),
+        }
+    }
+
+    /// Advises the operating system that the cached pages of the log file
+    /// will not be needed again.
+    pub(crate) fn drop_page_cache(&self) -> Result<()> {
+        match self {
+            Storage::File(file) => sys::drop_page_cache(file),
+            Storage::Memory(_) | Storage::Stream(_) => Ok(()),
+        }
+    }
+
+    /// Returns the log file, or an error of kind `Unsupported` naming
+    /// `feature` for a log kept anywhere else.
+    pub(crate) fn file(&self, feature: &str) -> Result<&File> {
+        match self {
+            Storage::File(file) => Ok(file),
+            Storage::Memory(_) | Storage::Stream(_) => {
+                let error_message = format!("{feature} requires a database file");
+                let error = Error::new(ErrorKind::Unsupported, error_message);
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Writes `count` zero bytes to `stream` at its current position.
+fn write_zeros(stream: &mut dyn Stream, count: u64) -> Result<()> {
+    let zeros = [0; 4096];
+    let mut remaining = count;
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        stream.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    Ok(())
+}
+
+impl Read for StorageReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            StorageReader::File(file) => file.read(buf),
+            StorageReader::Memory(cursor) => cursor.read(buf),
+            StorageReader::Stream { stream, position } => {
+                // Other readers may have moved the stream since the last call.
+                let mut stream = stream.borrow_mut();
+                stream.seek(SeekFrom::Start(*position))?;
+                let read = stream.read(buf)?;
+                *position += read as u64;
+                Ok(read)
+            }
+        }
+    }
+}
+
+impl Seek for StorageReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match self {
+            StorageReader::File(file) => file.seek(pos),
+            StorageReader::Memory(cursor) => cursor.seek(pos),
+            StorageReader::Stream { stream, position } => {
+                *position = match pos {
+                    SeekFrom::Start(offset) => offset,
+                    _ => {
+                        let mut stream = stream.borrow_mut();
+                        stream.seek(SeekFrom::Start(*position))?;
+                        stream.seek(pos)?
+                    }
+                };
+                Ok(*position)
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Thin wrappers over operating system calls that have no portable equivalent
+//! in the standard library.
+
+use std::fs::{File, OpenOptions};
+use std::io::Result;
+
+/// Makes `options` create files with the permission bits `mode`, such as
+/// `0o600`, where the platform has them.  The process umask still applies.
+/// `None` keeps the platform default.
+#[cfg(unix)]
+pub(crate) fn with_mode(options: &mut OpenOptions, mode: Option<u32>) -> &mut OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match mode {
+        Some(mode) => options.mode(mode),
+        None => options,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn with_mode(options: &mut OpenOptions, _mode: Option<u32>) -> &mut OpenOptions {
+    options
+}
+
+/// Returns `true` if `file` is still the file at `path`, rather than one that
+/// has since been replaced, as by compaction renaming a new log into place.
+#[cfg(unix)]
+pub(crate) fn is_same_file(file: &File, path: &std::path::Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let open = file.metadata()?;
+    let current = std::fs::metadata(path)?;
+    Ok(open.dev() == current.dev() && open.ino() == current.ino())
+}
+
+/// Without inode numbers, a replaced file is only noticed once it is shorter
+/// than the open one.
+#[cfg(not(unix))]
+pub(crate) fn is_same_file(file: &File, path: &std::path::Path) -> Result<bool> {
+    Ok(std::fs::metadata(path)?.len() >= file.metadata()?.len())
+}
+
+/// Grows `file` to `length` bytes, reserving the disk blocks up front where
+/// the platform supports it.
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(file: &File, length: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, length as libc::off_t) };
+    if result == 0 {
+        return Ok(());
+    }
+
+    // Not every file system implements fallocate; fall back to extending the
+    // file, which still keeps the record offsets stable.
+    file.set_len(length)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn preallocate(file: &File, length: u64) -> Result<()> {
+    file.set_len(length)
+}
+
+/// Advises the operating system that the cached pages of `file` will not be
+/// needed again, so that a large sequential pass over the database does not
+/// push other data out of the page cache.
+#[cfg(target_os = "linux")]
+pub(crate) fn drop_page_cache(file: &File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    match result {
+        0 => Ok(()),
+        errno => Err(std::io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn drop_page_cache(_file: &File) -> Result<()> {
+    Ok(())
+}
+
+/// Size, and required alignment, of every write issued with `O_DIRECT`.
+#[cfg(target_os = "linux")]
+const DIRECT_BLOCK: usize = 4096;
+
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy)]
+#[repr(C, align(4096))]
+struct Block([u8; DIRECT_BLOCK]);
+
+/// Appends records to the log through a second handle opened with `O_DIRECT`.
+/// Direct writes must cover whole, aligned blocks, so the writer keeps the
+/// partially filled last block in memory and rewrites it, zero padded, along
+/// with each new record.  The padding reads back like preallocated space.
+#[cfg(target_os = "linux")]
+pub(crate) struct DirectWriter {
+    file: File,
+    tail: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl DirectWriter {
+    /// Opens the log at `path` for direct writes at offset `end`, reading the
+    /// partially filled block before `end` through the buffered `log` handle.
+    pub(crate) fn open(path: &std::path::Path, log: &File, end: u64) -> Result<DirectWriter> {
+        use std::os::unix::fs::{FileExt, OpenOptionsExt};
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)?;
+
+        let partial = (end % DIRECT_BLOCK as u64) as usize;
+        let mut tail = vec![0; partial];
+        log.read_exact_at(&mut tail, end - partial as u64)?;
+
+        Ok(DirectWriter { file, tail })
+    }
+
+    /// Writes `record` at offset `end`, the current end of the log.
+    pub(crate) fn write_at(&mut self, end: u64, record: &[u8]) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let start = end - self.tail.len() as u64;
+        let length = self.tail.len() + record.len();
+        let mut blocks = vec![Block([0; DIRECT_BLOCK]); length.div_ceil(DIRECT_BLOCK)];
+        let buffer = unsafe {
+            std::slice::from_raw_parts_mut(
+                blocks.as_mut_ptr() as *mut u8,
+                blocks.len() * DIRECT_BLOCK,
+            )
+        };
+
+        buffer[..self.tail.len()].copy_from_slice(&self.tail);
+        buffer[self.tail.len()..length].copy_from_slice(record);
+        self.file.write_all_at(buffer, start)?;
+
+        let partial = length % DIRECT_BLOCK;
+        self.tail = buffer[length - partial..length].to_vec();
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct DirectWriter;
+
+#[cfg(not(target_os = "linux"))]
+impl DirectWriter {
+    pub(crate) fn open(_path: &std::path::Path, _log: &File, _end: u64) -> Result<DirectWriter> {
+        let error_message = "direct IO is only supported on Linux";
+        let error = std::io::Error::new(std::io::ErrorKind::Unsupported, error_message);
+        Err(error)
+    }
+
+    pub(crate) fn write_at(&mut self, _end: u64, _record: &[u8]) -> Result<()> {
+        unreachable!("a DirectWriter cannot be opened on this platform")
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{now, ActionKV, FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{Error, ErrorKind, Result};
+
+/// What [`ActionKV::top`] ranks keys by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopBy {
+    /// Reads counted since the database was opened with
+    /// [`Options::track_access`](crate::Options::track_access).
+    Reads,
+    /// Writes counted since the database was opened with
+    /// [`Options::track_access`](crate::Options::track_access), or else the
+    /// records of the key in the log, which are its writes since the last
+    /// compaction.
+    Writes,
+    /// Size of the value in bytes.
+    Size,
+}
+
+/// Reads and writes of every key, counted for
+/// [`Options::track_access`](crate::Options::track_access).
+#[derive(Default)]
+pub(crate) struct AccessCounts {
+    counts: HashMap<String, (u64, u64)>,
+}
+
+impl AccessCounts {
+    /// Counts a read of `key`.
+    pub(crate) fn read(&mut self, key: &str) {
+        match self.counts.get_mut(key) {
+            Some((reads, _)) => *reads += 1,
+            None => {
+                self.counts.insert(key.to_string(), (1, 0));
+            }
+        }
+    }
+
+    /// Counts a write of `key`.
+    pub(crate) fn written(&mut self, key: &str) {
+        match self.counts.get_mut(key) {
+            Some((_, writes)) => *writes += 1,
+            None => {
+                self.counts.insert(key.to_string(), (0, 1));
+            }
+        }
+    }
+}
+
+impl ActionKV {
+    /// Returns the `limit` keys ranking highest by `by`, with their counts or
+    /// sizes, highest first and then in key order, to help diagnose skewed
+    /// workloads.  Only keys in the database count; expired keys are left
+    /// out.  Sizes are kept in the index and write counts, without
+    /// [`Options::track_access`](crate::Options::track_access), read from the
+    /// log.  Ranking by reads fails with an error of kind `InvalidInput`
+    /// unless access is tracked.
+    pub fn top(&self, by: TopBy, limit: usize) -> Result<Vec<(String, u64)>> {
+        let now = now();
+        let live = |key: &str| self.database.contains_key(key) && !self.is_expired_at(key, now);
+
+        if let Some(access) = &self.access {
+            if by != TopBy::Size {
+                let access = access.borrow();
+                let counts = access.counts.iter().filter(|(key, _)| live(key));
+                let counts = counts.map(|(key, (reads, writes))| match by {
+                    TopBy::Reads => (key.as_str(), *reads),
+                    _ => (key.as_str(), *writes),
+                });
+                return Ok(highest(counts.filter(|(_, count)| *count > 0), limit));
+            }
+        }
+
+        match by {
+            TopBy::Reads => {
+                let error_message = "read counts require Options::track_access";
+                let error = Error::new(ErrorKind::InvalidInput, error_message);
+                Err(error)
+            }
+            TopBy::Writes => {
+                let mut writes: HashMap<String, u64> = HashMap::new();
+                self.scan_log(|_, _, akv| {
+                    let metadata = FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_LEASE | FLAG_PASSPHRASE;
+                    if akv.flags & metadata == 0 && live(&akv.key) {
+                        *writes.entry(akv.key).or_insert(0) += 1;
+                    }
+                    Ok(())
+                })?;
+                let writes = writes.iter().map(|(key, count)| (key.as_str(), *count));
+                Ok(highest(writes, limit))
+            }
+            TopBy::Size => {
+                let sizes = self
+                    .database
+                    .iter()
+                    .filter(|(key, _)| !self.is_expired_at(key, now));
+                let sizes = sizes.map(|(key, entry)| (key, entry.meta.value_size));
+                Ok(highest(sizes, limit))
+            }
+        }
+    }
+
+    /// Counts a read of `key` when access is tracked.
+    pub(crate) fn count_read(&self, key: &str) {
+        if let Some(access) = &self.access {
+            access.borrow_mut().read(key);
+        }
+    }
+
+    /// Counts a write of `key` when access is tracked.
+    pub(crate) fn count_write(&mut self, key: &str) {
+        if let Some(access) = &mut self.access {
+            access.get_mut().written(key);
+        }
+    }
+}
+
+/// Returns the `limit` keys with the highest values, highest first and then
+/// in key order.
+fn highest<'a>(values: impl Iterator<Item = (&'a str, u64)>, limit: usize) -> Vec<(String, u64)> {
+    // A min-heap of the highest values seen so far.
+    let mut highest = BinaryHeap::with_capacity(limit + 1);
+    for (key, value) in values {
+        highest.push(Reverse((value, Reverse(key))));
+        if highest.len() > limit {
+            highest.pop();
+        }
+    }
+
+    let mut highest: Vec<(u64, &str)> = highest
+        .into_iter()
+        .map(|Reverse((value, Reverse(key)))| (value, key))
+        .collect();
+    highest.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    highest
+        .into_iter()
+        .map(|(value, key)| (key.to_string(), value))
+        .collect()
+}
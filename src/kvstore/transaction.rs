@@ -0,0 +1,171 @@
+use crate::{now, ActionKV, IndexEntry, KeyNotFound, KeyValuePair};
+use crate::{Checksum, FLAG_BLOB, FLAG_CHECKPOINT, FLAG_DELETE};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Result, Seek, SeekFrom};
+
+/// Key of the record that starts a transaction.  Its value is the length, in
+/// bytes, of the records of the transaction that follow it.
+const TRANSACTION_KEY: &str = "kvstore-transaction";
+
+/// A write applied by [`ActionKV::apply_atomically`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Creates or updates the entry for `key`, as
+    /// [`insert`](ActionKV::insert) does.
+    Set {
+        /// The key to write.
+        key: String,
+        /// The value to write.
+        value: String,
+    },
+    /// Deletes `key`, as [`delete`](ActionKV::delete) does.
+    Delete {
+        /// The key to delete.
+        key: String,
+    },
+}
+
+impl ActionKV {
+    /// Applies `operations` in order as a single transaction: after a crash,
+    /// the database holds either all of their writes or none of them.  The
+    /// records are preceded by a transaction record giving their combined
+    /// length, and opening the database ignores a transaction whose records
+    /// did not all reach the log.  Every operation is checked, and passed
+    /// through the write and delete hooks, before anything is written; a
+    /// deleted key must exist.
+    pub fn apply_atomically(&mut self, operations: Vec<Operation>) -> Result<()> {
+        // Processes sharing the database must not write in between the checks
+        // and the transaction.
+        self.locked(|akv| akv.write_transaction(operations))
+    }
+
+    /// Checks `operations` and writes them as a transaction, for
+    /// `apply_atomically`.
+    fn write_transaction(&mut self, operations: Vec<Operation>) -> Result<()> {
+        let mut writes = Vec::with_capacity(operations.len());
+        let mut set = HashSet::new();
+        for operation in operations {
+            match operation {
+                Operation::Set { key, value } => {
+                    let value = self.run_write_hooks(&key, value)?;
+                    self.check_limits(&key, &value)?;
+                    set.insert(key.clone());
+                    writes.push((key, value, 0));
+                }
+                Operation::Delete { key } => {
+                    if !set.contains(&key) && !self.contains_key(&key) {
+                        let error = KeyNotFound::error(ErrorKind::InvalidData, &key);
+                        return Err(error);
+                    }
+                    self.run_delete_hooks(&key)?;
+                    writes.push((key, String::new(), FLAG_DELETE));
+                }
+            }
+        }
+        self.apply_repairs()?;
+
+        let timestamp = now();
+        let mut records = Vec::new();
+        let mut entries = Vec::with_capacity(writes.len());
+        for (key, value, flags) in writes {
+            let value_size = value.len() as u64;
+            let (value, flags, blob) = if self.spills(&value) {
+                let name = self.store_blob(value.as_bytes())?;
+                (name.clone(), FLAG_BLOB, Some(name))
+            } else {
+                (value, flags, None)
+            };
+
+            let offset = records.len() as u64;
+            let sequence = self.next_sequence;
+            ActionKV::write_record(
+                &mut records,
+                self.checksum,
+                &key,
+                &value,
+                &[],
+                flags,
+                timestamp,
+                sequence,
+            )?;
+            self.next_sequence += 1;
+            let length = records.len() as u64 - offset;
+            entries.push((key, offset, length, value_size, sequence, blob));
+        }
+
+        let length = records.len().to_string();
+        let mut transaction = Vec::with_capacity(records.len() + 64);
+        let sequence = self.next_sequence;
+        ActionKV::write_record(
+            &mut transaction,
+            self.checksum,
+            TRANSACTION_KEY,
+            &length,
+            &[],
+            FLAG_CHECKPOINT,
+            timestamp,
+            sequence,
+        )?;
+        self.next_sequence += 1;
+        let start = transaction.len() as u64;
+        transaction.extend_from_slice(&records);
+
+        let start = self.append(&transaction)? + start;
+        for (key, offset, length, value_size, sequence, blob) in entries {
+            self.blob_refs.set(&key, blob.as_deref());
+            self.expiries.remove(&key);
+            let entry = IndexEntry::new(start + offset, length, value_size, timestamp, sequence);
+            self.database.insert(&key, entry);
+            self.cache_written(&key, value_size)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if the transaction whose records take up the `length`
+    /// bytes from `start` was cut short at the tail of the log, so none of it
+    /// should be indexed.
+    pub(crate) fn is_transaction_complete(
+        &self,
+        start: u64,
+        length: u64,
+        verify: Option<Checksum>,
+    ) -> Result<bool> {
+        let end = start + length;
+        let mut file = self.storage.reader();
+        let mut position = file.seek(SeekFrom::Start(start))?;
+        while position < end {
+            match ActionKV::read_raw_record(&mut file, self.options.max_record_size, verify) {
+                Ok(_) => position = file.stream_position()?,
+                Err(e)
+                    if e.kind() == ErrorKind::UnexpectedEof && self.is_torn_tail(position)? =>
+                {
+                    return Ok(false);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if position != end {
+            let error_message = format!("corrupt transaction ending at offset {position}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            return Err(error);
+        }
+        Ok(true)
+    }
+}
+
+/// Returns the length of the records following `kv` if it starts a
+/// transaction.
+pub(crate) fn length(kv: &KeyValuePair) -> Result<Option<u64>> {
+    if kv.flags & FLAG_CHECKPOINT == 0 || kv.key != TRANSACTION_KEY {
+        return Ok(None);
+    }
+    match kv.value.parse() {
+        Ok(length) => Ok(Some(length)),
+        Err(e) => {
+            let error_message = format!("invalid transaction length: {e}");
+            let error = Error::new(ErrorKind::InvalidData, error_message);
+            Err(error)
+        }
+    }
+}
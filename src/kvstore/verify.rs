@@ -0,0 +1,330 @@
+use crate::{ActionKV, Checksum, RawRecord};
+use crate::{FLAG_CHECKPOINT, FLAG_EXPIRY, FLAG_LEASE, FLAG_PASSPHRASE, FLAG_TOMBSTONE};
+use std::io::{Error, ErrorKind, Result, Seek, SeekFrom};
+use std::sync::{mpsc, Mutex};
+
+/// The outcome of checking the log, as reported by [`ActionKV::verify_all`].
+/// [`to_json`](IntegrityReport::to_json) serializes it, so monitoring can keep
+/// reports and compare them over time.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    /// Number of keys whose records were checked.
+    pub keys: u64,
+    /// Combined length of the records checked, in bytes.
+    pub bytes: u64,
+    /// Number of records in the log, of every kind.
+    pub records: u64,
+    /// Number of current records of keys that passed.
+    pub valid: u64,
+    /// Number of records holding values since overwritten, which compaction
+    /// drops.
+    pub superseded: u64,
+    /// Number of tombstones.
+    pub tombstones: u64,
+    /// Number of expiry, lease, passphrase and checkpoint records.
+    pub metadata: u64,
+    /// The records that failed, in the order they appear in the log.
+    pub corrupt: Vec<CorruptRecord>,
+    /// Offset of a record so damaged that the records following it could not
+    /// be found, if there is one.  The current records of keys past it are
+    /// still checked, from the offsets the index holds.
+    pub unreadable_from: Option<u64>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if every record passed.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+
+    /// Returns the report as a JSON object, with the fields named as in the
+    /// struct and each repair given by its [`name`](Repair::name).
+    pub fn to_json(&self) -> String {
+        let corrupt: Vec<serde_json::Value> = self
+            .corrupt
+            .iter()
+            .map(|record| {
+                serde_json::json!({
+                    "key": record.key,
+                    "position": record.position,
+                    "error": record.error,
+                    "repair": record.repair.name(),
+                })
+            })
+            .collect();
+        let report = serde_json::json!({
+            "keys": self.keys,
+            "bytes": self.bytes,
+            "records": self.records,
+            "valid": self.valid,
+            "superseded": self.superseded,
+            "tombstones": self.tombstones,
+            "metadata": self.metadata,
+            "corrupt": corrupt,
+            "unreadable_from": self.unreadable_from,
+        });
+        report.to_string()
+    }
+}
+
+/// A record that failed [`ActionKV::verify_all`].
+#[derive(Debug)]
+pub struct CorruptRecord {
+    /// The key the record was written for, if it could be read.
+    pub key: Option<String>,
+    /// Offset of the record in the log.
+    pub position: u64,
+    /// What was wrong with it.
+    pub error: String,
+    /// How the damage can be repaired.
+    pub repair: Repair,
+}
+
+/// A repair suggested for a [`CorruptRecord`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repair {
+    /// The record holds the current value of its key, which
+    /// [`Options::secondary`](crate::Options::secondary) has a copy of:
+    /// reading the key fetches the value from there and rewrites it.
+    ReadRepair,
+    /// The record holds the current value of its key and no copy is at hand:
+    /// the key has to be restored from a backup, or written again.
+    Rewrite,
+    /// The blob file holding the current value of the key is missing and has
+    /// to be restored from a backup.
+    RestoreBlob,
+    /// The record no longer holds a current value: compaction, which only
+    /// copies current records, drops it.
+    Compact,
+}
+
+impl Repair {
+    /// Returns the name of the repair, as used by
+    /// [`IntegrityReport::to_json`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Repair::ReadRepair => "read-repair",
+            Repair::Rewrite => "rewrite",
+            Repair::RestoreBlob => "restore-blob",
+            Repair::Compact => "compact",
+        }
+    }
+}
+
+impl ActionKV {
+    /// Reads every record of the log and checks its checksum, counting the
+    /// records of each kind, then reads the current record of every key
+    /// again along with the blob file or the earlier records its value is
+    /// built from.  Damaged records are listed in the report, each with the
+    /// repair it calls for, rather than failing the call, which only returns
+    /// an error if the log cannot be read at all.
+    ///
+    /// Opening a database checks every record, but later damage to the file
+    /// is only noticed when a record is read, or never with
+    /// [`Options::skip_read_checksums`](crate::Options::skip_read_checksums)
+    /// set, so this is meant to run periodically on long-lived databases.
+    /// Records are read in log order.
+    pub fn verify_all(&self) -> Result<IntegrityReport> {
+        let mut records: Vec<(u64, u64, &str)> = self
+            .database
+            .iter()
+            .map(|(key, entry)| (entry.position, entry.length, key))
+            .collect();
+        records.sort_unstable();
+
+        let mut report = IntegrityReport::default();
+        let mut stale = Vec::new();
+        let unreadable_from = self.walk_log(|position, record| {
+            let is_current = records
+                .binary_search_by_key(&position, |(position, _, _)| *position)
+                .is_ok();
+            report.records += 1;
+            match record {
+                Ok(flags) => report.tally(flags, is_current),
+                // The pass over current records reports these.
+                Err(_) if is_current => {}
+                Err((key, error)) => stale.push(CorruptRecord {
+                    key,
+                    position,
+                    error: error.to_string(),
+                    repair: Repair::Compact,
+                }),
+            }
+        })?;
+        report.unreadable_from = unreadable_from;
+
+        for (position, length, key) in records {
+            report.keys += 1;
+            report.bytes += length;
+
+            let result = self
+                .get_record_at_position(position)
+                .and_then(|akv| self.resolve_value(akv));
+            match result {
+                Ok(_) => report.valid += 1,
+                Err(e) if is_damage(&e) => {
+                    let repair = match e.kind() {
+                        _ if self.options.secondary.is_some() => Repair::ReadRepair,
+                        ErrorKind::NotFound => Repair::RestoreBlob,
+                        _ => Repair::Rewrite,
+                    };
+                    report.corrupt.push(CorruptRecord {
+                        key: Some(key.to_string()),
+                        position,
+                        error: e.to_string(),
+                        repair,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        report.corrupt.extend(stale);
+        report.corrupt.sort_by_key(|record| record.position);
+        Ok(report)
+    }
+
+    /// Calls `visit` with the offset of every record in the log, oldest first,
+    /// and whether it passed its checksum.  Returns the offset of a record
+    /// that could not be read at all, past which no more records can be
+    /// found.
+    fn walk_log<F>(&self, mut visit: F) -> Result<Option<u64>>
+    where
+        F: FnMut(u64, Checked),
+    {
+        let max_record_size = self.options.max_record_size;
+        let flushed = self.end - self.pending.len() as u64;
+        let first_record = self.checksum.first_record();
+
+        let mut file = self.storage.reader();
+        let mut position = file.seek(SeekFrom::Start(first_record.min(flushed)))?;
+        while position < flushed {
+            let record = match ActionKV::read_raw_record(&mut file, max_record_size, None) {
+                Ok(record) => record,
+                Err(e) if is_damage(&e) => {
+                    visit(position, Err((None, e)));
+                    return Ok(Some(position));
+                }
+                Err(e) => return Err(e),
+            };
+            match record.verify(self.checksum) {
+                Ok(()) => visit(position, Ok(record.flags)),
+                Err(e) => {
+                    let key = record
+                        .split()
+                        .ok()
+                        .map(|(key, _, _)| String::from_utf8_lossy(key).to_string());
+                    visit(position, Err((key, e)));
+                }
+            }
+            position = file.stream_position()?;
+        }
+
+        // Records not yet flushed were checked as they were written.
+        let mut pending = std::io::Cursor::new(&self.pending);
+        pending.set_position(first_record.saturating_sub(flushed));
+        while (pending.position() as usize) < self.pending.len() {
+            let start = pending.position();
+            let record = ActionKV::read_raw_record(&mut pending, max_record_size, None)?;
+            visit(flushed + start, Ok(record.flags));
+        }
+        Ok(None)
+    }
+}
+
+/// A record read by `walk_log`: its flags if it passed its checksum, or else
+/// its key, if that could be read, and what was wrong with it.
+type Checked = std::result::Result<u8, (Option<String>, Error)>;
+
+impl IntegrityReport {
+    /// Counts a record with `flags` that passed its checksum.
+    fn tally(&mut self, flags: u8, is_current: bool) {
+        let metadata = FLAG_CHECKPOINT | FLAG_EXPIRY | FLAG_LEASE | FLAG_PASSPHRASE;
+        if flags & FLAG_TOMBSTONE != 0 {
+            self.tombstones += 1;
+        } else if flags & metadata != 0 {
+            self.metadata += 1;
+        } else if !is_current {
+            self.superseded += 1;
+        }
+    }
+}
+
+/// Returns `true` if `error` describes a damaged record or a missing blob
+/// file, rather than a failure to read the log.
+fn is_damage(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof | ErrorKind::NotFound
+    )
+}
+
+/// Logs at least this long have their checksums checked by worker threads
+/// when opened, while the loading thread reads and indexes the records.
+/// Starting the workers costs more than it saves on shorter logs.
+pub(crate) const PARALLEL_LENGTH: u64 = 8 * 1024 * 1024;
+
+/// Bytes of records handed to a worker at a time.
+const BATCH_LENGTH: usize = 1024 * 1024;
+
+/// Returns the number of worker threads to check checksums with: one per
+/// processor, up to eight, or one where threads are not available.
+pub(crate) fn workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get().min(8))
+}
+
+/// Runs `load`, checking every record it passes to the function it is given
+/// against `checksum` on worker threads.  A record that fails fails the
+/// whole call, with the error for the first such record in the log, even if
+/// `load` itself succeeded.
+pub(crate) fn in_parallel<T, F>(checksum: Checksum, load: F) -> Result<T>
+where
+    F: FnOnce(&mut dyn FnMut(u64, RawRecord)) -> Result<T>,
+{
+    let workers = workers();
+    let (sender, receiver) = mpsc::sync_channel::<Vec<(u64, RawRecord)>>(workers * 2);
+    let receiver = Mutex::new(receiver);
+    let failure: Mutex<Option<(u64, Error)>> = Mutex::new(None);
+
+    let result = std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let batch = match receiver.lock().map(|receiver| receiver.recv()) {
+                    Ok(Ok(batch)) => batch,
+                    _ => return,
+                };
+                let failed = batch.iter().find_map(|(position, record)| {
+                    let error = record.verify(checksum).err()?;
+                    Some((*position, error))
+                });
+                if let (Some((position, error)), Ok(mut failure)) = (failed, failure.lock()) {
+                    if failure.as_ref().is_none_or(|(first, _)| position < *first) {
+                        *failure = Some((position, error));
+                    }
+                }
+            });
+        }
+
+        let mut batch = Vec::new();
+        let mut batch_length = 0;
+        let result = load(&mut |position, record| {
+            batch_length += record.data.len();
+            batch.push((position, record));
+            if batch_length >= BATCH_LENGTH {
+                batch_length = 0;
+                let _ = sender.send(std::mem::take(&mut batch));
+            }
+        });
+        let _ = sender.send(batch);
+        drop(sender);
+        result
+    });
+
+    let failure = failure
+        .into_inner()
+        .map_err(|_| Error::other("checksum worker panicked"))?;
+    match failure {
+        Some((_, error)) => Err(error),
+        None => result,
+    }
+}
@@ -0,0 +1,61 @@
+use crate::scan::ReadAhead;
+use crate::{blob, now, ActionKV, FLAG_BLOB, FLAG_DELTA};
+use std::io::{ErrorKind, Result};
+
+impl ActionKV {
+    /// Calls `visit` with every key and its value, in key order, stopping at
+    /// the first error it returns.  Unlike [`scan`](ActionKV::scan), which
+    /// returns a fresh `String` for every key and value, the key is borrowed
+    /// from the index and the value from the chunk of the log read ahead, so
+    /// exports and analytics over many millions of records do not allocate
+    /// per record.  Values are passed as read, without checking that they are
+    /// UTF-8.
+    ///
+    /// Entries are otherwise read like [`get`](ActionKV::get): expired keys
+    /// are skipped, and values held in blob files, rebuilt from deltas,
+    /// passed through read hooks or repaired from
+    /// [`Options::secondary`](crate::Options::secondary) are read the way
+    /// `get` reads them, at the cost of its allocations.
+    pub fn for_each<F>(&self, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&str, &[u8]) -> Result<()>,
+    {
+        let now = now();
+        let read_hooks = self.has_read_hooks();
+        let verify = (!self.options.skip_read_checksums).then_some(self.checksum);
+        let mut read_ahead = ReadAhead::default();
+        for (key, entry) in self.database.iter() {
+            if self.is_expired_at(key, now) {
+                continue;
+            }
+            if read_hooks {
+                visit(key, self.get(key.to_string())?.as_bytes())?;
+                continue;
+            }
+            let Some(bytes) = read_ahead.record(self, *entry)? else {
+                visit(key, self.get(key.to_string())?.as_bytes())?;
+                continue;
+            };
+            let record =
+                match ActionKV::parse_raw_record(bytes, self.options.max_record_size, verify) {
+                    Ok(record) if record.flags & FLAG_DELTA == 0 => record,
+                    Err(e) if e.kind() != ErrorKind::InvalidData => return Err(e),
+                    _ => {
+                        visit(key, self.get(key.to_string())?.as_bytes())?;
+                        continue;
+                    }
+                };
+
+            self.cache_read(key);
+            let (_, _, value) = record.split()?;
+            if record.flags & FLAG_BLOB != 0 {
+                let name = String::from_utf8_lossy(value);
+                let value = blob::read_blob(&self.blob_dir()?, &name)?;
+                visit(key, &value)?;
+                continue;
+            }
+            visit(key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{blob, ActionKV, FLAG_BLOB, FLAG_DELTA};
+use bytes::Bytes;
+use std::io::{ErrorKind, Result};
+
+impl ActionKV {
+    /// Retrieves `key` from the database like [`get`](ActionKV::get), as
+    /// [`Bytes`] sliced out of the buffer the record was read into.  Unlike
+    /// `get`, the value is not copied into a fresh `String` and checked to be
+    /// UTF-8, so a service forwarding values onto the network can hand them
+    /// over as read.  The value of a blob is returned in the buffer its file
+    /// was read into.
+    ///
+    /// Values rebuilt from deltas, values passed through read hooks and
+    /// damaged records repaired from [`Options::secondary`] are read through
+    /// `get` and converted, which costs what `get` does.
+    ///
+    /// [`Options::secondary`]: crate::Options::secondary
+    pub fn get_bytes(&self, key: &str) -> Result<Bytes> {
+        let position = match self.database.get(key) {
+            Some(entry) if !self.is_expired(key) && !self.has_read_hooks() => entry.position,
+            _ => return self.get(key.to_string()).map(Bytes::from),
+        };
+        self.cache_read(key);
+
+        let record = match self.read_raw_record_at(position, !self.options.skip_read_checksums) {
+            Ok(record) if record.flags & FLAG_DELTA == 0 => record,
+            Err(e) if e.kind() != ErrorKind::InvalidData => return Err(e),
+            _ => return self.get(key.to_string()).map(Bytes::from),
+        };
+
+        let flags = record.flags;
+        let value_start = record.value_start()?;
+        let value = Bytes::from(record.data).slice(value_start..);
+        if flags & FLAG_BLOB != 0 {
+            let name = String::from_utf8_lossy(&value);
+            let value = blob::read_blob(&self.blob_dir()?, &name)?;
+            return Ok(Bytes::from(value));
+        }
+        Ok(value)
+    }
+}
@@ -1,35 +1,378 @@
-use clap::{Parser, Subcommand};
-use kvstore::ActionKV;
-use std::io::Result;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use kvstore::{
+    ActionKV, Checksum, CompactionReport, ConflictPolicy, Cursor, DatabaseInfo, EventCursor,
+    IndexFile, IndexMemory, KeyMeta, KeyNotFound, LockState, Operation, Options, TopBy,
+    ValueSizeStats,
+};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io::{self, Error, ErrorKind, Result};
+use std::ops::ControlFlow;
 use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod archive;
+mod audit;
+mod export;
+mod import;
+mod migrate;
+
+/// Environment variable holding the passphrase of a protected database.
+const PASSPHRASE_VAR: &str = "KVSTORE_PASSPHRASE";
+
+/// How often `events --follow` checks the database for new changes.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set by `--quiet` to leave out the status messages of commands.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a status message about what a command did, or how far it has got,
+/// to standard error unless `--quiet` was given.  Standard output is kept for
+/// the values a command returns, so they can be captured by scripts.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Exit code when the key a command needs does not exist.
+const EXIT_NOT_FOUND: u8 = 1;
+/// Exit code when a damaged record or file is found.
+const EXIT_CORRUPT: u8 = 2;
+/// Exit code when the command line, an argument or an input file is invalid.
+const EXIT_USAGE: u8 = 3;
+/// Exit code when a lease is held by another owner.
+const EXIT_LOCKED: u8 = 4;
+/// Exit code for every other failure, such as a database that does not
+/// exist or an IO error.
+const EXIT_FAILURE: u8 = 5;
+
+/// The error `acquire` fails with when the lease is held by another owner,
+/// told apart by [`exit_code`] from the write stalls that share its kind.
+#[derive(Debug)]
+struct LeaseHeld;
+
+impl std::fmt::Display for LeaseHeld {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "lease is held by another owner")
+    }
+}
+
+impl std::error::Error for LeaseHeld {}
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     action: Actions,
-    /// Database file name
-    database: String,
+    /// Database file name, required by every command but `completions`
+    database: Option<String>,
+    /// Create the database if it does not exist, instead of failing.
+    #[arg(long, global = true)]
+    create: bool,
+    /// Leave out status messages, printing only values and errors.
+    #[arg(long, short, global = true)]
+    quiet: bool,
+    /// Share the database with other processes, taking a lock while the
+    /// command runs.
+    #[arg(long, global = true)]
+    multi_process: bool,
 }
 
 #[derive(Subcommand)]
 enum Actions {
+    /// Acquire a lease and print its token
+    Acquire {
+        /// The name of the lease.
+        key: String,
+        /// How long the lease is held, in seconds.
+        #[arg(long, default_value_t = 30)]
+        ttl: u64,
+    },
+    /// Apply the set and delete operations listed in a JSON Lines file
+    Apply {
+        /// A file with one operation per line: {"op":"set","key":...,"value":...}
+        /// or {"op":"delete","key":...}.
+        file: String,
+        /// Apply every operation or, after a crash, none of them.
+        #[arg(long)]
+        atomic: bool,
+    },
+    /// Apply an incremental backup written by `backup`
+    ApplyIncrement {
+        /// The backup file to apply.
+        input: String,
+    },
+    /// Print the administrative commands run against the database
+    Audit {
+        /// Only print commands run at or after this RFC 3339 UTC time.
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Back up the records written after a sequence number to a new file
+    Backup {
+        /// The backup file to create.
+        output: String,
+        /// Only back up records written after this sequence number, as
+        /// printed by the previous backup.  Zero backs up the whole log.
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
+    /// Write a checkpoint so the next open only scans newer records
+    Checkpoint,
+    /// Print a completion script for a shell; takes no database
+    Completions {
+        /// The shell to complete commands in.
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Create a new, empty database
+    Create,
     /// Delete a value from the database
+    #[command(visible_aliases = ["del", "rm"])]
     Delete {
         /// The key for the key/value pair.
         key: String,
     },
+    /// Write a compacted copy of the database to a new file
+    Copy {
+        /// The database file to create.
+        destination: String,
+    },
+    /// Compare the database with another database
+    Diff {
+        /// The database file to compare against.
+        other: String,
+        /// Print the differences as a JSON object.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print changes to keys as JSON objects, one per line
+    Events {
+        /// Keep running, printing changes as they are written.
+        #[arg(long)]
+        follow: bool,
+        /// Only print changes written after this sequence number.  Defaults
+        /// to zero, or with --follow to the last write, so that only new
+        /// changes are printed.
+        #[arg(long)]
+        since: Option<u64>,
+    },
+    /// Make a key expire after a number of seconds
+    Expire {
+        /// The key to expire.
+        key: String,
+        /// Seconds until the key expires.
+        seconds: u64,
+    },
+    /// Make a key expire at a point in time
+    ExpireAt {
+        /// The key to expire.
+        key: String,
+        /// When the key expires, as an RFC 3339 UTC time.
+        time: String,
+    },
+    /// Write every entry of the database to a file in another format
+    Export {
+        /// The format to write.
+        #[arg(long, value_enum)]
+        format: export::Format,
+        /// The file to create.
+        output: String,
+    },
+    /// Compact the database, discarding superseded records
+    Gc {
+        /// Report how much space compaction would reclaim without compacting.
+        #[arg(long)]
+        dry_run: bool,
+        /// Character separating the key prefix used to group the report.
+        #[arg(long, default_value_t = ':')]
+        delimiter: char,
+        /// Report compaction progress on standard error.
+        #[arg(long, conflicts_with = "dry_run")]
+        progress: bool,
+    },
     /// Get a value from the database
     Get {
         /// The key for the key/value pair.
         key: String,
+        /// Get the value the key held at this RFC 3339 UTC time instead.
+        #[arg(long)]
+        as_of: Option<String>,
+        /// Print this value instead of failing if the key does not exist.
+        #[arg(long, conflicts_with = "as_of")]
+        default: Option<String>,
     },
+    /// Load the entries of a file in another format into the database
+    Import {
+        /// The format to read.
+        #[arg(long, value_enum)]
+        format: import::Format,
+        /// The file to read.
+        input: String,
+        /// CSV column holding the key, by header name or index (default: the
+        /// first column).
+        #[arg(long)]
+        key_column: Option<String>,
+        /// CSV column holding the value, by header name or index (default: the
+        /// second column).
+        #[arg(long, conflicts_with = "value_template")]
+        value_column: Option<String>,
+        /// Build each value from several CSV columns, replacing every
+        /// `{column}` in the template.
+        #[arg(long)]
+        value_template: Option<String>,
+    },
+    /// Print how the database is stored: its format, checksum, files, saved
+    /// index and lock
+    Info,
     /// Insert a value into the database
+    #[command(visible_alias = "set")]
     Insert {
         /// The key for the key/value pair.
         key: String,
         /// The value for the key/value pair.
         value: String,
+        /// Fail if the key already exists instead of overwriting it.
+        #[arg(long)]
+        no_overwrite: bool,
+    },
+    /// Insert every key/value pair listed in a file with a single write
+    InsertMany {
+        /// A file with one pair per line: key<TAB>value, key=value, or a JSON
+        /// object with "key" and "value" strings.
+        file: String,
+    },
+    /// List the keys in the database
+    #[command(visible_alias = "ls")]
+    Keys {
+        /// Only list keys starting with this prefix.
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// The order to list keys in.
+        #[arg(long, value_enum, default_value_t = Sort::Key)]
+        sort: Sort,
+    },
+    /// Merge the entries of another database into the database
+    Merge {
+        /// The database file to merge from.
+        other: String,
+        /// Which value to keep when a key exists in both databases.
+        #[arg(long, value_enum, default_value_t = Policy::Newest)]
+        policy: Policy,
+    },
+    /// Import every entry of a sled or LevelDB database
+    Migrate {
+        /// The store that wrote the database.
+        #[arg(long, value_enum)]
+        from: migrate::Source,
+        /// The directory of the database to import, or its file for kvstore.
+        dir: String,
+    },
+    /// Remove the expiry of a key
+    Persist {
+        /// The key to keep.
+        key: String,
+    },
+    /// Release a lease held with the given token
+    Release {
+        /// The name of the lease.
+        key: String,
+        /// The token printed by `acquire`.
+        token: u64,
+    },
+    /// Create the database from an archive written by `snapshot`
+    Restore {
+        /// The archive to restore.
+        archive: String,
+    },
+    /// Write the database as it stood at a point in time to a new file
+    RestoreTo {
+        /// The cutoff, as an RFC 3339 UTC time such as 2024-05-01T12:00:00Z.
+        time: String,
+        /// The database file to create.
+        output: String,
+    },
+    /// Print keys chosen at random, in key order
+    Sample {
+        /// How many keys to print.
+        count: usize,
+    },
+    /// Print every key and its value, separated by a tab
+    Scan {
+        /// Only print keys starting with this prefix.
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// The order to print entries in.
+        #[arg(long, value_enum, default_value_t = Sort::Key)]
+        sort: Sort,
+        /// Print at most this many entries, in key order, followed by a
+        /// cursor for the next page on standard error.
+        #[arg(long, conflicts_with = "sort")]
+        limit: Option<usize>,
+        /// Resume a paged scan at the cursor printed by the previous page.
+        #[arg(long, requires = "limit")]
+        cursor: Option<Cursor>,
+    },
+    /// Require a passphrase, read from standard input, to open the database
+    SetPassphrase {
+        /// Remove the passphrase instead of setting one.
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Write a compressed archive of the database that `restore` can unpack
+    Snapshot {
+        /// The archive file to create, conventionally ending in `.tar.zst`.
+        output: String,
+    },
+    /// Show how value sizes are distributed, which keys are largest and how
+    /// much memory the index takes up
+    Stats {
+        /// How many of the largest keys to list.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Print the length of a value in bytes, without reading it
+    Strlen {
+        /// The key to query.
+        key: String,
+    },
+    /// Remove every expired key from the database
+    Sweep,
+    /// List the keys written most often or holding the largest values
+    Top {
+        /// What to rank keys by.
+        #[arg(long, value_enum, default_value_t = Rank::Writes)]
+        by: Rank,
+        /// How many keys to list.
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Rewrite a key with a fresh timestamp, optionally extending its expiry
+    Touch {
+        /// The key to touch.
+        key: String,
+        /// Make the key expire this many seconds from now.
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+    /// Print the seconds left before a key expires, or "none"
+    Ttl {
+        /// The key to query.
+        key: String,
+    },
+    /// List the keys that expire, soonest first, with the seconds left and
+    /// the time they expire
+    TtlList {
+        /// Only list keys starting with this prefix.
+        #[arg(default_value = "")]
+        prefix: String,
     },
     /// Update a value in the database
     Update {
@@ -37,25 +380,771 @@ enum Actions {
         key: String,
         /// The value for the key/value pair.
         value: String,
+        /// Fail if the key does not exist instead of creating it.
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Check the checksum of every record and list those that fail
+    Verify {
+        /// Print the integrity report as a JSON object.
+        #[arg(long)]
+        json: bool,
     },
 }
 
+/// Orders accepted by the `keys` and `scan` commands.
+#[derive(Clone, Copy, ValueEnum)]
+enum Sort {
+    /// Ascending by key
+    Key,
+    /// Largest values first
+    ValueSize,
+    /// Most recently written first
+    Mtime,
+}
+
+/// Rankings accepted by the `top` command.  Reads are only counted by
+/// programs that open the database with `Options::track_access`.
+#[derive(Clone, Copy, ValueEnum)]
+enum Rank {
+    /// Records of the key in the log, its writes since the last compaction
+    Writes,
+    /// Size of the value
+    Size,
+}
+
+impl From<Rank> for TopBy {
+    fn from(rank: Rank) -> Self {
+        match rank {
+            Rank::Writes => TopBy::Writes,
+            Rank::Size => TopBy::Size,
+        }
+    }
+}
+
+/// Conflict resolution policies accepted by the `merge` command.
+#[derive(Clone, Copy, ValueEnum)]
+enum Policy {
+    /// Keep the value already in the database
+    Ours,
+    /// Take the value from the other database
+    Theirs,
+    /// Keep the most recently written value
+    Newest,
+}
+
+impl From<Policy> for ConflictPolicy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::Ours => ConflictPolicy::Ours,
+            Policy::Theirs => ConflictPolicy::Theirs,
+            Policy::Newest => ConflictPolicy::Newest,
+        }
+    }
+}
+
 // entry point
-fn main() -> Result<()> {
-    let args = Cli::parse();
+fn main() -> ExitCode {
+    let args = match Cli::try_parse() {
+        Ok(args) => args,
+        Err(e) => return usage_error(e),
+    };
+
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    let database = match (&args.action, args.database) {
+        (Actions::Completions { shell }, _) => {
+            clap_complete::generate(*shell, &mut Cli::command(), "kvstore", &mut io::stdout());
+            return ExitCode::SUCCESS;
+        }
+        (_, Some(database)) => database,
+        (_, None) => {
+            let error = Cli::command().error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a database file name is required",
+            );
+            return usage_error(error);
+        }
+    };
+
+    match execute(args.action, &database, args.create, args.multi_process) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            exit_code(&e)
+        }
+    }
+}
+
+/// Prints `error` from parsing the command line, or the help or version it
+/// carries, and returns the exit code for it.
+fn usage_error(error: clap::Error) -> ExitCode {
+    let _ = error.print();
+    match error.use_stderr() {
+        true => ExitCode::from(EXIT_USAGE),
+        false => ExitCode::SUCCESS,
+    }
+}
 
-    let path = Path::new(&args.database);
-    let mut akv = ActionKV::open(path)?;
+/// Returns the exit code reporting `error`: keys that do not exist, damaged
+/// records, bad arguments and leases held elsewhere each have their own.
+fn exit_code(error: &Error) -> ExitCode {
+    let not_found = error
+        .get_ref()
+        .is_some_and(|e| e.downcast_ref::<KeyNotFound>().is_some());
+    let held = error.get_ref().is_some_and(|e| e.is::<LeaseHeld>());
+    let code = match error.kind() {
+        _ if not_found => EXIT_NOT_FOUND,
+        _ if held => EXIT_LOCKED,
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => EXIT_CORRUPT,
+        ErrorKind::InvalidInput => EXIT_USAGE,
+        _ => EXIT_FAILURE,
+    };
+    ExitCode::from(code)
+}
+
+/// Runs `action` against the database named `database` on the command line,
+/// recording it in the audit log if it changes the database.
+fn execute(action: Actions, database: &str, create: bool, multi_process: bool) -> Result<()> {
+    let path = Path::new(database);
+    check_exists(&action, path, create)?;
+    let operation = audit::operation(&action);
+    let result = run(action, path, database, multi_process);
+    match operation {
+        // The command's own error takes precedence over failing to audit it.
+        Some(operation) => {
+            let recorded = audit::record(path, &operation, &result);
+            result.and(recorded)
+        }
+        None => result,
+    }
+}
 
-    match args.action {
+/// Returns an error if the database at `path` does not exist, unless `action`
+/// creates one or `create` allows it to be created, so that a mistyped name
+/// does not silently start an empty database.
+fn check_exists(action: &Actions, path: &Path, create: bool) -> Result<()> {
+    match action {
+        Actions::Create if path.exists() => {
+            let error_message = format!("database: {} already exists", path.display());
+            let error = Error::new(ErrorKind::AlreadyExists, error_message);
+            Err(error)
+        }
+        Actions::Create | Actions::Restore { .. } => Ok(()),
+        _ if create || path.exists() => Ok(()),
+        _ => {
+            let error_message = format!(
+                "database: {} does not exist (use create or --create)",
+                path.display()
+            );
+            let error = Error::new(ErrorKind::NotFound, error_message);
+            Err(error)
+        }
+    }
+}
+
+/// Runs `action` against the database at `path`, named `database` on the
+/// command line.
+fn run(action: Actions, path: &Path, database: &str, multi_process: bool) -> Result<()> {
+    // Restoring creates the database, so it must not be opened first.
+    if let Actions::Restore { archive } = &action {
+        let summary = archive::restore(Path::new(archive), path)?;
+        status!(
+            "restored {} keys ({} bytes, {} blobs) from {archive}",
+            summary.keys,
+            summary.data_bytes,
+            summary.blobs
+        );
+        return Ok(());
+    }
+    // Following reads a file other processes keep writing to, without
+    // holding the lock of a shared database while it waits.
+    if let Actions::Events { follow, since } = action {
+        let akv = ActionKV::open_read_only(path, options())?;
+        return print_events(akv, since, follow);
+    }
+
+    let options = Options {
+        multi_process,
+        ..options()
+    };
+    let mut akv = ActionKV::open_with_options(path, options)?;
+    // Other processes sharing the database wait until the command is done,
+    // so nothing it reads changes under it.
+    akv.locked(|akv| command(action, akv, path, database))
+}
+
+/// Runs `action`, any command but `restore`, against the open database `akv`.
+fn command(action: Actions, akv: &mut ActionKV, path: &Path, database: &str) -> Result<()> {
+    match action {
+        Actions::Acquire { key, ttl } => match akv.acquire(key, Duration::from_secs(ttl))? {
+            Some(token) => println!("{token}"),
+            None => {
+                let error = Error::new(ErrorKind::WouldBlock, LeaseHeld);
+                return Err(error);
+            }
+        },
+        Actions::Apply { file, atomic } => {
+            let operations = import::read_operations(Path::new(&file))?;
+            let applied = operations.len();
+            if atomic {
+                akv.apply_atomically(operations)?;
+            } else {
+                for operation in operations {
+                    match operation {
+                        Operation::Set { key, value } => akv.insert(key, value)?,
+                        Operation::Delete { key } => akv.delete(key)?,
+                    }
+                }
+            }
+            akv.flush()?;
+            status!("applied {applied} operations from {file}");
+        }
+        Actions::ApplyIncrement { input } => {
+            let applied = akv.apply_increment(Path::new(&input))?;
+            status!("applied {applied} records from {input}");
+        }
+        Actions::Audit { since } => {
+            let since = match since {
+                Some(since) => parse_time(&since)?,
+                None => 0,
+            };
+            audit::print(path, since)?;
+        }
+        Actions::Backup { output, since } => {
+            let sequence = akv.backup_since(since, Path::new(&output))?;
+            println!("{sequence}");
+            status!(
+                "backed up to sequence {sequence}; take the next increment with --since {sequence}"
+            );
+        }
+        Actions::Checkpoint => {
+            let checkpoint = akv.checkpoint()?;
+            status!(
+                "checkpoint at sequence {} (offset {})",
+                checkpoint.sequence,
+                checkpoint.position
+            );
+        }
+        Actions::Copy { destination } => akv.copy_to(Path::new(&destination))?,
+        Actions::Completions { .. } => unreachable!("completions are printed before opening"),
+        Actions::Create => status!("created {database}"),
         Actions::Delete { key } => akv.delete(key)?,
-        Actions::Get { key } => {
+        Actions::Diff { other, json } => {
+            let other_path = Path::new(&other);
+            if !other_path.exists() {
+                let error_message = format!("database: {other} does not exist");
+                let error = Error::new(ErrorKind::NotFound, error_message);
+                return Err(error);
+            }
+            let other_akv = ActionKV::open_with_options(other_path, options())?;
+            let diff = diff(akv, &other_akv)?;
+            if json {
+                print_diff_json(&diff);
+            } else {
+                print_diff(&diff, database, &other);
+            }
+        }
+        Actions::Events { .. } => unreachable!("events are printed before opening"),
+        Actions::Expire { key, seconds } => akv.expire(&key, Duration::from_secs(seconds))?,
+        Actions::ExpireAt { key, time } => akv.expire_at(&key, parse_time(&time)?)?,
+        Actions::Export { format, output } => {
+            let exported = export::export(akv, format, Path::new(&output))?;
+            status!("exported {exported} keys to {output}");
+        }
+        Actions::Gc {
+            dry_run,
+            delimiter,
+            progress,
+        } => {
+            if dry_run {
+                let report = akv.compaction_report(delimiter)?;
+                print_compaction_report(&report);
+            } else if progress {
+                akv.compact_with_progress(|status| {
+                    if status.records % 10_000 == 0 || status.records == status.total_records {
+                        status!(
+                            "compacted {}/{} records, {} bytes",
+                            status.records,
+                            status.total_records,
+                            status.bytes
+                        );
+                    }
+                    ControlFlow::Continue(())
+                })?;
+            } else {
+                akv.compact()?;
+            }
+        }
+        Actions::Get {
+            key,
+            as_of: None,
+            default: None,
+        } => {
             let value = akv.get(key)?;
             println!("{value}");
         }
-        Actions::Insert { key, value } => akv.insert(key, value)?,
-        Actions::Update { key, value } => akv.update(key, value)?,
+        Actions::Get {
+            key,
+            as_of: None,
+            default: Some(default),
+        } => {
+            let value = akv.get_or(key, default)?;
+            println!("{value}");
+        }
+        Actions::Get {
+            key,
+            as_of: Some(time),
+            ..
+        } => {
+            let value = akv.get_as_of(key, parse_time(&time)?)?;
+            println!("{value}");
+        }
+        Actions::Info => print_info(database, &akv.info()?),
+        Actions::Import {
+            format,
+            input,
+            key_column,
+            value_column,
+            value_template,
+        } => {
+            let columns = import::Columns {
+                key: key_column,
+                value: value_column,
+                template: value_template,
+            };
+            let imported = import::import(akv, format, Path::new(&input), &columns)?;
+            status!("imported {imported} keys from {input}");
+        }
+        Actions::Insert {
+            key,
+            value,
+            no_overwrite: false,
+        } => akv.insert(key, value)?,
+        Actions::Insert {
+            key,
+            value,
+            no_overwrite: true,
+        } => akv.insert_new(key, value)?,
+        Actions::InsertMany { file } => {
+            let pairs = import::read_pairs(Path::new(&file))?;
+            let mut seen = HashSet::new();
+            let mut overwritten = 0;
+            for (key, _) in &pairs {
+                if akv.contains_key(key) || !seen.insert(key.as_str()) {
+                    overwritten += 1;
+                }
+            }
+            let inserted = pairs.len() - overwritten;
+
+            akv.insert_many(pairs)?;
+            akv.flush()?;
+            status!("inserted {inserted} keys, overwrote {overwritten} keys");
+        }
+        Actions::Keys { prefix, sort } => {
+            for key in sorted_keys(akv, &prefix, sort) {
+                println!("{key}");
+            }
+        }
+        Actions::Merge { other, policy } => {
+            let merged = akv.merge_from(Path::new(&other), policy.into())?;
+            status!("merged {merged} keys from {other}");
+        }
+        Actions::Migrate { from, dir } => {
+            let summary = migrate::migrate(akv, from, Path::new(&dir))?;
+            status!(
+                "migrated {} keys from {dir}, skipped {} that are not valid UTF-8",
+                summary.imported,
+                summary.skipped
+            );
+        }
+        Actions::Persist { key } => {
+            if !akv.persist(&key)? {
+                status!("key: {key} has no expiry");
+            }
+        }
+        Actions::Release { key, token } => {
+            if !akv.release(&key, token)? {
+                let error_message = format!("lease: {key} is not held with token {token}");
+                let error = Error::new(ErrorKind::PermissionDenied, error_message);
+                return Err(error);
+            }
+        }
+        Actions::Restore { .. } => unreachable!("restore is handled before opening"),
+        Actions::RestoreTo { time, output } => {
+            let restored = akv.restore_to(parse_time(&time)?, Path::new(&output))?;
+            status!("restored {restored} keys as of {time} to {output}");
+        }
+        Actions::Sample { count } => {
+            for key in akv.sample(count) {
+                println!("{key}");
+            }
+        }
+        Actions::Scan {
+            prefix,
+            sort: Sort::Key,
+            limit: None,
+            ..
+        } => {
+            for entry in akv.scan(&prefix) {
+                let (key, value) = entry?;
+                println!("{key}\t{value}");
+            }
+        }
+        Actions::Scan {
+            prefix,
+            sort,
+            limit: None,
+            ..
+        } => {
+            for key in sorted_keys(akv, &prefix, sort) {
+                let value = akv.get(key.to_string())?;
+                println!("{key}\t{value}");
+            }
+        }
+        Actions::Scan {
+            prefix,
+            limit: Some(limit),
+            cursor,
+            ..
+        } => {
+            let (entries, next) = akv.scan_page(&prefix, cursor.as_ref(), limit)?;
+            for (key, value) in entries {
+                println!("{key}\t{value}");
+            }
+            if let Some(next) = next {
+                eprintln!("next cursor: {next}");
+            }
+        }
+        Actions::SetPassphrase { remove: true } => akv.set_passphrase(None)?,
+        Actions::SetPassphrase { remove: false } => {
+            let mut passphrase = String::new();
+            std::io::stdin().read_line(&mut passphrase)?;
+            let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+            if passphrase.is_empty() {
+                let error_message = "no passphrase given on standard input";
+                let error = Error::new(ErrorKind::InvalidInput, error_message);
+                return Err(error);
+            }
+            akv.set_passphrase(Some(passphrase))?;
+        }
+        Actions::Snapshot { output } => {
+            let summary = archive::snapshot(akv, path, Path::new(&output))?;
+            status!(
+                "wrote {} keys ({} bytes, {} blobs) to {output}",
+                summary.keys,
+                summary.data_bytes,
+                summary.blobs
+            );
+        }
+        Actions::Stats { top } => {
+            print_value_size_stats(&akv.value_size_stats(top));
+            print_index_memory(&akv.index_memory_estimate());
+        }
+        Actions::Strlen { key } => println!("{}", akv.value_len(&key)?),
+        Actions::Sweep => {
+            let removed = akv.remove_expired()?;
+            status!("removed {removed} expired keys");
+        }
+        Actions::Top { by, limit } => {
+            for (key, count) in akv.top(by.into(), limit)? {
+                println!("{count:>12}  {key}");
+            }
+        }
+        Actions::Touch { key, ttl } => akv.touch(&key, ttl.map(Duration::from_secs))?,
+        Actions::Ttl { key } => match akv.ttl(&key)? {
+            Some(ttl) => println!("{}", ttl.as_secs_f64().ceil()),
+            None => println!("none"),
+        },
+        Actions::TtlList { prefix } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            for (key, expires_at) in akv.expiring(&prefix) {
+                let remaining = Duration::from_millis(expires_at).saturating_sub(now);
+                let remaining = remaining.as_secs_f64().ceil();
+                println!("{remaining}\t{}\t{key}", format_millis(expires_at));
+            }
+        }
+        Actions::Update {
+            key,
+            value,
+            strict: false,
+        } => akv.update(key, value)?,
+        Actions::Update {
+            key,
+            value,
+            strict: true,
+        } => akv.update_strict(key, value)?,
+        Actions::Verify { json } => {
+            let report = akv.verify_all()?;
+            if json {
+                println!("{}", report.to_json());
+            } else {
+                for record in &report.corrupt {
+                    let key = record.key.as_deref().unwrap_or("");
+                    let repair = record.repair.name();
+                    println!("{}\t{key}\t{}\t{repair}", record.position, record.error);
+                }
+            }
+            status!(
+                "keys: {}, bytes: {}, records: {}, superseded: {}, tombstones: {}",
+                report.keys,
+                report.bytes,
+                report.records,
+                report.superseded,
+                report.tombstones
+            );
+            if !report.is_ok() {
+                let error_message = format!("{} corrupt records", report.corrupt.len());
+                let error = Error::new(ErrorKind::InvalidData, error_message);
+                return Err(error);
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Returns the options databases are opened with, taking the passphrase from
+/// the environment.
+fn options() -> Options {
+    Options {
+        passphrase: std::env::var(PASSPHRASE_VAR).ok(),
+        ..Options::default()
+    }
+}
+
+/// The error returned when the command needed for a format or store was not
+/// compiled in.
+#[cfg(not(all(feature = "leveldb", feature = "sled", feature = "sqlite")))]
+fn missing_feature(feature: &str) -> Error {
+    let error_message = format!("kvstore was built without the `{feature}` feature");
+    Error::new(ErrorKind::Unsupported, error_message)
+}
+
+/// Returns the keys starting with `prefix`, ordered by `sort`.  The index is
+/// already in key order, which the stable sorts keep for ties.
+fn sorted_keys<'a>(akv: &'a ActionKV, prefix: &str, sort: Sort) -> Vec<&'a str> {
+    let mut entries: Vec<(&str, KeyMeta)> = akv
+        .keys_with_meta()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .collect();
+
+    match sort {
+        Sort::Key => {}
+        Sort::ValueSize => entries.sort_by_key(|(_, meta)| Reverse(meta.value_size)),
+        Sort::Mtime => entries.sort_by_key(|(_, meta)| Reverse(meta.timestamp)),
+    }
+
+    entries.into_iter().map(|(key, _)| key).collect()
+}
+
+/// Prints the value size histogram, one line per size class, and the largest
+/// keys.
+fn print_value_size_stats(stats: &ValueSizeStats) {
+    println!("keys: {}, value bytes: {}", stats.keys, stats.total_bytes);
+    for (class, count) in &stats.histogram {
+        let upper = match class {
+            0 => 1,
+            class => class * 2,
+        };
+        println!("{class:>12} .. {upper:<12} {count}");
+    }
+
+    if !stats.largest.is_empty() {
+        println!("largest values:");
+    }
+    for (key, size) in &stats.largest {
+        println!("{size:>12}  {key}");
+    }
+}
+
+/// Prints the estimated memory taken up by the index and its companions.
+fn print_index_memory(memory: &IndexMemory) {
+    println!(
+        "index memory: {} bytes estimated, {} per key",
+        memory.total(),
+        memory.per_key()
+    );
+    println!(
+        "  index {}, expiries {}, blobs {}, lru {}, block cache {}",
+        memory.index, memory.expiries, memory.blobs, memory.lru, memory.block_cache
+    );
+}
+
+/// Prints how the database at `database` is stored, for `info`.
+fn print_info(database: &str, info: &DatabaseInfo) {
+    let checksum = match info.checksum {
+        Checksum::Crc32 => "CRC-32",
+        Checksum::Crc32c => "CRC-32C",
+    };
+    println!("checksum: {checksum}");
+    println!("compression: none");
+    let passphrase = match info.passphrase {
+        true => "passphrase required to open, values not encrypted",
+        false => "none",
+    };
+    println!("encryption: {passphrase}");
+    println!("keys: {}, last sequence {}", info.keys, info.last_sequence);
+    println!(
+        "log: {database}, {} bytes of records in a {} byte file",
+        info.log_bytes, info.file_bytes
+    );
+    println!(
+        "blobs: {database}.blobs, {} files, {} bytes",
+        info.blob_files, info.blob_bytes
+    );
+    match info.last_checkpoint {
+        Some(checkpoint) => println!(
+            "last checkpoint: sequence {} at offset {}, {}",
+            checkpoint.sequence,
+            checkpoint.position,
+            format_millis(checkpoint.timestamp)
+        ),
+        None => println!("last checkpoint: none"),
+    }
+    match info.index_file {
+        IndexFile::Missing => println!("saved index: none, opening scans the whole log"),
+        IndexFile::Current(checkpoint) => println!(
+            "saved index: {database}.index, current as of sequence {}",
+            checkpoint.sequence
+        ),
+        IndexFile::Stale => println!("saved index: {database}.index, stale and ignored"),
+    }
+    match info.lock {
+        LockState::Unshared => println!("lock: none, not shared between processes"),
+        LockState::Free => println!("lock: {database}.lock, free"),
+        LockState::Held => println!("lock: {database}.lock, held by another process"),
+    }
+}
+
+/// Prints what a compaction would reclaim, overall and per key prefix.
+fn print_compaction_report(report: &CompactionReport) {
+    println!(
+        "records: {} total, {} reclaimable",
+        report.total_records, report.reclaimable_records
+    );
+    println!(
+        "bytes: {} total, {} reclaimable",
+        report.total_bytes, report.reclaimable_bytes
+    );
+    println!(
+        "blob files: {} reclaimable ({} bytes)",
+        report.reclaimable_blob_files, report.reclaimable_blob_bytes
+    );
+    for (prefix, prefix_report) in &report.prefixes {
+        println!(
+            "prefix {prefix:?}: {} records, {} bytes reclaimable",
+            prefix_report.reclaimable_records, prefix_report.reclaimable_bytes
+        );
+    }
+}
+
+/// The keys that differ between two databases, each list sorted by key.
+struct Diff {
+    only_in_a: Vec<String>,
+    only_in_b: Vec<String>,
+    different: Vec<String>,
+}
+
+/// Compares every key in `a` and `b` and reports where the databases differ.
+fn diff(a: &ActionKV, b: &ActionKV) -> Result<Diff> {
+    let mut only_in_a = Vec::new();
+    let mut different = Vec::new();
+    for key in a.keys() {
+        if !b.contains_key(key) {
+            only_in_a.push(key.to_string());
+        } else if a.get(key.to_string())? != b.get(key.to_string())? {
+            different.push(key.to_string());
+        }
+    }
+
+    let mut only_in_b: Vec<String> = b
+        .keys()
+        .filter(|key| !a.contains_key(key))
+        .map(|key| key.to_string())
+        .collect();
+
+    only_in_a.sort();
+    only_in_b.sort();
+    different.sort();
+
+    Ok(Diff {
+        only_in_a,
+        only_in_b,
+        different,
+    })
+}
+
+/// Prints `diff` one key per line.
+fn print_diff(diff: &Diff, a: &str, b: &str) {
+    for key in &diff.only_in_a {
+        println!("only in {a}: {key}");
+    }
+    for key in &diff.only_in_b {
+        println!("only in {b}: {key}");
+    }
+    for key in &diff.different {
+        println!("differs: {key}");
+    }
+}
+
+/// Prints the changes to `akv` written after `since` as JSON objects, one per
+/// line.  With `follow`, keeps refreshing the database and printing the
+/// changes appended since, until interrupted.
+fn print_events(mut akv: ActionKV, since: Option<u64>, follow: bool) -> Result<()> {
+    let mut cursor = match (since, follow) {
+        (Some(since), _) => EventCursor::new(since),
+        (None, true) => akv.event_cursor(),
+        (None, false) => EventCursor::new(0),
+    };
+    loop {
+        let (events, next) = akv.events_after(cursor)?;
+        for event in events {
+            let event = serde_json::json!({
+                "op": event.op.name(),
+                "key": event.key,
+                "seq": event.sequence,
+                "timestamp": event.timestamp,
+            });
+            println!("{event}");
+        }
+        cursor = next;
+        if !follow {
+            return Ok(());
+        }
+        thread::sleep(FOLLOW_INTERVAL);
+        akv.refresh()?;
+    }
+}
+
+/// Prints `diff` as a single JSON object.
+fn print_diff_json(diff: &Diff) {
+    let diff = serde_json::json!({
+        "only_in_a": diff.only_in_a,
+        "only_in_b": diff.only_in_b,
+        "different": diff.different,
+    });
+    println!("{diff}");
+}
+
+/// Parses an RFC 3339 UTC time into milliseconds since the Unix epoch.
+fn parse_time(time: &str) -> Result<u64> {
+    let parsed = match humantime::parse_rfc3339_weak(time) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let error_message = format!("time: {time} is not an RFC 3339 UTC time: {e}");
+            let error = Error::new(ErrorKind::InvalidInput, error_message);
+            return Err(error);
+        }
+    };
+    let elapsed = parsed.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(elapsed.as_millis() as u64)
+}
+
+/// Formats `millis`, in milliseconds since the Unix epoch, as an RFC 3339
+/// UTC time.
+fn format_millis(millis: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_millis(millis);
+    humantime::format_rfc3339_seconds(time).to_string()
+}
@@ -0,0 +1,110 @@
+//! Importers for the `migrate` command, which copy every entry of a database
+//! written by another key/value store, or by the first version of this one.
+//! Each importer of another store is behind a cargo feature of the same name
+//! so the default build does not pull in the other stores.
+
+use clap::ValueEnum;
+use kvstore::ActionKV;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Stores the `migrate` command can import from.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Source {
+    /// A sled database directory (requires the `sled` feature)
+    Sled,
+    /// A LevelDB database directory (requires the `leveldb` feature)
+    Leveldb,
+    /// A database file written by kvstore 0.1, in the original record format
+    Kvstore,
+}
+
+/// Counts reported once a migration finishes.
+#[derive(Default)]
+pub struct Summary {
+    /// Entries written to the database.
+    pub imported: u64,
+    /// Entries skipped because their key or value is not valid UTF-8.
+    pub skipped: u64,
+}
+
+/// Copies every entry of the `source` database in `dir`, or the file `dir`
+/// for kvstore, into `akv` and flushes it.
+pub fn migrate(akv: &mut ActionKV, source: Source, dir: &Path) -> Result<Summary> {
+    if !dir.exists() {
+        let error_message = format!("database: {} does not exist", dir.display());
+        let error = Error::new(ErrorKind::NotFound, error_message);
+        return Err(error);
+    }
+
+    let mut summary = Summary::default();
+    let mut import = |key: Vec<u8>, value: Vec<u8>| -> Result<()> {
+        match (String::from_utf8(key), String::from_utf8(value)) {
+            (Ok(key), Ok(value)) => {
+                akv.insert(key, value)?;
+                summary.imported += 1;
+            }
+            _ => summary.skipped += 1,
+        }
+        Ok(())
+    };
+
+    match source {
+        Source::Sled => for_each_sled(dir, &mut import)?,
+        Source::Leveldb => for_each_leveldb(dir, &mut import)?,
+        Source::Kvstore => ActionKV::for_each_original(dir, &mut import)?,
+    }
+
+    akv.flush()?;
+    Ok(summary)
+}
+
+/// Calls `visit` with every key and value of the sled database in `dir`.
+#[cfg(feature = "sled")]
+fn for_each_sled<F>(dir: &Path, visit: &mut F) -> Result<()>
+where
+    F: FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    let db = sled::open(dir)?;
+    for entry in db.iter() {
+        let (key, value) = entry?;
+        visit(key.to_vec(), value.to_vec())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sled"))]
+fn for_each_sled<F>(_dir: &Path, _visit: &mut F) -> Result<()>
+where
+    F: FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    Err(crate::missing_feature("sled"))
+}
+
+/// Calls `visit` with every key and value of the LevelDB database in `dir`.
+#[cfg(feature = "leveldb")]
+fn for_each_leveldb<F>(dir: &Path, visit: &mut F) -> Result<()>
+where
+    F: FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    use rusty_leveldb::LdbIterator;
+
+    let options = rusty_leveldb::Options {
+        create_if_missing: false,
+        ..rusty_leveldb::Options::default()
+    };
+    let mut db = rusty_leveldb::DB::open(dir, options).map_err(Error::other)?;
+    let mut entries = db.new_iter().map_err(Error::other)?;
+    while let Some((key, value)) = entries.next() {
+        visit(key, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "leveldb"))]
+fn for_each_leveldb<F>(_dir: &Path, _visit: &mut F) -> Result<()>
+where
+    F: FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    Err(crate::missing_feature("leveldb"))
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::scratch_dir;
+use std::path::Path;
+use std::process::Command;
+
+/// Exit code of the command line tool for an invalid argument or input file.
+const EXIT_USAGE: i32 = 3;
+
+/// Runs the command line tool on the database at `database` with `args`,
+/// and returns its exit code.
+fn kvstore(database: &Path, args: &[&str]) -> i32 {
+    let output = Command::new(env!("CARGO_BIN_EXE_kvstore"))
+        .arg(database)
+        .args(args)
+        .output()
+        .unwrap();
+    output.status.code().unwrap()
+}
+
+#[test]
+fn a_malformed_input_file_is_a_usage_error() {
+    let dir = scratch_dir("cli-input");
+    let database = dir.join("kvstore.db");
+    assert_eq!(kvstore(&database, &["create"]), 0);
+
+    let operations = dir.join("bad.jsonl");
+    std::fs::write(&operations, "{\"op\":\"set\",\"key\":\"a\"}\n").unwrap();
+    let operations = operations.to_str().unwrap();
+    assert_eq!(kvstore(&database, &["apply", operations]), EXIT_USAGE);
+    assert_eq!(
+        kvstore(&database, &["apply", operations, "--atomic"]),
+        EXIT_USAGE
+    );
+
+    let pairs = dir.join("bad.txt");
+    std::fs::write(&pairs, "no separator\n").unwrap();
+    let pairs = pairs.to_str().unwrap();
+    assert_eq!(kvstore(&database, &["insert-many", pairs]), EXIT_USAGE);
+
+    let binary = dir.join("binary.txt");
+    std::fs::write(&binary, [b'k', b'=', 0xff]).unwrap();
+    let binary = binary.to_str().unwrap();
+    assert_eq!(kvstore(&database, &["insert-many", binary]), EXIT_USAGE);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use common::scratch_dir;
+use kvstore::{fail_compaction_at, ActionKV, CompactionStep, Options, SharedKV};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the temporary file compaction writes to.
+fn compaction_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".compact");
+    PathBuf::from(temp)
+}
+
+/// Returns the path of the directory holding the blob files of a database.
+fn blob_dir(path: &Path) -> PathBuf {
+    let mut dir = path.as_os_str().to_owned();
+    dir.push(".blobs");
+    PathBuf::from(dir)
+}
+
+/// Populates a database with overwritten keys so there is something to
+/// compact, spilling the larger values into blob files.
+fn populate(path: &Path, options: &Options) {
+    let mut akv = ActionKV::open_with_options(path, options.clone()).unwrap();
+    akv.insert("alpha".to_string(), "1".to_string()).unwrap();
+    akv.insert("beta".to_string(), "a blob value".to_string())
+        .unwrap();
+    akv.insert("alpha".to_string(), "2".to_string()).unwrap();
+    akv.insert("beta".to_string(), "another blob value".to_string())
+        .unwrap();
+    akv.insert("gamma".to_string(), "3".to_string()).unwrap();
+}
+
+/// Asserts that the database holds the latest value of every key.
+fn assert_contents(path: &Path, options: &Options) {
+    let akv = ActionKV::open_with_options(path, options.clone()).unwrap();
+    assert_eq!(akv.get("alpha".to_string()).unwrap(), "2");
+    assert_eq!(akv.get("beta".to_string()).unwrap(), "another blob value");
+    assert_eq!(akv.get("gamma".to_string()).unwrap(), "3");
+}
+
+fn blob_options() -> Options {
+    Options {
+        blob_threshold: Some(8),
+        ..Options::default()
+    }
+}
+
+#[test]
+fn compaction_keeps_latest_values_and_shrinks_file() {
+    let dir = scratch_dir("compact");
+    let path = dir.join("db.akv");
+    let options = blob_options();
+    populate(&path, &options);
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    let mut akv = ActionKV::open_with_options(&path, options.clone()).unwrap();
+    akv.compact().unwrap();
+    assert_eq!(akv.get("alpha".to_string()).unwrap(), "2");
+    akv.insert("delta".to_string(), "4".to_string()).unwrap();
+    drop(akv);
+
+    assert!(std::fs::metadata(&path).unwrap().len() < before);
+    assert!(!compaction_path(&path).exists());
+    assert_contents(&path, &options);
+
+    assert_eq!(std::fs::read_dir(blob_dir(&path)).unwrap().count(), 1);
+}
+
+/// Compacts the database at `path`, stopping at `step` as if the process
+/// died there.
+fn crash_compaction_at(path: &Path, options: &Options, step: CompactionStep) {
+    let mut akv = ActionKV::open_with_options(path, options.clone()).unwrap();
+    fail_compaction_at(Some(step));
+    let stopped = akv.compact();
+    fail_compaction_at(None);
+    assert!(stopped.is_err());
+}
+
+#[test]
+fn crash_while_writing_temp_file() {
+    let dir = scratch_dir("crash-writing");
+    let path = dir.join("db.akv");
+    let options = blob_options();
+    populate(&path, &options);
+
+    // Only some of the bytes of the unsynced temporary file made it.
+    crash_compaction_at(&path, &options, CompactionStep::Written);
+    let temp = std::fs::OpenOptions::new()
+        .write(true)
+        .open(compaction_path(&path))
+        .unwrap();
+    temp.set_len(6).unwrap();
+    drop(temp);
+
+    assert_contents(&path, &options);
+    assert!(!compaction_path(&path).exists());
+}
+
+#[test]
+fn crash_after_temp_file_synced_before_rename() {
+    let dir = scratch_dir("crash-before-rename");
+    let path = dir.join("db.akv");
+    let options = blob_options();
+    populate(&path, &options);
+
+    crash_compaction_at(&path, &options, CompactionStep::Synced);
+    assert!(compaction_path(&path).exists());
+
+    assert_contents(&path, &options);
+    assert!(!compaction_path(&path).exists());
+}
+
+#[test]
+fn crash_after_rename_before_blob_cleanup() {
+    let dir = scratch_dir("crash-after-rename");
+    let path = dir.join("db.akv");
+    let options = blob_options();
+    populate(&path, &options);
+    // Leave every blob file unreferenced.
+    let mut akv = ActionKV::open_with_options(&path, options.clone()).unwrap();
+    akv.insert("beta".to_string(), "b".to_string()).unwrap();
+    drop(akv);
+
+    crash_compaction_at(&path, &options, CompactionStep::Renamed);
+    assert!(!compaction_path(&path).exists());
+    assert_eq!(std::fs::read_dir(blob_dir(&path)).unwrap().count(), 2);
+
+    // The next compaction removes the blob files the crash left behind.
+    let mut akv = ActionKV::open_with_options(&path, options.clone()).unwrap();
+    assert_eq!(akv.get("alpha".to_string()).unwrap(), "2");
+    assert_eq!(akv.get("beta".to_string()).unwrap(), "b");
+    assert_eq!(akv.get("gamma".to_string()).unwrap(), "3");
+    akv.compact().unwrap();
+    drop(akv);
+    assert_eq!(std::fs::read_dir(blob_dir(&path)).unwrap().count(), 0);
+}
+
+#[test]
+fn open_leaves_the_temp_file_of_a_compaction_holding_the_lock() {
+    let dir = scratch_dir("leftover-locked");
+    let path = dir.join("db.akv");
+    let options = Options {
+        multi_process: true,
+        ..Options::default()
+    };
+    let mut shared = ActionKV::open_with_options(&path, options).unwrap();
+    shared
+        .locked(|_| {
+            std::fs::write(compaction_path(&path), b"in progress")?;
+            let _akv = ActionKV::open(&path)?;
+            assert!(compaction_path(&path).exists());
+            Ok(())
+        })
+        .unwrap();
+    drop(shared);
+
+    let _akv = ActionKV::open(&path).unwrap();
+    assert!(!compaction_path(&path).exists());
+}
+
+#[test]
+fn shared_compaction_keeps_writes_made_while_it_runs() {
+    let dir = scratch_dir("online");
+    let path = dir.join("db.akv");
+    let options = Options {
+        delta_threshold: Some(16),
+        ..blob_options()
+    };
+    populate(&path, &options);
+
+    let shared = SharedKV::open(&path, options.clone()).unwrap();
+    let long = "x".repeat(64);
+    shared.insert("epsilon".to_string(), long.clone()).unwrap();
+    let writer = shared.clone();
+    let mut written = false;
+    shared
+        .compact_with_progress(|_| {
+            // The database lock is not held while records are copied.
+            if !written {
+                writer.insert("alpha".to_string(), "5".to_string()).unwrap();
+                writer.insert("delta".to_string(), "4".to_string()).unwrap();
+                writer
+                    .insert("epsilon".to_string(), format!("{long}y"))
+                    .unwrap();
+                writer.delete("gamma".to_string()).unwrap();
+                written = true;
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+    assert!(written);
+    assert_eq!(shared.get("alpha".to_string()).unwrap(), "5");
+    drop(shared);
+
+    assert!(!compaction_path(&path).exists());
+    let akv = ActionKV::open_with_options(&path, options).unwrap();
+    assert_eq!(akv.get("alpha".to_string()).unwrap(), "5");
+    assert_eq!(akv.get("beta".to_string()).unwrap(), "another blob value");
+    assert_eq!(akv.get("delta".to_string()).unwrap(), "4");
+    assert_eq!(akv.get("epsilon".to_string()).unwrap(), format!("{long}y"));
+    assert_eq!(akv.get("gamma".to_string()).unwrap(), "");
+}
+
+#[test]
+fn sequence_numbers_keep_increasing_after_compaction_and_reopen() {
+    let dir = scratch_dir("sequence");
+    let path = dir.join("db.akv");
+    let mut akv = ActionKV::open(&path).unwrap();
+    akv.insert("alpha".to_string(), "1".to_string()).unwrap();
+    akv.insert("beta".to_string(), "2".to_string()).unwrap();
+    // The highest sequence numbers are held by records compaction discards.
+    akv.insert("alpha".to_string(), "3".to_string()).unwrap();
+    assert_eq!(akv.take("beta".to_string()).unwrap().as_deref(), Some("2"));
+    let last = akv.last_sequence();
+    akv.compact().unwrap();
+    drop(akv);
+
+    let mut akv = ActionKV::open(&path).unwrap();
+    assert_eq!(akv.last_sequence(), last);
+    akv.insert("gamma".to_string(), "4".to_string()).unwrap();
+    assert_eq!(akv.key_meta("gamma").unwrap().sequence, last + 1);
+    assert_eq!(akv.get("alpha".to_string()).unwrap(), "3");
+    assert!(akv.get("beta".to_string()).is_err());
+
+    // A second compaction and a copy carry the sequence number along too.
+    akv.compact().unwrap();
+    akv.copy_to(&dir.join("copy.akv")).unwrap();
+    drop(akv);
+    for path in [path, dir.join("copy.akv")] {
+        let akv = ActionKV::open(&path).unwrap();
+        assert_eq!(akv.last_sequence(), last + 1);
+        assert_eq!(akv.events_since(0).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,184 @@
+use kvstore::{ActionKV, Key, KeyDecoder, KeyEncoder};
+use std::io::ErrorKind;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Asserts that `keys`, built from values listed in ascending order, sort in
+/// that order too.
+fn assert_ascending(keys: &[String]) {
+    for pair in keys.windows(2) {
+        assert!(
+            pair[0] < pair[1],
+            "{:?} does not sort before {:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+}
+
+#[test]
+fn signed_integers_sort_and_decode_across_zero() {
+    let values = [
+        i64::MIN,
+        -1_000_000,
+        -256,
+        -1,
+        0,
+        1,
+        255,
+        1_000_000,
+        i64::MAX,
+    ];
+    let keys: Vec<String> = values
+        .iter()
+        .map(|&value| KeyEncoder::new().i64(value).finish())
+        .collect();
+    assert_ascending(&keys);
+
+    for (key, value) in keys.iter().zip(values) {
+        let mut decoder = KeyDecoder::new(key);
+        assert_eq!(decoder.i64().unwrap(), value);
+        assert!(decoder.is_empty());
+    }
+}
+
+#[test]
+fn timestamps_sort_and_decode_before_the_epoch() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let times = [
+        UNIX_EPOCH - 365 * day,
+        UNIX_EPOCH - Duration::from_millis(1),
+        UNIX_EPOCH,
+        UNIX_EPOCH + Duration::from_millis(1),
+        UNIX_EPOCH + 20_000 * day,
+    ];
+    let keys: Vec<String> = times
+        .iter()
+        .map(|&time| KeyEncoder::new().timestamp(time).finish())
+        .collect();
+    assert_ascending(&keys);
+
+    for (key, time) in keys.iter().zip(times) {
+        assert_eq!(KeyDecoder::new(key).timestamp().unwrap(), time);
+    }
+}
+
+#[test]
+fn strings_with_nul_sort_and_decode() {
+    let values = [
+        "", "\0", "\0\0", "a", "a\0", "a\0\u{1}", "a\0b", "a\u{1}", "ab",
+    ];
+    let keys: Vec<String> = values
+        .iter()
+        .map(|value| KeyEncoder::new().str(value).finish())
+        .collect();
+    assert_ascending(&keys);
+
+    for (key, value) in keys.iter().zip(values) {
+        let mut decoder = KeyDecoder::new(key);
+        assert_eq!(decoder.str().unwrap(), value);
+        assert!(decoder.is_empty());
+    }
+}
+
+#[test]
+fn a_string_sorts_before_the_strings_it_is_a_prefix_of() {
+    // Whatever follows the shorter string, it sorts first.
+    let keys = [
+        KeyEncoder::new().str("user").u64(u64::MAX).finish(),
+        KeyEncoder::new().str("user").str("\u{10FFFF}").finish(),
+        KeyEncoder::new().str("user\0").u64(0).finish(),
+        KeyEncoder::new().str("username").u64(0).finish(),
+    ];
+    assert_ascending(&keys);
+
+    // The parts of a key are not a prefix of a key with a longer part.
+    let user = KeyEncoder::new().str("user").finish();
+    assert!(keys[1].starts_with(&user));
+    assert!(!keys[2].starts_with(&user));
+    assert!(!keys[3].starts_with(&user));
+}
+
+#[test]
+fn composite_keys_decode_part_by_part() {
+    let time = UNIX_EPOCH - Duration::from_millis(1500);
+    let key = KeyEncoder::new()
+        .str("order\0line")
+        .u64(42)
+        .i64(-7)
+        .timestamp(time)
+        .str("")
+        .finish();
+
+    let mut decoder = KeyDecoder::new(&key);
+    assert_eq!(decoder.str().unwrap(), "order\0line");
+    assert_eq!(decoder.u64().unwrap(), 42);
+    assert_eq!(decoder.i64().unwrap(), -7);
+    assert_eq!(decoder.timestamp().unwrap(), time);
+    assert!(!decoder.is_empty());
+    assert_eq!(decoder.str().unwrap(), "");
+    assert!(decoder.is_empty());
+
+    let built = Key::new()
+        .part("order\0line")
+        .part(42u64)
+        .part(-7i64)
+        .part(time)
+        .part("");
+    assert_eq!(built.as_str(), key);
+}
+
+#[test]
+fn malformed_parts_are_invalid_data() {
+    let integer = KeyEncoder::new().u64(1).finish();
+    let malformed = [
+        // Too short, not hex, and upper case hex.
+        "00000001".to_string(),
+        "000000000000000g".to_string(),
+        "000000000000000A".to_string(),
+        // A string where an integer was expected.
+        KeyEncoder::new().str("1").finish(),
+    ];
+    for key in &malformed {
+        let error = KeyDecoder::new(key).u64().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{key:?}");
+    }
+
+    let strings = [
+        "unterminated",
+        "escape\0",
+        "bad escape\0\u{3}",
+        "escaped\0\u{2}but unterminated",
+        integer.as_str(),
+    ];
+    for key in strings {
+        let error = KeyDecoder::new(key).str().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{key:?}");
+    }
+
+    // A failed read leaves the decoder where it was, even past an escape.
+    let mut decoder = KeyDecoder::new(&integer);
+    assert!(decoder.str().is_err());
+    assert_eq!(decoder.u64().unwrap(), 1);
+    let escaped = format!("\0\u{2}{integer}");
+    let mut decoder = KeyDecoder::new(&escaped);
+    assert!(decoder.str().is_err());
+    assert!(decoder.u64().is_err());
+}
+
+#[test]
+fn scans_visit_numeric_keys_in_numeric_order() {
+    let mut akv = ActionKV::open_in_memory().unwrap();
+    for value in [10i64, -3, 0, 2, -40, 7] {
+        let key = Key::new().part("n").part(value);
+        akv.insert(key.into(), value.to_string()).unwrap();
+    }
+    akv.insert("m".to_string(), "before".to_string()).unwrap();
+    akv.insert(Key::new().part("nn").into(), "after".to_string())
+        .unwrap();
+
+    let values: Vec<String> = akv
+        .scan_partial(&Key::new().part("n"))
+        .map(|item| item.unwrap().1)
+        .collect();
+    assert_eq!(values, ["-40", "-3", "0", "2", "7", "10"]);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::scratch_dir;
+use kvstore::ActionKV;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Writes `entries` to a new log at `path` the way version 0.1 did: a CRC-32
+/// of the key and value, their lengths, and then the key and value.
+fn write_original(path: &Path, entries: &[(&str, &str)]) {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+    let mut log = Vec::new();
+    for (key, value) in entries {
+        let data = [key.as_bytes(), value.as_bytes()].concat();
+        log.extend_from_slice(&crc.checksum(&data).to_be_bytes());
+        log.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        log.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        log.extend_from_slice(&data);
+    }
+    std::fs::write(path, log).unwrap();
+}
+
+#[test]
+fn a_log_in_the_original_format_is_refused_and_left_alone() {
+    let dir = scratch_dir("original-open");
+    let path = dir.join("old.db");
+    write_original(&path, &[("hello", "world")]);
+    let log = std::fs::read(&path).unwrap();
+
+    let error = ActionKV::open(&path).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+    assert_eq!(std::fs::read(&path).unwrap(), log);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_log_in_the_original_format_can_be_imported() {
+    let dir = scratch_dir("original-import");
+    let path = dir.join("old.db");
+    // Version 0.1 deleted a key by writing an empty value for it.
+    let entries = [
+        ("hello", "world"),
+        ("counter", "1"),
+        ("counter", "2"),
+        ("gone", "soon"),
+        ("gone", ""),
+    ];
+    write_original(&path, &entries);
+
+    let mut imported = Vec::new();
+    ActionKV::for_each_original(&path, |key, value| {
+        let key = String::from_utf8(key).unwrap();
+        let value = String::from_utf8(value).unwrap();
+        imported.push((key, value));
+        Ok(())
+    })
+    .unwrap();
+    let expected = [("counter", "2"), ("gone", ""), ("hello", "world")];
+    let expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    assert_eq!(imported, expected);
+
+    // A log in the current format is not mistaken for one.
+    let mut akv = ActionKV::open(&dir.join("new.db")).unwrap();
+    for (key, value) in imported {
+        akv.insert(key, value).unwrap();
+    }
+    drop(akv);
+    let error = ActionKV::for_each_original(&dir.join("new.db"), |_, _| Ok(())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    let akv = ActionKV::open(&dir.join("new.db")).unwrap();
+    assert_eq!(akv.get("counter".to_string()).unwrap(), "2");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
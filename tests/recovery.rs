@@ -0,0 +1,85 @@
+mod common;
+
+use common::scratch_dir;
+use kvstore::ActionKV;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Writes five keys to a new database at `path`.
+fn populate(path: &Path) {
+    let mut akv = ActionKV::open(path).unwrap();
+    for (key, value) in [
+        ("alpha", "1"),
+        ("bravo", "2"),
+        ("charlie", "3"),
+        ("delta", "4"),
+        ("echo", "5"),
+    ] {
+        akv.insert(key.to_string(), value.to_string()).unwrap();
+    }
+}
+
+/// Returns the offset of the record holding `key`, found by its key bytes
+/// following the 29 bytes of the record header.
+fn record_offset(log: &[u8], key: &str) -> usize {
+    let key_start = log
+        .windows(key.len())
+        .position(|window| window == key.as_bytes())
+        .unwrap();
+    key_start - 29
+}
+
+#[test]
+fn a_damaged_length_before_the_tail_is_corruption() {
+    let dir = scratch_dir("recovery-length");
+    let path = dir.join("kvstore.db");
+    populate(&path);
+
+    // Claim a value running past the end of the file for the first record.
+    let mut log = std::fs::read(&path).unwrap();
+    let first = record_offset(&log, "alpha");
+    log[first + 8..first + 12].copy_from_slice(&0x202u32.to_be_bytes());
+    std::fs::write(&path, &log).unwrap();
+
+    let error = ActionKV::open(&path).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert_eq!(std::fs::read(&path).unwrap(), log);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_torn_tail_is_left_until_the_next_write() {
+    let dir = scratch_dir("recovery-torn");
+    let path = dir.join("kvstore.db");
+    populate(&path);
+
+    // Append the first half of a record longer than the one written next.
+    let other = dir.join("other.db");
+    let mut akv = ActionKV::open(&other).unwrap();
+    akv.insert("golf".to_string(), "7".repeat(100)).unwrap();
+    drop(akv);
+    let record = std::fs::read(&other).unwrap();
+    let start = record_offset(&record, "golf");
+    let torn = record[start..start + (record.len() - start) / 2].to_vec();
+    let mut log = std::fs::read(&path).unwrap();
+    log.extend_from_slice(&torn);
+    std::fs::write(&path, &log).unwrap();
+
+    let akv = ActionKV::open(&path).unwrap();
+    assert_eq!(akv.get("echo".to_string()).unwrap(), "5");
+    drop(akv);
+    assert_eq!(std::fs::read(&path).unwrap(), log);
+
+    let mut akv = ActionKV::open(&path).unwrap();
+    akv.insert("foxtrot".to_string(), "6".to_string()).unwrap();
+    drop(akv);
+    // Header, key, value and trailer of the new record replace the torn one.
+    let length = std::fs::metadata(&path).unwrap().len() as usize;
+    assert_eq!(length, log.len() - torn.len() + 29 + 7 + 1 + 8);
+    let akv = ActionKV::open(&path).unwrap();
+    assert_eq!(akv.get("foxtrot".to_string()).unwrap(), "6");
+    assert_eq!(akv.keys().count(), 6);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::scratch_dir;
+use kvstore::{ActionKV, Options, SharedKV, SyncPolicy};
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+/// Number of threads writing at once.
+const THREADS: usize = 8;
+
+/// Number of writes each thread makes.
+const WRITES: usize = 200;
+
+/// Writes from several threads at once under `sync`, then checks that every
+/// write acknowledged is there after the database is reopened, each with a
+/// sequence number of its own.
+fn acknowledged_writes_survive_reopen(name: &str, sync: SyncPolicy) {
+    let dir = scratch_dir(name);
+    let path = dir.join("kvstore.db");
+    let options = Options {
+        sync,
+        ..Options::default()
+    };
+
+    let shared = SharedKV::open(&path, options.clone()).unwrap();
+    let writers: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let mut acknowledged = Vec::with_capacity(WRITES);
+                for write in 0..WRITES {
+                    let key = format!("thread{thread}/key{write:03}");
+                    let value = format!("{thread}:{write}");
+                    shared.insert(key.clone(), value.clone()).unwrap();
+                    acknowledged.push((key, value));
+                }
+                // Overwrite a key another thread writes too, so writers
+                // contend for the same keys as well as the log.
+                shared
+                    .insert("shared".to_string(), thread.to_string())
+                    .unwrap();
+                acknowledged
+            })
+        })
+        .collect();
+    let acknowledged: Vec<(String, String)> = writers
+        .into_iter()
+        .flat_map(|writer| writer.join().unwrap())
+        .collect();
+    drop(shared);
+
+    let akv = ActionKV::open_with_options(&path, options).unwrap();
+    for (key, value) in &acknowledged {
+        assert_eq!(&akv.get(key.clone()).unwrap(), value, "{key}");
+    }
+    let last: usize = akv.get("shared".to_string()).unwrap().parse().unwrap();
+    assert!(last < THREADS);
+    assert_eq!(akv.keys().count(), THREADS * WRITES + 1);
+
+    let writes = THREADS * (WRITES + 1);
+    let events = akv.events_since(0).unwrap();
+    let sequences: HashSet<u64> = events.iter().map(|event| event.sequence).collect();
+    assert_eq!(events.len(), writes);
+    assert_eq!(sequences.len(), writes);
+    assert_eq!(akv.last_sequence(), writes as u64);
+
+    drop(akv);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concurrent_writes_survive_reopen_without_syncing() {
+    acknowledged_writes_survive_reopen("shared-never", SyncPolicy::Never);
+}
+
+#[test]
+fn concurrent_writes_survive_reopen_with_group_commit() {
+    acknowledged_writes_survive_reopen("shared-always", SyncPolicy::Always);
+}
+
+#[test]
+fn concurrent_writes_survive_reopen_when_written_behind() {
+    // Writes are flushed as the buffer fills, and the rest when the last
+    // handle is dropped, long before `max_delay`.
+    let sync = SyncPolicy::WriteBehind {
+        max_bytes: 4096,
+        max_delay: Duration::from_secs(3600),
+    };
+    acknowledged_writes_survive_reopen("shared-write-behind", sync);
+}